@@ -0,0 +1,345 @@
+//! Benchmark harness. Every file in a corpus is compressed at each level with each combination of the Options flags,
+//! the output is verified with inflate, and the ratio and throughput are reported as CSV or JSON ( one record per
+//! file and configuration ) so results can be compared between versions.
+//!
+//! Inflate is timed on each compressor's output. The flate2 output is also decoded with the streaming Inflater, with flate2
+//! and with the inflate implementation from before the decode loop was reworked ( the decoder column ), for comparison. 
+//! With no files, a generated corpus is used, so runs are repeatable.
+//!
+//! Example:
+//! flate3 --bench --json --repeat=5 corpus/ > results.json
+
+use std::time::Instant;
+use std::io::{Read,Write};
+use std::path::{Path,PathBuf};
+use crate::{compress,inflate,matcher};
+use crate::compress::{Level,Options};
+
+/// Benchmark settings.
+pub struct BenchConfig
+{
+  pub json: bool, // Report JSON rather than CSV.
+  pub repeat: usize, // Each measurement is the fastest of this many runs.
+  pub flate2: bool, // Include flate2 at levels 1, 6 and 9 for reference.
+}
+
+impl BenchConfig
+{
+  pub fn new() -> BenchConfig
+  {
+    BenchConfig{ json: false, repeat: 3, flate2: true }
+  }
+}
+
+/// Run the benchmarks on the files in paths ( directories are read recursively ), writing the results to out.
+/// If paths is empty, a synthetic corpus is used. Returns the number of configurations that failed to round trip.
+pub fn run<W: Write>( paths: &[String], c: &BenchConfig, out: &mut W ) -> std::io::Result<usize>
+{
+  let mut files = Vec::new();
+  for p in paths { corpus_files( Path::new( p ), &mut files )?; }
+
+  let mut report = Report{ out, json: c.json, records: 0 };
+  report.start()?;
+  let mut failures = 0;
+  if paths.is_empty()
+  {
+    failures += bench_file( "synthetic", &synthetic_corpus(), c, &mut report )?;
+    failures += bench_records( "synthetic", &synthetic_corpus(), c, &mut report )?;
+  }
+  for f in &files
+  {
+    let data = std::fs::read( f )?;
+    failures += bench_file( &f.to_string_lossy(), &data, c, &mut report )?;
+    failures += bench_records( &f.to_string_lossy(), &data, c, &mut report )?;
+  }
+  report.finish()?;
+  Ok( failures )
+}
+
+/// Add the files in path ( recursively, in name order ) to result.
+fn corpus_files( path: &Path, result: &mut Vec<PathBuf> ) -> std::io::Result<()>
+{
+  if path.is_dir()
+  {
+    let mut entries = std::fs::read_dir( path )?.map( |e| e.map( |e| e.path() ) ).collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for e in entries { corpus_files( &e, result )?; }
+  } else {
+    result.push( path.to_path_buf() );
+  }
+  Ok(())
+}
+
+/// Number of passes used when benchmarking optimal parsing.
+const OPTIMAL_PASSES : usize = 3;
+
+/// The Options to benchmark for each level : the store only level, then for other levels the level's options
+/// with every combination of dynamic_block_size, lazy_match and optimal parsing ( lazy matching does not apply
+/// to optimal parsing, so that is only combined with lazy_match = true ), and the level's options with each other
+/// match finder.
+fn settings() -> Vec<( Level, Options )>
+{
+  let mut result = Vec::new();
+  for level in 0..=9
+  {
+    let base = Level( level ).options();
+    if base.store_only { result.push( ( Level( level ), base ) ); continue; }
+    for &dynamic_block_size in &[ false, true ]
+    {
+      for &lazy_match in &[ false, true ]
+      {
+        for &optimal_passes in &[ 0, OPTIMAL_PASSES ]
+        {
+          if optimal_passes > 0 && !lazy_match { continue; }
+          let options = Options{ dynamic_block_size, lazy_match, optimal_passes, ..base.clone() };
+          result.push( ( Level( level ), options ) );
+        }
+      }
+    }
+    for &match_finder in &matcher::FINDERS[ 1.. ]
+    {
+      result.push( ( Level( level ), Options{ match_finder, ..base.clone() } ) );
+    }
+  }
+  result
+}
+
+/// Result of benchmarking one file with one configuration.
+struct Record<'a>
+{
+  file: &'a str,
+  size: usize,
+  engine: &'static str,
+  decoder: &'static str, // Decoder timed for inflate, see DECODERS.
+  level: u8,
+  options: Option<&'a Options>, // None for flate2.
+  compressed: usize,
+  compress_secs: f64,
+  inflate_secs: f64,
+  ok: bool, // Inflated output matched the input.
+}
+
+/// Benchmark one file with every configuration, returning the number of failures.
+fn bench_file<W: Write>( name: &str, data: &[u8], c: &BenchConfig, report: &mut Report<W> ) -> std::io::Result<usize>
+{
+  let mut failures = 0;
+  for ( level, options ) in settings()
+  {
+    let mut config = compress::Config::with_level( level );
+    config.options = options.clone();
+    let ( cb, compress_secs ) = fastest( c.repeat, || compress::compress( data, &mut config ) );
+    failures += measure( name, data, "flate3", level.0, Some( &options ), &cb, compress_secs, DECODERS[ 0 ], c, report )?;
+  }
+  if c.flate2
+  {
+    for level in [ 1, 6, 9 ]
+    {
+      let ( cb, compress_secs ) = fastest( c.repeat, ||
+      {
+        let mut e = flate2::write::ZlibEncoder::new( Vec::new(), flate2::Compression::new( level as u32 ) );
+        e.write_all( data ).unwrap();
+        e.finish().unwrap()
+      } );
+      for decoder in DECODERS
+      {
+        failures += measure( name, data, "flate2", level, None, &cb, compress_secs, decoder, c, report )?;
+      }
+    }
+  }
+  Ok( failures )
+}
+
+/// Sizes of the records for bench_records.
+const RECORD_SIZES : [usize; 2] = [ 100, 1000 ];
+
+/// Benchmark compressing data as many small records, each compressed separately, with compress::compress ( "flate3" ) and 
+/// with a reused CompressorContext ( "flate3-context" ). Sizes and times are totals for all the records.
+/// Returns the number of failures.
+fn bench_records<W: Write>( name: &str, data: &[u8], c: &BenchConfig, report: &mut Report<W> ) -> std::io::Result<usize>
+{
+  let mut failures = 0;
+  for size in RECORD_SIZES
+  {
+    let file = format!( "{} ( {} byte records )", name, size );
+    let records : Vec<&[u8]> = data.chunks( size ).collect();
+    for level in [ 1, 6, 9 ]
+    {
+      let mut config = compress::Config::with_level( Level( level ) );
+      let options = config.options.clone();
+      let ( cbs, compress_secs ) = fastest( c.repeat, || records.iter().map( |r| compress::compress( r, &mut config ) ).collect() );
+      failures += measure_records( &file, &records, "flate3", level, &options, cbs, compress_secs, c, report )?;
+
+      let mut ctx = compress::CompressorContext::new();
+      let ( cbs, compress_secs ) = fastest( c.repeat, || records.iter().map( |r| ctx.compress( r, &mut config ).to_vec() ).collect() );
+      failures += measure_records( &file, &records, "flate3-context", level, &options, cbs, compress_secs, c, report )?;
+    }
+  }
+  Ok( failures )
+}
+
+/// As measure, for records compressed separately ( cbs ). Returns 1 if any record failed to round trip.
+#[allow(clippy::too_many_arguments)]
+fn measure_records<W: Write>( file: &str, records: &[&[u8]], engine: &'static str, level: u8, options: &Options,
+  cbs: Vec<Vec<u8>>, compress_secs: f64, c: &BenchConfig, report: &mut Report<W> ) -> std::io::Result<usize>
+{
+  let ( ubs, inflate_secs ) = fastest( c.repeat, || cbs.iter().map( |cb| inflate::try_inflate( cb ) ).collect::<Vec<_>>() );
+  let ok = ubs.len() == records.len() && ubs.iter().zip( records ).all( |( ub, r )| matches!( ub, Ok( ub ) if ub == r ) );
+  let size = records.iter().map( |r| r.len() ).sum();
+  let compressed = cbs.iter().map( |cb| cb.len() ).sum();
+  report.record( &Record{ file, size, engine, decoder: DECODERS[ 0 ], level, options: Some( options ), compressed, compress_secs, 
+    inflate_secs, ok } )?;
+  Ok( if ok { 0 } else { 1 } )
+}
+
+/// Decoders : inflate::try_inflate, inflate::Inflater, the flate2 zlib decoder and the inflate implementation before the
+/// decode loop was reworked ( old_inflate ).
+const DECODERS : [&str; 4] = [ "flate3", "flate3-stream", "flate2", "flate3-old" ];
+
+/// Decode the zlib stream cb with decoder ( one of DECODERS ), None if it is not valid.
+fn decode( decoder: &str, cb: &[u8] ) -> Option<Vec<u8>>
+{
+  let mut ub = Vec::new();
+  match decoder
+  {
+    "flate3-stream" => inflate::Inflater::new( cb ).read_to_end( &mut ub ).ok()?,
+    "flate2" => flate2::read::ZlibDecoder::new( cb ).read_to_end( &mut ub ).ok()?,
+    "flate3-old" => return crate::old_inflate::inflate( cb ).ok(),
+    _ => return inflate::try_inflate( cb ).ok()
+  };
+  Some( ub )
+}
+
+/// Inflate cb with decoder ( timing it ) and check the result is data, then report the record. Returns 1 if the check failed.
+#[allow(clippy::too_many_arguments)]
+fn measure<W: Write>( file: &str, data: &[u8], engine: &'static str, level: u8, options: Option<&Options>,
+  cb: &[u8], compress_secs: f64, decoder: &'static str, c: &BenchConfig, report: &mut Report<W> ) -> std::io::Result<usize>
+{
+  let ( ub, inflate_secs ) = fastest( c.repeat, || decode( decoder, cb ) );
+  let ok = ub.as_deref() == Some( data );
+  report.record( &Record{ file, size: data.len(), engine, decoder, level, options, compressed: cb.len(), compress_secs, inflate_secs,
+    ok } )?;
+  Ok( if ok { 0 } else { 1 } )
+}
+
+/// Call f repeat times, returning the last result and the fastest time in seconds.
+fn fastest<T, F: FnMut() -> T>( repeat: usize, mut f: F ) -> ( T, f64 )
+{
+  let mut best = f64::MAX;
+  let mut result = None;
+  for _ in 0..std::cmp::max( repeat, 1 )
+  {
+    let start = Instant::now();
+    result = Some( f() );
+    best = best.min( start.elapsed().as_secs_f64() );
+  }
+  ( result.unwrap(), best )
+}
+
+/// Writes records as CSV ( with a header line ) or as a JSON array of objects.
+struct Report<'a, W: Write>
+{
+  out: &'a mut W,
+  json: bool,
+  records: usize, // Number of records written.
+}
+
+impl<'a, W: Write> Report<'a, W>
+{
+  fn start( &mut self ) -> std::io::Result<()>
+  {
+    if self.json
+    {
+      writeln!( self.out, "[" )
+    } else {
+      writeln!( self.out, "version,file,size,engine,decoder,level,dynamic_block_size,lazy_match,optimal_passes,probe_max,block_size,\
+        match_finder,compressed,ratio,compress_mb_s,inflate_mb_s,ok" )
+    }
+  }
+
+  fn record( &mut self, r: &Record ) -> std::io::Result<()>
+  {
+    let mb_s = | secs: f64 | if secs > 0.0 { r.size as f64 / secs / 1e6 } else { 0.0 };
+    let ratio = if r.size == 0 { 0.0 } else { r.compressed as f64 / r.size as f64 };
+    let version = env!( "CARGO_PKG_VERSION" );
+    if self.json
+    {
+      let options = match r.options
+      {
+        Some( o ) => format!( "\"dynamic_block_size\":{},\"lazy_match\":{},\"optimal_passes\":{},\"probe_max\":{},\"block_size\":{},\
+          \"match_finder\":\"{:?}\"", o.dynamic_block_size, o.lazy_match, o.optimal_passes, o.probe_max, o.block_size, o.match_finder ),
+        None => "\"dynamic_block_size\":null,\"lazy_match\":null,\"optimal_passes\":null,\"probe_max\":null,\"block_size\":null,\
+          \"match_finder\":null".to_string()
+      };
+      writeln!( self.out, "{}{{\"version\":\"{}\",\"file\":{},\"size\":{},\"engine\":\"{}\",\"decoder\":\"{}\",\"level\":{},{},\
+        \"compressed\":{},\"ratio\":{:.6},\"compress_mb_s\":{:.3},\"inflate_mb_s\":{:.3},\"ok\":{}}}",
+        if self.records > 0 { "," } else { "" }, version, json_string( r.file ), r.size, r.engine, r.decoder, r.level, options,
+        r.compressed, ratio, mb_s( r.compress_secs ), mb_s( r.inflate_secs ), r.ok )?;
+    } else {
+      let options = match r.options
+      {
+        Some( o ) => format!( "{},{},{},{},{},{:?}", o.dynamic_block_size, o.lazy_match, o.optimal_passes, o.probe_max, o.block_size,
+          o.match_finder ),
+        None => ",,,,,".to_string()
+      };
+      writeln!( self.out, "{},{},{},{},{},{},{},{},{:.6},{:.3},{:.3},{}", version, csv_string( r.file ), r.size, r.engine, r.decoder, r.level,
+        options, r.compressed, ratio, mb_s( r.compress_secs ), mb_s( r.inflate_secs ), r.ok )?;
+    }
+    self.records += 1;
+    self.out.flush()
+  }
+
+  fn finish( &mut self ) -> std::io::Result<()>
+  {
+    if self.json { writeln!( self.out, "]" )?; }
+    self.out.flush()
+  }
+}
+
+/// String as a JSON string literal.
+fn json_string( s: &str ) -> String
+{
+  let mut result = String::from( "\"" );
+  for c in s.chars()
+  {
+    match c
+    {
+      '"' => result.push_str( "\\\"" ),
+      '\\' => result.push_str( "\\\\" ),
+      c if ( c as u32 ) < 0x20 => result.push_str( &format!( "\\u{:04x}", c as u32 ) ),
+      c => result.push( c )
+    }
+  }
+  result.push( '"' );
+  result
+}
+
+/// String as a CSV field, quoted if necessary.
+fn csv_string( s: &str ) -> String
+{
+  if s.contains( [ ',', '"', '\n', '\r' ] ) { format!( "\"{}\"", s.replace( '"', "\"\"" ) ) } else { s.to_string() }
+}
+
+/// Corpus with sections of differing content ( text, numbers, skewed and random bytes ), used when no files are given.
+fn synthetic_corpus() -> Vec<u8>
+{
+  let mut result = Vec::new();
+  let mut x : u32 = 1;
+  let mut rand = || { x = x.wrapping_mul( 1103515245 ).wrapping_add( 12345 ); ( x >> 16 ) as usize };
+
+  // Text from a small vocabulary.
+  let words = [ "the", "block", "of", "input", "is", "split", "where", "symbol", "frequencies", "change", "and", "a", "new" ];
+  while result.len() < 60000
+  {
+    result.extend_from_slice( words[ rand() % words.len() ].as_bytes() );
+    result.push( if rand() % 12 == 0 { b'\n' } else { b' ' } );
+  }
+  // Table of little-endian numbers.
+  for i in 0..15000u32 { result.extend_from_slice( &( i * i ).to_le_bytes() ); }
+  // Bytes from a skewed distribution.
+  for _ in 0..40000 { let r = rand() % 64; result.push( ( r * r / 64 ) as u8 ); }
+  // Random bytes.
+  for _ in 0..20000 { result.push( rand() as u8 ); }
+  // More text.
+  for i in 0..2000 { result.extend_from_slice( format!( "line {} value {}\n", i, i * 7 % 1000 ).as_bytes() ); }
+  result
+}
@@ -1,17 +1,14 @@
-use crate::col::Heap;
-use crate::inflate::reverse;
+use crate::huffman;
 
-/// RFC 1951 length-limited Huffman coding.
+/// RFC 1951 length-limited Huffman coding ( see huffman.rs ).
 pub struct BitCoder
 {
   pub symbols: usize,  // Number of symbols to be encoded (input/output).
   pub used: Vec<u32>,  // Number of times each symbol is used in the block being encoded ( input ).
   pub bits: Vec<u8>,   // Number of bits used to encode each symbol ( output ).
-  pub code: Vec<u16>,  // Code for each symbol (output).
+  pub code: Vec<u32>,  // Code for each symbol (output).
 
   lim_bits: usize,  // Limit on code length ( 15 or 7 for RFC 1951 ).
-  max_bits: usize,  // Maximum code length.
-  left: Vec<u16>, right: Vec<u16>, // Tree storage.
 }
 
 impl BitCoder
@@ -22,229 +19,40 @@ impl BitCoder
     { 
       symbols,
       lim_bits, 
-      max_bits: 0,
       used:  vec![0;symbols],
       bits:  vec![0;symbols],
-      left:  vec![0;symbols],
-      right: vec![0;symbols],
       code:  Vec::with_capacity( symbols ),
     }
   }
 
-  pub fn compute_bits( &mut self ) // Compute bits from used.
+  /// Clear the counts and codes, so the coder can be reused for another block.
+  pub fn reset( &mut self )
   {
-    // First try to compute a Huffman code.
-    // Most of the time this succeeds, but sometime lim_bits is exceeeded in which case package_merge is used.
-
-    // Tree nodes are encoded in a u64 using 32 bits for used count, 8 bits for the tree depth, 16 bits for the id.
-    // Constants for accessing the bitfields.
-    const USEDBITS : u8 = 32;
-    const DEPTHBITS : u8 = 8;
-    const IDBITS : u8 = 16;
-
-    const USEDMASK : u64 = ( ( 1 << USEDBITS ) - 1 ) << ( IDBITS + DEPTHBITS );
-    const DEPTHMASK : u64 = ( ( 1 << DEPTHBITS ) - 1 ) << IDBITS;
-    const DEPTHONE : u64 = 1 << IDBITS;
-    const IDMASK : u64 = ( 1 << IDBITS ) - 1;
-
-    // First compute the number of bits to encode each symbol (self.bits), using a Heap.
-    let mut heap = Heap::<u64>::new( self.symbols as usize );
-
-    // Add the leaf nodes to the heap.
-    for id in 0..self.symbols
-    {
-      let used = self.used[ id ];
-      if used > 0 
-      { 
-        heap.add( ( used as u64 ) << ( IDBITS + DEPTHBITS ) | id as u64 );
-      }
-    }
-    heap.make();
-
-    // Construct the binary (non-leaf) nodes of the tree.
-    let non_zero : usize = heap.count();
-   
-    match non_zero
-    {
-      0 => {}
-      1 =>
-      { 
-        self.get_bits( ( heap.remove() & IDMASK ) as usize, 1 );
-        self.max_bits = 1;
-      } 
-      _ =>
-      {
-        let mut node = 0;
-
-        loop // Keep pairing the lowest frequency (least used) tree nodes.
-        {
-          let left = heap.remove(); 
-          self.left[ node ] = ( left & IDMASK ) as u16;
-
-          let right = heap.remove(); 
-          self.right[ node ] = ( right & IDMASK ) as u16;
-
-          // Extract depth of left and right nodes ( still shifted though ).
-          let depth_left = left & DEPTHMASK;
-          let depth_right = right & DEPTHMASK; 
-
-          // New node depth is 1 + larger of depth_left and depth_right.
-          let depth = DEPTHONE + std::cmp::max(depth_left,depth_right);
-
-          // Add the new tree node to the heap, as above, Used | Depth | Id
-          heap.insert( ( left + right ) & USEDMASK | depth | ( self.symbols + node ) as u64 );
-
-          node += 1;
+    self.symbols = self.used.len();
+    self.used.iter_mut().for_each( |x| *x = 0 );
+    self.bits.iter_mut().for_each( |x| *x = 0 );
+    self.code.clear();
+  }
 
-          if heap.count() < 2 { break }
-        }
-        
-        let root = ( heap.remove() & ( DEPTHMASK | IDMASK ) ) as usize;
-        self.max_bits = root >> IDBITS;
-        if self.max_bits <= self.lim_bits
-        {
-          self.get_bits( root & IDMASK as usize, 0 );
-        } else {
-          self.max_bits = self.lim_bits;
-          self.package_merge( non_zero );
-        }
-      }
-    }
+  pub fn compute_bits( &mut self ) // Compute bits from used.
+  {
+    // The number of symbols is small enough for any RFC 1951 limit, so this cannot fail.
+    let bits = huffman::code_lengths( &self.used[ ..self.symbols ], self.lim_bits ).unwrap();
+    self.bits[ ..self.symbols ].copy_from_slice( &bits );
 
     // Reduce symbol count if there are unused trailing symbols.
     while self.symbols > 0 && self.bits[ self.symbols - 1 ] == 0
     { 
       self.symbols -= 1; 
     }
-
-/*
-    println!( "computed bits" );
-    for i in 0..self.symbols
-    {
-      if self.bits[i] > 0
-      {
-        println!( "symbol={} used={} bits={}", i, self.used[i], self.bits[i] );
-      }
-    }
-*/
-
   }
 
-  fn get_bits( &mut self, mut tree_node: usize, mut depth:u8 )
+  /// Use the given code lengths rather than computing them from used ( e.g. RFC 1951 fixed codes ).
+  pub fn set_bits( &mut self, bits: &[u8] )
   {
-    // Walk the tree reading off the number of bits to encode each symbol ( which is depth of tree ).
-   
-    if tree_node < self.symbols // node is a leaf.
-    {
-      self.bits[ tree_node ] = depth;
-    } else {
-      tree_node -= self.symbols;
-      depth += 1;
-      self.get_bits( self.left[ tree_node ] as usize, depth );
-      self.get_bits( self.right[ tree_node ] as usize, depth );
-    }
-  }
-
-  fn package_merge( &mut self, non_zero : usize )
-  {
-    // Tree nodes are encoded in a ulong using 16 bits for the id, 32 bits for Used.
-    const IDBITS : i32 = 16;
-    const IDMASK : u64 = ( 1 << IDBITS ) - 1;
-    const USEDBITS : i32 = 32;
-    const USEDMASK : u64 = ( ( 1 << USEDBITS ) - 1 ) << IDBITS;
-
-    let tree_size = self.symbols * self.lim_bits;
-
-    // Tree storage.
-    self.left = vec![ 0; tree_size ];
-    self.right = vec![ 0; tree_size ];
-
-    // First create the leaf nodes for the tree and sort.
-    let mut leaves : Vec<u64> = Vec::with_capacity( non_zero );
-
-    for i in 0..self.symbols
-    {
-      let used = self.used[ i ];
-      if used != 0 
-      {
-        leaves.push( (used as u64) << IDBITS | i as u64 );
-      }
-    }
-    leaves.sort();
-
-    let mut merged = Vec::<u64>::with_capacity( self.symbols );
-    let mut next = Vec::<u64>::with_capacity( self.symbols );
-
-    let mut package : usize = self.symbols; // Allocator for package (tree node) ids.
-
-    for _i in 0..self.lim_bits
-    {
-      let mut lix = 0; // Index into leaves.
-      let mut mix = 0; // Index into merged.
-      let llen = leaves.len();
-      let mlen = merged.len();
-      let mut total = ( llen + mlen ) / 2;
-      while total > 0
-      {
-        // Compute left.
-        let mut left : u64;
-        if mix < mlen
-        {
-          left = merged[ mix ];
-          if lix < llen
-          {
-            let leaf = leaves[ lix ];
-            if left < leaf { mix += 1; }
-            else { left = leaf; lix += 1; }
-          }
-          else { mix += 1; }
-        }
-        else { left = leaves[ lix ]; lix += 1; }
-
-        // Compute right.
-        let mut right : u64;
-        if mix < mlen
-        {
-          right = merged[ mix ];
-          if lix < llen
-          {
-            let leaf = leaves[ lix ];
-            if right < leaf { mix += 1; }
-            else { right = leaf; lix += 1; }
-          }
-          else { mix += 1; }
-        }
-        else { right = leaves[ lix ]; lix += 1; }
-
-        // Package left and right.  
-        self.left[ package ] = ( left & IDMASK ) as u16;
-        self.right[ package ] = ( right & IDMASK ) as u16;
-        next.push( ( left + right ) & USEDMASK | package as u64 );        
-        package += 1;
-        total -= 1;
-      }
-
-      // Swap merged and next.
-      std::mem::swap( &mut merged, &mut next );
-      next.clear();
-    }
-
-    // Calculate the number of bits to encode each symbol.
-    for node in merged
-    {
-      self.merge_get_bits( ( node & IDMASK ) as usize );
-    }
-  }
-
-  fn merge_get_bits( &mut self, node : usize )
-  {
-    if node < self.symbols
-    {
-      self.bits[ node ] += 1;
-    } else {
-      self.merge_get_bits( self.left[ node ] as usize );
-      self.merge_get_bits( self.right[ node ] as usize );
-    }
+    self.symbols = bits.len();
+    self.bits[ ..bits.len() ].copy_from_slice( bits );
+    self.code.clear();
   }
 
   pub fn total( &mut self ) -> usize
@@ -259,45 +67,7 @@ impl BitCoder
 
   pub fn compute_codes( &mut self )
   {
-    // Code below is from RFC 1951 page 7.
-
-    // bl_count[N] is the number of symbols encoded with N bits.
-    let mut bl_count : Vec<u16> = vec![ 0; self.max_bits + 1 ];
-    for sym in 0..self.symbols
-    {
-      bl_count[ self.bits[ sym ] as usize ] += 1; 
-    }
-
-    // Find the numerical value of the smallest code for each code length.
-    let mut next_code : Vec<u16> = Vec::with_capacity( self.max_bits + 1 );
-    let mut code : u16 = 0; 
-    bl_count[ 0 ] = 0;
-    next_code.push( 0 );
-    for bc in bl_count
-    {
-      code = ( code + bc ) << 1;
-      next_code.push( code );
-    }
-
-    // Calculate the result.
-    for sym in 0..self.symbols
-    {
-      let length = self.bits[ sym ] as usize;      
-      self.code.push( reverse( next_code[ length ] as usize, length ) as u16 );
-      next_code[ length ] += 1;
-    }
-
-/*
-    println!( "computed codes" );
-    for i in 0..self.symbols
-    {
-      if self.bits[i] > 0
-      {
-        println!( "symbol={} used={} bits={} code={}", i, self.used[i], self.bits[i], self.code[i] );
-      }
-    }
-*/
-
+    self.code = huffman::canonical_codes( &self.bits[ ..self.symbols ] ).unwrap();
   }
 
 } // end impl BitCoder
@@ -324,6 +94,15 @@ impl LenCoder
     }
   }
 
+  pub fn reset( &mut self )
+  {
+    self.bc.reset();
+    self.last_pass = false;
+    self.previous_length = 0;
+    self.zero_run = 0;
+    self.repeat = 0;
+  }
+
   // Run length encoding of code lengths - RFC 1951, page 13.
 
   pub fn encode_lengths( &mut self, is_lit: bool, count: usize, lengths: &[u8], output: &mut BitStream )
@@ -437,7 +216,6 @@ impl BitStream
 
   /// Write first n bits of value to BitStream, least significant bit is written first.
   /// Unused bits of value must be zero, i.e. value must be in range 0 .. 2^n-1.
-
   pub fn write( &mut self, mut n: u8, mut value: u64 )
   {
     if n + self.bits_in_buffer >= 64
@@ -453,6 +231,24 @@ impl BitStream
     self.bits_in_buffer += n;
   }
 
+  /// Write bytes to BitStream, which must be at a byte boundary ( see pad ).
+  pub fn write_bytes( &mut self, data: &[u8] )
+  {
+    while self.bits_in_buffer > 0
+    {
+      self.bytes.push( ( self.buffer & 255 ) as u8 );
+      self.buffer >>= 8;
+      self.bits_in_buffer -= 8;
+    }
+    self.bytes.extend_from_slice( data );
+  }
+
+  /// Number of bits written so far.
+  pub fn bit_count( &self ) -> usize
+  {
+    self.bytes.len() * 8 + self.bits_in_buffer as usize
+  }
+
   /// Pad output with zero bits to n bit boundary where n is power of 2 in range 1,2,4..64, typically n=8.
   pub fn pad( &mut self, n: u8 )
   {
@@ -460,6 +256,17 @@ impl BitStream
     if w > 0 { self.write( n - w, 0 ); }
   }
   
+  /// Move complete bytes from the bit buffer to bytes, leaving less than 8 bits in the buffer.
+  pub fn flush_bytes( &mut self )
+  {
+    while self.bits_in_buffer >= 8
+    {
+      self.bytes.push( ( self.buffer & 255 ) as u8 );
+      self.buffer >>= 8;
+      self.bits_in_buffer -= 8;
+    }
+  }
+
   /// Flush bit buffer to bytes.
   pub fn flush( &mut self )
   {
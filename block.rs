@@ -9,6 +9,46 @@ use crate::inflate::MATCH_EXTRA;
 use crate::inflate::DIST_OFF;
 use crate::inflate::DIST_EXTRA;
 
+/// RFC 1951 block type.
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum BlockType { Stored, Fixed, Dynamic }
+
+/// Statistics for a block written by the compressor, see compress::CompressStats.
+#[derive(Clone,Debug)]
+pub struct BlockStats
+{
+  pub block_type: BlockType,
+  pub input_start: usize, // Offset of the first input byte in the block.
+  pub input_end: usize, // Offset of the input byte following the block.
+  pub bits: usize, // Size of the block in the output, including the header.
+  pub literals: usize, // Number of literal symbols ( 0 for stored blocks ).
+  pub matches: usize, // Number of match ( length, distance ) pairs ( 0 for stored blocks ).
+  pub match_bytes: usize, // Input bytes covered by matches.
+  pub lit_symbols: usize, // Dynamic block header values : number of literal/length codes ( HLIT + 257 ),
+  pub dist_symbols: usize, // number of distance codes ( HDIST + 1 ),
+  pub len_symbols: usize, // and number of code length codes ( HCLEN + 4 ), 0 for other block types.
+}
+
+impl BlockStats
+{
+  pub fn new( block_type: BlockType, input_start: usize, input_end: usize, bits: usize ) -> BlockStats
+  {
+    BlockStats
+    {
+      block_type,
+      input_start,
+      input_end,
+      bits,
+      literals: 0,
+      matches: 0,
+      match_bytes: 0,
+      lit_symbols: 0,
+      dist_symbols: 0,
+      len_symbols: 0,
+    }
+  }
+}
+
 /// Compression of RFC 1951 blocks.
 pub struct Block
 {
@@ -18,6 +58,7 @@ pub struct Block
   pub match_end: usize,
   lit: BitCoder, dist: BitCoder, len: LenCoder,
   len_symbols: usize,
+  extra_bits: usize, // Number of extra bits for match lengths and distances.
   bits_computed: bool,
 }
  
@@ -35,10 +76,26 @@ impl Block
       dist: BitCoder::new( 15, 32 ), 
       len:  LenCoder::new( 7, 19 ),
       len_symbols: 0,
+      extra_bits: 0,
       bits_computed: false,
     }
   }
 
+  /// Reuse the block ( and the vectors it has allocated ), as if it was made by new.
+  pub fn reset( &mut self, input_start: usize, input_count: usize, match_start: usize )
+  {
+    self.input_start = input_start;
+    self.input_end = input_start + input_count;
+    self.match_start = match_start;
+    self.match_end = 0;
+    self.lit.reset();
+    self.dist.reset();
+    self.len.reset();
+    self.len_symbols = 0;
+    self.extra_bits = 0;
+    self.bits_computed = false;
+  }
+
   pub fn init( &mut self, input: &[u8], mlist: &[Match] )
   {
     // Counts how many times each symbol is used, also determines exact end of block.
@@ -62,11 +119,12 @@ impl Block
 
       // Compute match and distance codes.
       position += mat.length as usize;
-      let mut mc = 0; while mat.length as u16 >= MATCH_OFF[ mc ] { mc += 1; } mc -= 1;
+      let mut mc = 0; while mat.length >= MATCH_OFF[ mc ] { mc += 1; } mc -= 1;
       let mut dc = 29; while mat.distance < DIST_OFF[ dc ] { dc -= 1; }
 
       self.lit.used[ 257 + mc ] += 1;
       self.dist.used[ dc ] += 1;
+      self.extra_bits += ( MATCH_EXTRA[ mc ] + DIST_EXTRA[ dc ] ) as usize;
 
       mi += 1;  
     }
@@ -88,9 +146,135 @@ impl Block
     17 + 3 * self.len_symbols + self.len.bc.total() + self.lit.total() + self.dist.total()
   }
 
-  pub fn write( &mut self, input: &[u8], mlist: &[Match], output: &mut BitStream, last: bool )
+  /// Size in bits of the block encoded with dynamic Huffman codes.
+  pub fn dynamic_size( &mut self, output: &mut BitStream ) -> usize
+  {
+    self.bit_size( output ) + self.extra_bits
+  }
+
+  /// Code lengths for the literal/length and distance symbols ( 0 if a symbol is not used ).
+  pub fn code_bits( &mut self, output: &mut BitStream ) -> ( &[u8], &[u8] )
+  {
+    self.compute_bits( output );
+    ( &self.lit.bits, &self.dist.bits )
+  }
+
+  /// Write the block using whichever block type ( dynamic, fixed or stored ) gives the smallest output.
+  pub fn write( &mut self, input: &[u8], mlist: &[Match], output: &mut BitStream, last: bool ) -> BlockType
+  {
+    let dyn_bits = self.dynamic_size( output );
+    let fixed_bits = self.fixed_size();
+    let stored_bits = self.stored_size( output );
+
+    if stored_bits <= fixed_bits && stored_bits < dyn_bits
+    {
+      self.write_stored( input, output, last );
+      BlockType::Stored
+    } 
+    else if fixed_bits <= dyn_bits 
+    {
+      self.write_fixed( input, mlist, output, last );
+      BlockType::Fixed
+    } 
+    else 
+    { 
+      self.write_dynamic( input, mlist, output, last );
+      BlockType::Dynamic
+    }
+  }
+
+  /// Append statistics for the block, after it has been written as block_type using bits bits of output.
+  /// A stored block longer than 0xffff bytes is written as several RFC 1951 blocks, which are listed separately.
+  pub fn stats( &self, mlist: &[Match], block_type: BlockType, bits: usize, stats: &mut Vec<BlockStats> )
+  {
+    if block_type == BlockType::Stored
+    {
+      let first = stats.len();
+      let mut start = self.input_start;
+      loop
+      {
+        let end = start + std::cmp::min( self.input_end - start, 0xffff );
+        stats.push( BlockStats::new( block_type, start, end, 40 + ( end - start ) * 8 ) );
+        start = end;
+        if start == self.input_end { break; }
+      }
+      // The first block has the padding to a byte boundary.
+      let others : usize = stats[ first + 1.. ].iter().map( |b| b.bits ).sum();
+      stats[ first ].bits = bits - others;
+      return;
+    }
+    let mut result = BlockStats::new( block_type, self.input_start, self.input_end, bits );
+    let matches = &mlist[ self.match_start..self.match_end ];
+    result.matches = matches.len();
+    result.match_bytes = matches.iter().map( |m| m.length as usize ).sum();
+    result.literals = self.input_end - self.input_start - result.match_bytes;
+    if block_type == BlockType::Dynamic
+    {
+      result.lit_symbols = self.lit.symbols;
+      result.dist_symbols = self.dist.symbols;
+      result.len_symbols = self.len_symbols;
+    }
+    stats.push( result );
+  }
+
+  /// Size in bits of the block encoded with fixed Huffman codes.
+  fn fixed_size( &self ) -> usize
+  {
+    let mut result = 3 + self.extra_bits;
+    for ( sym, used ) in self.lit.used.iter().enumerate()
+    {
+      result += *used as usize * FIXED_LIT_BITS[ sym ] as usize;
+    }
+    for used in &self.dist.used 
+    { 
+      result += *used as usize * 5; 
+    }
+    result
+  }
+
+  /// Size in bits of the block written as stored ( uncompressed ) blocks, which are limited to 65535 bytes.
+  fn stored_size( &self, output: &BitStream ) -> usize
+  {
+    let n = self.input_end - self.input_start;
+    let blocks = std::cmp::max( 1, n.div_ceil( 0xffff ) );
+    let pad = ( 8 - ( output.bit_count() + 3 ) % 8 ) % 8;
+    pad + 35 + ( blocks - 1 ) * 40 + n * 8
+  }
+
+  /// Write the block as stored ( uncompressed ) blocks.
+  pub fn write_stored( &mut self, input: &[u8], output: &mut BitStream, last: bool )
+  {
+    let mut start = self.input_start;
+    loop
+    {
+      let n = std::cmp::min( self.input_end - start, 0xffff );
+      let end = start + n;
+      output.write( 1, if last && end == self.input_end {1} else {0} );
+      output.write( 2, 0 ); // block type 0 = stored ( uncompressed ) block.
+      output.pad( 8 );
+      output.write( 16, n as u64 );
+      output.write( 16, ( !n & 0xffff ) as u64 );
+      output.write_bytes( &input[ start..end ] );
+      start = end;
+      if start == self.input_end { break; }
+    }
+  }
+
+  fn write_fixed( &mut self, input: &[u8], mlist: &[Match], output: &mut BitStream, last: bool )
+  {
+    self.lit.set_bits( &FIXED_LIT_BITS );
+    self.dist.set_bits( &[ 5; 32 ] );
+    self.lit.compute_codes();
+    self.dist.compute_codes();
+
+    output.write( 1, if last {1} else {0} );
+    output.write( 2, 1 ); // block type 1 = block encoded with fixed Huffman codes.
+    self.put_codes( input, mlist, output );
+    output.write( self.lit.bits[ 256 ], self.lit.code[ 256 ] as u64 ); // End of block code
+  }
+
+  fn write_dynamic( &mut self, input: &[u8], mlist: &[Match], output: &mut BitStream, last: bool )
   {
-    self.bit_size( output );
     self.lit.compute_codes();
     self.dist.compute_codes();
     self.len.bc.compute_codes();
@@ -177,3 +361,34 @@ impl Block
 
 } // end impl Block
 
+/// Write an empty stored block, leaving the output at a byte boundary ( a sync point ).
+/// Data following a sync point can be decoded without the bits that precede it being complete.
+pub fn write_sync( output: &mut BitStream )
+{
+  output.write( 1, 0 ); 
+  output.write( 2, 0 ); // block type 0 = stored ( uncompressed ) block.
+  output.pad( 8 );
+  output.write( 16, 0 );
+  output.write( 16, 0xffff );
+}
+
+/// Write an empty fixed code block ( 10 bits ), so the bits of the preceding block are complete once output is
+/// written up to a byte boundary ( zlib's partial flush ).
+pub fn write_empty_fixed( output: &mut BitStream )
+{
+  output.write( 1, 0 ); 
+  output.write( 2, 1 ); // block type 1 = block encoded with fixed Huffman codes.
+  output.write( 7, 0 ); // End of block code ( 256 ) is seven zero bits.
+}
+
+/// Code lengths for RFC 1951 fixed Huffman codes ( page 12 ).
+pub static FIXED_LIT_BITS : [u8; 288] = fixed_lit_bits();
+
+const fn fixed_lit_bits() -> [u8; 288]
+{
+  let mut result = [ 8; 288 ];
+  let mut i = 144;
+  while i < 256 { result[ i ] = 9; i += 1; }
+  while i < 280 { result[ i ] = 7; i += 1; }
+  result
+}
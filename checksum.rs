@@ -0,0 +1,174 @@
+//! Checksums used by the RFC 1950 ( Adler-32 ) and RFC 1952 ( CRC-32 ) containers.
+//! Each checksum can be updated incrementally, and the checksums of two adjacent pieces of input
+//! can be combined, so checksums may be computed for chunks in parallel.
+
+/// Adler-32 checksum per RFC 1950.
+pub fn adler32( input: &[u8] ) -> u32
+{
+  adler32_update( 1, input )
+}
+
+const ADLER_MOD : u32 = 65521; // Largest prime less than 65536.
+
+/// Largest n such that 255n(n+1)/2 + (n+1)(ADLER_MOD-1) fits in 32 bits, so the modulo can be deferred for n bytes.
+const ADLER_NMAX : usize = 5552;
+
+/// Update Adler-32 checksum with more input ( the checksum of no input is 1 ).
+pub fn adler32_update( check: u32, input: &[u8] ) -> u32
+{
+  let mut s1 = check & 0xffff;
+  let mut s2 = check >> 16;
+  for chunk in input.chunks( ADLER_NMAX )
+  {
+    // Blocks of 16 bytes are summed independently ( which the compiler can vectorise ),
+    // s2 gains 16 times s1 plus each byte weighted by the number of times it is added.
+    let mut blocks = chunk.chunks_exact( 16 );
+    for block in &mut blocks
+    {
+      let mut sum = 0;
+      let mut weighted = 0;
+      for ( i, b ) in block.iter().enumerate()
+      {
+        sum += *b as u32;
+        weighted += ( 16 - i as u32 ) * *b as u32;
+      }
+      s2 += 16 * s1 + weighted;
+      s1 += sum;
+    }
+    for b in blocks.remainder()
+    {
+      s1 += *b as u32;
+      s2 += s1;
+    }
+    s1 %= ADLER_MOD;
+    s2 %= ADLER_MOD;
+  }
+  s2 << 16 | s1
+}
+
+/// Adler-32 checksum of the concatenation of two inputs, given the checksum of each ( a and b ) and the length of the second.
+pub fn adler32_combine( a: u32, b: u32, len_b: usize ) -> u32
+{
+  let rem = ( len_b % ADLER_MOD as usize ) as u32;
+  let a1 = a & 0xffff;
+  let s1 = ( a1 + ( b & 0xffff ) + ADLER_MOD - 1 ) % ADLER_MOD;
+  let s2 = ( ( rem * a1 ) % ADLER_MOD + ( a >> 16 ) + ( b >> 16 ) + ADLER_MOD - rem ) % ADLER_MOD;
+  s2 << 16 | s1
+}
+
+/// CRC-32 checksum per RFC 1952.
+pub fn crc32( input: &[u8] ) -> u32
+{
+  crc32_update( 0, input )
+}
+
+/// Update CRC-32 checksum with more input ( the checksum of no input is 0 ).
+/// Input is processed 8 bytes at a time ( "slice-by-8" ) using 8 tables.
+pub fn crc32_update( crc: u32, input: &[u8] ) -> u32
+{
+  let t = &CRC_TABLE;
+  let mut c = !crc;
+  let mut blocks = input.chunks_exact( 8 );
+  for b in &mut blocks
+  {
+    let lo = c ^ u32::from_le_bytes( [ b[ 0 ], b[ 1 ], b[ 2 ], b[ 3 ] ] );
+    let hi = u32::from_le_bytes( [ b[ 4 ], b[ 5 ], b[ 6 ], b[ 7 ] ] );
+    c = t[ 7 ][ ( lo & 255 ) as usize ] ^ t[ 6 ][ ( ( lo >> 8 ) & 255 ) as usize ]
+      ^ t[ 5 ][ ( ( lo >> 16 ) & 255 ) as usize ] ^ t[ 4 ][ ( lo >> 24 ) as usize ]
+      ^ t[ 3 ][ ( hi & 255 ) as usize ] ^ t[ 2 ][ ( ( hi >> 8 ) & 255 ) as usize ]
+      ^ t[ 1 ][ ( ( hi >> 16 ) & 255 ) as usize ] ^ t[ 0 ][ ( hi >> 24 ) as usize ];
+  }
+  for b in blocks.remainder()
+  {
+    c = t[ 0 ][ ( ( c ^ *b as u32 ) & 255 ) as usize ] ^ ( c >> 8 );
+  }
+  !c
+}
+
+/// CRC-32 checksum of the concatenation of two inputs, given the checksum of each ( a and b ) and the length of the second.
+/// The first checksum is multiplied by x^( 8 * len_b ) modulo the CRC polynomial.
+pub fn crc32_combine( a: u32, b: u32, len_b: usize ) -> u32
+{
+  let mut p = 1 << 31; // x^0
+  let mut n = len_b;
+  let mut k = 3; // x^( 2^3 ) is the effect of one byte.
+  while n != 0
+  {
+    if n & 1 != 0 { p = mult_mod_p( X2N_TABLE[ k & 31 ], p ); }
+    n >>= 1;
+    k += 1;
+  }
+  mult_mod_p( p, a ) ^ b
+}
+
+const CRC_POLY : u32 = 0xedb88320; // Polynomial with bit order reversed ( x^0 is the most significant bit ).
+
+/// Multiply a by b modulo the CRC polynomial.
+const fn mult_mod_p( mut a: u32, mut b: u32 ) -> u32
+{
+  let m = 1 << 31;
+  let mut p = 0;
+  loop
+  {
+    if a & m != 0
+    {
+      p ^= b;
+      if a & ( m - 1 ) == 0 { break; }
+    }
+    a <<= 1;
+    b = if b & 1 != 0 { ( b >> 1 ) ^ CRC_POLY } else { b >> 1 };
+  }
+  p
+}
+
+/// x^( 2^k ) modulo the CRC polynomial, for k = 0..31.
+static X2N_TABLE : [u32; 32] = x2n_table();
+
+const fn x2n_table() -> [u32; 32]
+{
+  let mut table = [ 0; 32 ];
+  let mut p = 1 << 30; // x^1
+  let mut k = 0;
+  while k < 32
+  {
+    table[ k ] = p;
+    p = mult_mod_p( p, p );
+    k += 1;
+  }
+  table
+}
+
+/// Tables for slice-by-8 CRC computation, CRC_TABLE[ 0 ] is the CRC of each byte value,
+/// CRC_TABLE[ k ] is the CRC of each byte value followed by k zero bytes.
+static CRC_TABLE : [[u32; 256]; 8] = crc_table();
+
+const fn crc_table() -> [[u32; 256]; 8]
+{
+  let mut table = [ [ 0; 256 ]; 8 ];
+  let mut n = 0;
+  while n < 256
+  {
+    let mut c = n as u32;
+    let mut k = 0;
+    while k < 8
+    {
+      c = if c & 1 != 0 { CRC_POLY ^ ( c >> 1 ) } else { c >> 1 };
+      k += 1;
+    }
+    table[ 0 ][ n ] = c;
+    n += 1;
+  }
+  let mut n = 0;
+  while n < 256
+  {
+    let mut k = 1;
+    while k < 8
+    {
+      let c = table[ k - 1 ][ n ];
+      table[ k ][ n ] = ( c >> 8 ) ^ table[ 0 ][ ( c & 255 ) as usize ];
+      k += 1;
+    }
+    n += 1;
+  }
+  table
+}
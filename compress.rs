@@ -1,39 +1,196 @@
 use crossbeam::{channel,Receiver};
+use std::io::Write;
 
 use crate::matcher;
-use crate::matcher::Match;
+use crate::optimal;
+use crate::split;
+use crate::matcher::{Match,MatchFinder,Finder};
 use crate::bit::BitStream;
-use crate::block::Block;
+use crate::block::{Block,BlockType,BlockStats,write_sync,write_empty_fixed};
+use crate::container::{Container,GzipHeader};
 
+#[derive(Clone)]
 pub struct Options
 {
   pub dynamic_block_size: bool,
   pub block_size: usize,
   pub probe_max: usize, 
-  pub lazy_match: bool
+  pub lazy_match: bool,
+  pub store_only: bool, // No compression, output is stored blocks.
+  pub optimal_passes: usize, // If non-zero, use optimal parsing ( slower, smaller output ) with this many passes.
+  pub match_finder: Finder, // How matches are found, probe_max limits the positions searched.
+}
+
+/// Compression level, from 0 ( store only ) to 9 ( best compression ), similar to zlib.
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct Level( pub u8 );
+
+impl Level
+{
+  pub const NONE : Level = Level( 0 );
+  pub const FASTEST : Level = Level( 1 );
+  pub const DEFAULT : Level = Level( 6 );
+  pub const BEST : Level = Level( 9 );
+
+  /// Options for the level.
+  pub fn options( self ) -> Options
+  {
+    // Higher levels search longer hash chains, lower levels use greedy matching ( no lazy matching ).
+    let ( probe_max, lazy_match, dynamic_block_size ) = match self.0
+    {
+      0 | 1 => ( 1, false, false ),
+      2 => ( 2, false, false ),
+      3 => ( 4, false, false ),
+      4 => ( 4, true, false ),
+      5 => ( 6, true, false ),
+      6 => ( 10, true, false ),
+      7 => ( 32, true, true ),
+      8 => ( 128, true, true ),
+      _ => ( 1024, true, true )
+    };
+    Options
+    {
+      dynamic_block_size,
+      block_size: 0x2000,
+      probe_max,
+      lazy_match,
+      store_only: self.0 == 0,
+      optimal_passes: 0,
+      match_finder: Finder::HashChain
+    }
+  }
+
+  /// The RFC 1950 FLEVEL value for the level : 0 = fastest, 1 = fast, 2 = default, 3 = maximum.
+  pub fn flevel( self ) -> u8
+  {
+    match self.0
+    {
+      0 | 1 => 0,
+      2..=5 => 1,
+      6 => 2,
+      _ => 3
+    }
+  }
 }
 
 pub struct Config
 {
   pub options: Options,
-  pub pool: scoped_threadpool::Pool
+  pub level: Level, // Level used to create options ( recorded in the zlib header ).
+  pub container: Container,
+  pub gzip: GzipHeader, // Header fields used when container is Gzip.
+  pub dictionary: Vec<u8>, // Preset dictionary ( not used for Gzip, which cannot signal that a dictionary is needed ).
+  pub threads: usize, // If more than 1, input is split into chunks which are compressed in parallel by this many threads.
+  pub pool: scoped_threadpool::Pool,
+  pub stats: Option<CompressStats>, // If Some, compress replaces it with statistics for the output.
 }
 
 impl Config
 {
   pub fn new() -> Config
   {
+    Config::with_level( Level::DEFAULT )
+  }
+
+  pub fn with_level( level: Level ) -> Config
+  {
+    let mut gzip = GzipHeader::new();
+    gzip.xfl = match level.0 { 1 => 4, 9 => 2, _ => 0 };
     Config
     { 
-      options: Options
+      options: level.options(),
+      level,
+      container: Container::Zlib,
+      gzip,
+      dictionary: Vec::new(),
+      threads: 1,
+      pool: scoped_threadpool::Pool::new(2),
+      stats: None,
+    }
+  }
+
+  /// The part of the preset dictionary that can be referenced ( the last 32K ).
+  pub fn dictionary( &self ) -> &[u8]
+  {
+    if self.container == Container::Gzip { return &[]; }
+    let n = self.dictionary.len();
+    &self.dictionary[ n - std::cmp::min( n, matcher::WINDOW ).. ]
+  }
+}
+
+/// Statistics for the blocks of compressed output, see Config::stats.
+#[derive(Clone,Debug)]
+pub struct CompressStats
+{
+  pub blocks: Vec<BlockStats>, // In output order, input offsets do not include any preset dictionary.
+}
+
+impl CompressStats
+{
+  pub fn new() -> CompressStats
+  {
+    CompressStats{ blocks: Vec::new() }
+  }
+
+  /// Number of blocks of the specified type.
+  pub fn count( &self, block_type: BlockType ) -> usize
+  {
+    self.blocks.iter().filter( |b| b.block_type == block_type ).count()
+  }
+
+  /// Total number of literal symbols.
+  pub fn literals( &self ) -> usize
+  {
+    self.blocks.iter().map( |b| b.literals ).sum()
+  }
+
+  /// Total number of matches.
+  pub fn matches( &self ) -> usize
+  {
+    self.blocks.iter().map( |b| b.matches ).sum()
+  }
+
+  /// Average match length ( 0 if there are no matches ).
+  pub fn average_match( &self ) -> f64
+  {
+    let bytes : usize = self.blocks.iter().map( |b| b.match_bytes ).sum();
+    if bytes == 0 { 0.0 } else { bytes as f64 / self.matches() as f64 }
+  }
+
+  /// Add the statistics for the blocks of other, with input offsets increased by offset.
+  fn append( &mut self, other: CompressStats, offset: usize )
+  {
+    for mut b in other.blocks
+    {
+      b.input_start += offset;
+      b.input_end += offset;
+      self.blocks.push( b );
+    }
+  }
+}
+
+impl std::fmt::Display for CompressStats
+{
+  /// One line per block, then totals.
+  fn fmt( &self, f: &mut std::fmt::Formatter ) -> std::fmt::Result
+  {
+    writeln!( f, "{:>6} {:<8} {:>10} {:>10} {:>10} {:>9} {:>9} {:>6} {:>6}", 
+      "block", "type", "start", "end", "bits", "literals", "matches", "avg", "codes" )?;
+    for ( i, b ) in self.blocks.iter().enumerate()
+    {
+      let avg = if b.matches == 0 { 0.0 } else { b.match_bytes as f64 / b.matches as f64 };
+      let codes = if b.block_type == BlockType::Dynamic 
       { 
-        dynamic_block_size: false, 
-        block_size: 0x2000, 
-        probe_max: 10, 
-        lazy_match: true 
-      },
-      pool: scoped_threadpool::Pool::new(2)
+        format!( "{},{},{}", b.lit_symbols, b.dist_symbols, b.len_symbols ) 
+      } else { 
+        "-".to_string() 
+      };
+      writeln!( f, "{:>6} {:<8} {:>10} {:>10} {:>10} {:>9} {:>9} {:>6.1} {:>6}", 
+        i, format!( "{:?}", b.block_type ).to_lowercase(), b.input_start, b.input_end, b.bits, b.literals, b.matches, avg, codes )?;
     }
+    write!( f, "{} blocks ( {} stored, {} fixed, {} dynamic ), {} literals, {} matches, average match length {:.2}",
+      self.blocks.len(), self.count( BlockType::Stored ), self.count( BlockType::Fixed ), self.count( BlockType::Dynamic ),
+      self.literals(), self.matches(), self.average_match() )
   }
 }
 
@@ -42,73 +199,233 @@ impl Config
 /// let data = [ 1,2,3,4,1,2,3 ];
 /// let cb : Vec<u8> = compress::compress( &data, &mut c );
 /// println!( "compressed size={}", cb.len() );
-
 pub fn compress( inp: &[u8], c: &mut Config ) -> Vec<u8>
 {
+  if c.threads > 1 && inp.len() > PARALLEL_CHUNK { return compress_parallel( inp, c ); }
+
   let mut out = BitStream::new( inp.len() );
   let ( mtx, mrx ) = channel::bounded(1000); // channel for matches
   let ( ctx, crx ) = channel::bounded(1); // channel for checksum
 
+  let container = c.container;
+  container.write_header( c, &mut out );
+
+  // If there is a dictionary, it is placed before the input, so matches can refer to it.
+  let dict = c.dictionary();
+  let start = dict.len();
+  let buf = if start == 0 { std::borrow::Cow::Borrowed( inp ) } else { std::borrow::Cow::Owned( [ dict, inp ].concat() ) };
+  let buf = &buf[..];
   let opts = &c.options;
+  if c.stats.is_some() { c.stats = Some( CompressStats::new() ); }
+  let stats = &mut c.stats;
 
   // Execute the match finding, checksum computation and block output in parallel using the scoped thread pool.
   c.pool.scoped( |s| 
   {
-    s.execute( || { matcher::find( inp, mtx , &opts ); } );
-    s.execute( || { ctx.send( adler32( &inp ) ).unwrap(); } );
-    write_blocks( inp, mrx, crx, &mut out, &opts );
+    if opts.store_only { /* No matches. */ }
+    else if opts.optimal_passes > 0 { s.execute( move || { optimal::find( buf, start, &mut |m| mtx.send( m ).unwrap(), opts ); } ); }
+    else { s.execute( move || { matcher::find( buf, start, &mut |m| mtx.send( m ).unwrap(), opts ); } ); }
+    s.execute( || { ctx.send( container.check_update( container.check_init(), inp ) ).unwrap(); } );
+    write_blocks( buf, start, mrx, &mut out, opts, stats );
   } );
 
+  container.write_trailer( crx.recv().unwrap(), inp.len(), &mut out );
+  out.flush();
   out.bytes
 }
 
-pub fn write_blocks( inp: &[u8], mrx: Receiver<Match>, crx: Receiver<u32>, out: &mut BitStream, opt: &Options )
+/// Amount of input in each chunk when compressing in parallel.
+const PARALLEL_CHUNK : usize = 0x20000;
+
+/// Compress in parallel ( similar to pigz ). The input is split into chunks which are compressed independently, each using 
+/// the preceding 32K of input as history. The compressed chunks are ended with a sync point ( so are byte aligned ) and concatenated.
+/// The checksum of each chunk is also computed in parallel, and the checksums combined.
+fn compress_parallel( inp: &[u8], c: &mut Config ) -> Vec<u8>
 {
-  out.write( 16, 0x9c78 );
+  if c.pool.thread_count() < c.threads as u32 { c.pool = scoped_threadpool::Pool::new( c.threads as u32 ); }
 
-  let len = inp.len();
-  let mut block_start = 0; // start of next block
+  let mut out = BitStream::new( inp.len() );
+  let container = c.container;
+  container.write_header( c, &mut out );
+
+  let dict = c.dictionary();
+  let start = dict.len();
+  let buf = if start == 0 { std::borrow::Cow::Borrowed( inp ) } else { std::borrow::Cow::Owned( [ dict, inp ].concat() ) };
+  let buf = &buf[..];
+  let opts = &c.options;
+
+  let chunks = inp.len().div_ceil( PARALLEL_CHUNK );
+  let ( tx, rx ) = channel::unbounded(); // channel for compressed chunks
+  let want_stats = c.stats.is_some();
+
+  c.pool.scoped( |s|
+  {
+    for i in 0..chunks
+    {
+      let tx = tx.clone();
+      s.execute( move ||
+      {
+        let chunk_start = start + i * PARALLEL_CHUNK;
+        let chunk_end = std::cmp::min( chunk_start + PARALLEL_CHUNK, buf.len() );
+        let history = std::cmp::min( chunk_start, matcher::WINDOW );
+        let chunk = &buf[ chunk_start - history..chunk_end ];
+        let last = i + 1 == chunks;
+
+        let mut mlist = Vec::new();
+        if opts.store_only { /* No matches. */ }
+        else if opts.optimal_passes > 0 { optimal::find( chunk, history, &mut |m| mlist.push( m ), opts ); }
+        else { matcher::find( chunk, history, &mut |m| mlist.push( m ), opts ); }
+
+        let check = container.check_update( container.check_init(), &buf[ chunk_start..chunk_end ] );
+
+        let mut bits = BitStream::new( chunk.len() );
+        let mut stats = if want_stats { Some( CompressStats::new() ) } else { None };
+        write_range( chunk, history, chunk.len(), last, &mut mlist.into_iter(), &mut bits, opts, &mut stats, &mut Vec::new() );
+        if !last 
+        { 
+          let before = bits.bit_count();
+          write_sync( &mut bits ); 
+          let n = chunk_end - chunk_start;
+          if let Some( s ) = &mut stats { s.blocks.push( BlockStats::new( BlockType::Stored, n, n, bits.bit_count() - before ) ); }
+        }
+        bits.flush();
+        tx.send( ( i, bits.bytes, check, chunk_end - chunk_start, stats ) ).unwrap();
+      } );
+    }
+  } );
+  drop( tx );
+
+  // Stitch the chunks together in order after the header ( which is a whole number of bytes ), combining the checksums.
+  let mut parts : Vec<_> = rx.iter().collect();
+  parts.sort_by_key( |p| p.0 );
+  let mut check = container.check_init();
+  if want_stats { c.stats = Some( CompressStats::new() ); }
+  for ( i, bytes, chunk_check, len, stats ) in parts 
+  { 
+    out.write_bytes( &bytes ); 
+    check = container.check_combine( check, chunk_check, len );
+    if let ( Some( all ), Some( s ) ) = ( &mut c.stats, stats ) { all.append( s, i * PARALLEL_CHUNK ); }
+  }
+
+  container.write_trailer( check, inp.len(), &mut out );
+  out.flush();
+  out.bytes
+}
+
+/// Buffers retained between calls to compress, for compressing many small inputs without allocating for each one.
+/// The output is the same as compress::compress, but all the work is done on the calling thread.
+///
+/// Example:
+/// let mut ctx = compress::CompressorContext::new();
+/// for r in &records { let cb : &[u8] = ctx.compress( r, &mut c ); }
+pub struct CompressorContext
+{
+  finder: Option<( Finder, Box<dyn MatchFinder> )>, // Match finder from the previous call, and its kind.
+  buf: Vec<u8>, // Dictionary followed by input.
+  mlist: Vec<Match>,
+  bytes: Vec<u8>, // Compressed output.
+  spare: Vec<Block>,
+}
+
+impl CompressorContext
+{
+  pub fn new() -> CompressorContext
+  {
+    CompressorContext{ finder: None, buf: Vec::new(), mlist: Vec::new(), bytes: Vec::new(), spare: Vec::new() }
+  }
+
+  /// Compress inp, the result is valid until the next call.
+  pub fn compress( &mut self, inp: &[u8], c: &mut Config ) -> &[u8]
+  {
+    let mut out = BitStream::new( 0 );
+    out.bytes = std::mem::take( &mut self.bytes );
+    out.bytes.clear();
+    let container = c.container;
+    container.write_header( c, &mut out );
+
+    self.buf.clear();
+    self.buf.extend_from_slice( c.dictionary() );
+    let start = self.buf.len();
+    self.buf.extend_from_slice( inp );
+    let len = self.buf.len();
+    let opts = &c.options;
+
+    self.mlist.clear();
+    if !opts.store_only && len > matcher::MIN_MATCH
+    {
+      let mut m = match self.finder.take()
+      {
+        Some( ( kind, mut m ) ) if kind == opts.match_finder && m.reusable( len, opts ) => { m.reset(); m }
+        _ => matcher::new_finder( len, opts )
+      };
+      let mlist = &mut self.mlist;
+      m.prime( &self.buf, start );
+      if opts.optimal_passes > 0 { optimal::parse( m.as_mut(), &self.buf, start, len, opts, &mut |x| mlist.push( x ) ); }
+      else { m.find( &self.buf, start, len - 2, &mut |x| mlist.push( x ) ); }
+      self.finder = Some( ( opts.match_finder, m ) );
+    }
+
+    if c.stats.is_some() { c.stats = Some( CompressStats::new() ); }
+    write_range( &self.buf, start, len, true, &mut self.mlist.drain( .. ), &mut out, opts, &mut c.stats, &mut self.spare );
+    container.write_trailer( container.check_update( container.check_init(), inp ), inp.len(), &mut out );
+    out.flush();
+    self.bytes = out.bytes;
+    &self.bytes
+  }
+}
+
+/// Write blocks for inp[start..], inp[..start] is a preset dictionary. Block statistics are added to stats if it is Some.
+pub fn write_blocks( inp: &[u8], start: usize, mrx: Receiver<Match>, out: &mut BitStream, opt: &Options, stats: &mut Option<CompressStats> )
+{
+  write_range( inp, start, inp.len(), true, &mut mrx.iter(), out, opt, stats, &mut Vec::new() );
+}
+
+/// Write blocks for inp[start..end], matches are supplied in position order by mi.
+/// If last is true, the final block is marked as the last block of the stream.
+/// If stats is Some, statistics for each block are added, with input offsets relative to start.
+/// Blocks are taken from spare ( if any ) and returned to it once written, so their vectors are reused.
+#[allow(clippy::too_many_arguments)]
+fn write_range<I: Iterator<Item=Match>>( inp: &[u8], start: usize, len: usize, last: bool, mi: &mut I, out: &mut BitStream, opt: &Options,
+  stats: &mut Option<CompressStats>, spare: &mut Vec<Block> )
+{
+  let mut block_start = start; // start of next block
   let mut match_start = 0; // start of matches for next block
   let mut match_position = 0; // latest match position
   let mut mlist : Vec<Match> = Vec::new(); // list of matches
+  if opt.store_only
+  {
+    let before = out.bit_count();
+    let mut b = new_block( spare, start, len - start, 0 );
+    b.write_stored( inp, out, last );
+    add_stats( stats, &b, start, &mlist, BlockType::Stored, out.bit_count() - before );
+    spare.push( b );
+    return;
+  }
   loop
   {
     let mut block_size = len - block_start;
-    let mut target_size = opt.block_size;
+    let target_size = if opt.dynamic_block_size { SPLIT_REGION } else { opt.block_size };
     if block_size > target_size { block_size = target_size; }
 
-    let mut b = Block::new( block_start, block_size, match_start );
-    match_position = get_matches( match_position, b.input_end, &mrx, &mut mlist );
-    b.init( &inp, &mlist );
+    let mut b = new_block( spare, block_start, block_size, match_start );
+    match_position = get_matches( match_position, b.input_end, mi, &mut mlist );
+    b.init( inp, &mlist );
 
-    if opt.dynamic_block_size // Investigate larger block size.
+    if opt.dynamic_block_size // Split the region into blocks where symbol frequencies change.
     {
-      let mut bits = b.bit_size( out );
-      loop
+      let match_end = b.match_end;
+      let ends = split::split( inp, block_start, b.input_end, &mlist[ match_start..match_end ] );
+      let first = new_block( spare, block_start, ends[ 0 ] - block_start, match_start );
+      spare.push( std::mem::replace( &mut b, first ) );
+      b.init( inp, &mlist );
+      for &end in &ends[ 1.. ]
       {
-        // b2 is a block which starts just after b, same size.
-        block_size = len - b.input_end;
-        if block_size == 0 { break; }
-        target_size = b.input_end - b.input_start;
-        if block_size > target_size { block_size = target_size; }
-        let mut b2 = Block::new( b.input_end, block_size, b.match_end );
-        match_position = get_matches( match_position, b2.input_end, &mrx, &mut mlist );
-        b2.init( &inp, &mlist );
-
-        // b3 covers b and b2 exactly as one block.
-        let mut b3 = Block::new( b.input_start, b2.input_end - b.input_start, b.match_start );
-        b3.init( &inp, &mlist );
-
-        let bits2 = b2.bit_size( out );
-        let bits3 = b3.bit_size( out ); 
-
-        if bits3 > bits + bits2 
-        {
-          // tune_boundary( b, b2 ); 
-          break; 
-        }
-        b = b3;
-        bits = bits3;
+        if end <= b.input_end { continue; }
+        let mut b2 = new_block( spare, b.input_end, end - b.input_end, b.match_end );
+        b2.init( inp, &mlist );
+        tune_boundary( inp, &mlist, &mut b, &mut b2, out, spare );
+        write_block( &mut b, inp, start, &mlist, out, false, stats );
+        spare.push( std::mem::replace( &mut b, b2 ) );
       }
     }
 
@@ -117,41 +434,261 @@ pub fn write_blocks( inp: &[u8], mrx: Receiver<Match>, crx: Receiver<u32>, out:
 
     // println!( "block size={} start={} end={}", b.input_end - b.input_start, b.input_start, b.input_end );
 
-    b.write( &inp, &mlist, out, block_start == len );
-    if b.input_end == len { break; }
+    write_block( &mut b, inp, start, &mlist, out, last && block_start == len, stats );
+    let done = b.input_end == len;
+    spare.push( b );
+    if done { break; }
   }   
-  out.pad(8);
-  out.write( 32, crx.recv().unwrap() as u64 );
-  out.flush();
+}
+
+/// A block taken from spare ( reset ), or a new block if spare is empty.
+fn new_block( spare: &mut Vec<Block>, input_start: usize, input_count: usize, match_start: usize ) -> Block
+{
+  match spare.pop()
+  {
+    Some( mut b ) => { b.reset( input_start, input_count, match_start ); b }
+    None => Block::new( input_start, input_count, match_start )
+  }
+}
+
+/// Write block b, adding its statistics if stats is Some.
+fn write_block( b: &mut Block, inp: &[u8], start: usize, mlist: &[Match], out: &mut BitStream, last: bool, stats: &mut Option<CompressStats> )
+{
+  let before = out.bit_count();
+  let block_type = b.write( inp, mlist, out, last );
+  add_stats( stats, b, start, mlist, block_type, out.bit_count() - before );
+}
+
+/// If stats is Some, add the statistics for block b ( with input offsets relative to start ).
+fn add_stats( stats: &mut Option<CompressStats>, b: &Block, start: usize, mlist: &[Match], block_type: BlockType, bits: usize )
+{
+  if let Some( s ) = stats
+  {
+    let n = s.blocks.len();
+    b.stats( mlist, block_type, bits, &mut s.blocks );
+    for bs in &mut s.blocks[ n.. ]
+    {
+      bs.input_start -= start;
+      bs.input_end -= start;
+    }
+  }
+}
+
+/// Amount of input split into blocks at a time when dynamic_block_size is set.
+const SPLIT_REGION : usize = 0x40000;
+
+/// Smallest adjustment made by tune_boundary.
+const MIN_TUNE : usize = 0x40;
+
+/// Move the boundary between adjacent blocks b and b2 ( b2 starts where b ends ) if this reduces the total size.
+fn tune_boundary( inp: &[u8], mlist: &[Match], b: &mut Block, b2: &mut Block, out: &mut BitStream, spare: &mut Vec<Block> )
+{
+  let start = b.input_start;
+  let end = b2.input_end;
+  let mut best = b.dynamic_size( out ) + b2.dynamic_size( out );
+  let mut step = ( end - start ) / 16;
+  while step >= MIN_TUNE
+  {
+    let mut moved = false;
+    for boundary in [ b.input_end.saturating_sub( step ), b.input_end + step ]
+    {
+      if boundary <= start || boundary >= end { continue; }
+      let mut c = new_block( spare, start, boundary - start, b.match_start );
+      c.init( inp, mlist );
+      if c.input_end >= end { spare.push( c ); continue; }
+      let mut c2 = new_block( spare, c.input_end, end - c.input_end, c.match_end );
+      c2.init( inp, mlist );
+      let size = c.dynamic_size( out ) + c2.dynamic_size( out );
+      if size < best
+      {
+        best = size;
+        spare.push( std::mem::replace( b, c ) );
+        spare.push( std::mem::replace( b2, c2 ) );
+        moved = true;
+        break;
+      }
+      spare.push( c );
+      spare.push( c2 );
+    }
+    if !moved { step /= 2; }
+  }
+}
+
+/// Streaming compression, compressed data is written to out as input is supplied ( rather than all at once ).
+/// Input is buffered until enough is available to find matches, with a sliding window of recent input kept as history.
+///
+/// Pending input can be compressed and written to out before the end of input with flush_mode ( for interactive protocols ).
+///
+/// Example:
+/// let mut c = compress::Compressor::new( Vec::new(), &compress::Config::new() );
+/// c.write_all( &data )?;
+/// let cb : Vec<u8> = c.finish()?;
+pub struct Compressor<W: Write>
+{
+  out: W,
+  options: Options,
+  container: Container,
+  matcher: Box<dyn MatchFinder>,
+  buf: Vec<u8>, // Recent input.
+  done: usize, // Length of buf that has been compressed ( the rest is pending ).
+  bits: BitStream,
+  check: u32, // Checksum of input so far.
+  len: usize, // Length of input so far.
+  spare: Vec<Block>, // Blocks reused for each segment.
+}
+
+/// Pending input needed before the streaming compressor compresses a segment.
+const SEGMENT : usize = 0x10000;
+
+/// How Compressor::flush_mode ends the output written so far ( similar to the zlib deflate flush values ).
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Flush
+{
+  Block, // End the current block, the last few bits ( less than a byte ) are held back until more output is written ( Z_BLOCK ).
+  Partial, // End the current block and write an empty fixed code block, so the current block can be decoded ( Z_PARTIAL_FLUSH ).
+  Sync, // End the current block and write an empty stored block, so the output is byte aligned ( Z_SYNC_FLUSH ).
+  Full, // As Sync, and discard the history, so decoding can start from this point ( Z_FULL_FLUSH ).
+}
+
+impl<W: Write> Compressor<W>
+{
+  pub fn new( out: W, c: &Config ) -> Compressor<W>
+  {
+    let mut bits = BitStream::new( SEGMENT );
+    c.container.write_header( c, &mut bits );
+    let mut matcher = matcher::new_finder( SEGMENT + matcher::WINDOW, &c.options );
+    let mut buf = Vec::with_capacity( SEGMENT + matcher::WINDOW + matcher::LOOKAHEAD );
+    buf.extend_from_slice( c.dictionary() );
+    matcher.prime( &buf, buf.len() );
+    Compressor
+    {
+      out,
+      options: c.options.clone(),
+      container: c.container,
+      matcher,
+      done: buf.len(),
+      buf,
+      bits,
+      check: c.container.check_init(),
+      len: 0,
+      spare: Vec::new(),
+    }
+  }
+
+  /// Compress any pending input, write the checksum and return the output.
+  pub fn finish( mut self ) -> std::io::Result<W>
+  {
+    self.compress_segment( true, true );
+    self.container.write_trailer( self.check, self.len, &mut self.bits );
+    self.bits.flush();
+    self.out.write_all( &self.bits.bytes )?;
+    Ok( self.out )
+  }
+
+  /// Compress all pending input and write it to out, ending the output as specified by mode, then flush out.
+  /// The stream can be continued with more input, or ended with finish.
+  pub fn flush_mode( &mut self, mode: Flush ) -> std::io::Result<()>
+  {
+    self.compress_segment( true, false );
+    match mode
+    {
+      Flush::Block => {}
+      Flush::Partial => write_empty_fixed( &mut self.bits ),
+      Flush::Sync | Flush::Full => write_sync( &mut self.bits )
+    }
+    if mode == Flush::Full
+    {
+      self.buf.drain( 0..self.done );
+      self.done = 0;
+      self.matcher.reset();
+    }
+    self.bits.flush_bytes();
+    self.write_output()?;
+    self.out.flush()
+  }
+
+  /// Compress pending input ( except for lookahead unless all is set ), then slide the window.
+  /// If last is set, the final block is marked as the last block of the stream.
+  fn compress_segment( &mut self, all: bool, last: bool )
+  {
+    let len = self.buf.len();
+    let mut mlist = Vec::new();
+    let mut end = if all { len } else { len - matcher::LOOKAHEAD };
+    if !self.options.store_only
+    {
+      let match_end = if all { len.saturating_sub( 2 ) } else { end };
+      let reached = if self.options.optimal_passes > 0
+      {
+        optimal::parse( self.matcher.as_mut(), &self.buf, self.done, end, &self.options, &mut |m| mlist.push( m ) )
+      } else {
+        self.matcher.find( &self.buf, self.done, match_end, &mut |m| mlist.push( m ) )
+      };
+      if !all { end = reached; }
+    }
+
+    if end > self.done || last
+    {
+      write_range( &self.buf, self.done, end, last, &mut mlist.into_iter(), &mut self.bits, &self.options, &mut None, &mut self.spare );
+    }
+    self.done = end;
+
+    if self.done > matcher::WINDOW
+    {
+      let n = self.done - matcher::WINDOW;
+      self.buf.drain( 0..n );
+      self.done -= n;
+      if !self.options.store_only { self.matcher.slide( n ); }
+    }
+  }
+
+  /// Write complete bytes of output to out.
+  fn write_output( &mut self ) -> std::io::Result<()>
+  {
+    self.out.write_all( &self.bits.bytes )?;
+    self.bits.bytes.clear();
+    Ok(())
+  }
+}
+
+impl<W: Write> Write for Compressor<W>
+{
+  fn write( &mut self, data: &[u8] ) -> std::io::Result<usize>
+  {
+    // Accept at most enough input to complete the next segment, so buf does not grow beyond a segment plus history.
+    let full = self.done + SEGMENT + matcher::LOOKAHEAD;
+    let n = std::cmp::min( data.len(), full - self.buf.len() );
+    self.check = self.container.check_update( self.check, &data[..n] );
+    self.len += n;
+    self.buf.extend_from_slice( &data[..n] );
+    if self.buf.len() == full
+    {
+      self.compress_segment( false, false );
+      self.write_output()?;
+    }
+    Ok( n )
+  }
+
+  /// Same as flush_mode( Flush::Sync ).
+  fn flush( &mut self ) -> std::io::Result<()>
+  {
+    self.flush_mode( Flush::Sync )
+  }
 }
 
 /// Get matches up to position.
-fn get_matches( mut match_position: usize, to_position: usize, mrx: &Receiver<Match>, mlist: &mut Vec<Match> ) -> usize
+fn get_matches<I: Iterator<Item=Match>>( mut match_position: usize, to_position: usize, mi: &mut I, mlist: &mut Vec<Match> ) -> usize
 {
   while match_position < to_position 
   {
-    match mrx.recv()
+    match mi.next()
     {
-      Ok( m ) => 
+      Some( m ) => 
       {
         match_position = m.position;
         mlist.push( m );          
       },
-      Err( _err ) => match_position = usize::MAX
+      None => match_position = usize::MAX
     }
   }
   match_position
 }
-
-/// Checksum function per RFC 1950.
-pub fn adler32( input: &[u8] ) -> u32
-{
-  let mut s1 = 1;
-  let mut s2 = 0;
-  for b in input
-  {
-    s1 = ( s1 + *b as u32 ) % 65521;
-    s2 = ( s2 + s1 ) % 65521;
-  }
-  s2 * 65536 + s1   
-}
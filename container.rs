@@ -0,0 +1,174 @@
+use crate::bit::BitStream;
+use crate::compress::Config;
+use crate::checksum::{adler32,adler32_update,adler32_combine,crc32,crc32_update,crc32_combine};
+
+/// Format of the data wrapping RFC 1951 compressed data.
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Container
+{
+  Raw, // RFC 1951 only ( no header or checksum ).
+  Zlib, // RFC 1950 : 2 byte header, Adler-32 checksum.
+  Gzip, // RFC 1952 : header with optional fields, CRC-32 checksum and input length.
+}
+
+impl Container
+{
+  /// Checksum of no input.
+  pub fn check_init( self ) -> u32
+  {
+    match self
+    {
+      Container::Gzip => 0,
+      _ => 1
+    }
+  }
+
+  /// Update checksum with more input ( Raw has no checksum ).
+  pub fn check_update( self, check: u32, input: &[u8] ) -> u32
+  {
+    match self
+    {
+      Container::Raw => check,
+      Container::Zlib => adler32_update( check, input ),
+      Container::Gzip => crc32_update( check, input )
+    }
+  }
+
+  /// Checksum of two adjacent pieces of input, given the checksum of each ( a and b ) and the length of the second.
+  pub fn check_combine( self, a: u32, b: u32, len_b: usize ) -> u32
+  {
+    match self
+    {
+      Container::Raw => a,
+      Container::Zlib => adler32_combine( a, b, len_b ),
+      Container::Gzip => crc32_combine( a, b, len_b )
+    }
+  }
+
+  /// Write the header that precedes the compressed data.
+  pub fn write_header( self, c: &Config, out: &mut BitStream )
+  {
+    let bytes = match self
+    {
+      Container::Raw => Vec::new(),
+      Container::Zlib => 
+      {
+        let dictid = if c.dictionary.is_empty() { None } else { Some( adler32( &c.dictionary ) ) };
+        ZlibHeader{ window_size: 0x8000, level: c.level.flevel(), dictid }.to_bytes()
+      }
+      Container::Gzip => c.gzip.to_bytes()
+    };
+    for b in bytes { out.write( 8, b as u64 ); }
+  }
+
+  /// Write the trailer that follows the compressed data, check is the checksum of the input, len is the input length.
+  pub fn write_trailer( self, check: u32, len: usize, out: &mut BitStream )
+  {
+    out.pad( 8 );
+    match self
+    {
+      Container::Raw => (),
+      Container::Zlib => out.write( 32, check.swap_bytes() as u64 ), // Most significant byte first.
+      Container::Gzip =>
+      {
+        out.write( 32, check as u64 );
+        out.write( 32, len as u32 as u64 );
+      }
+    }
+  }
+}
+
+/// RFC 1950 header fields.
+#[derive(Clone,Debug,PartialEq)]
+pub struct ZlibHeader
+{
+  pub window_size: usize, // LZ77 window size used by the compressor ( 256 to 32K ).
+  pub level: u8, // Compression level, 0 = fastest, 1 = fast, 2 = default, 3 = maximum.
+  pub dictid: Option<u32>, // Adler-32 of the preset dictionary ( FDICT ).
+}
+
+impl ZlibHeader
+{
+  /// Get the header as bytes.
+  pub fn to_bytes( &self ) -> Vec<u8>
+  {
+    let mut cinfo = 0;
+    while 256 << cinfo < self.window_size { cinfo += 1; }
+    let cmf = cinfo << 4 | 8;
+    let mut flg = self.level << 6;
+    if self.dictid.is_some() { flg |= 32; }
+    let check = ( ( cmf as u16 ) << 8 | flg as u16 ) % 31;
+    if check != 0 { flg += ( 31 - check ) as u8; } // FCHECK
+    let mut b = vec![ cmf, flg ];
+    if let Some( id ) = self.dictid { b.extend_from_slice( &id.to_be_bytes() ); }
+    b
+  }
+}
+
+/// RFC 1952 header fields.
+#[derive(Clone,Debug,PartialEq)]
+pub struct GzipHeader
+{
+  pub text: bool, // Data is probably text ( FTEXT ).
+  pub mtime: u32, // Modification time of the original file, seconds since 1970, 0 if not available.
+  pub xfl: u8, // Extra flags, 2 = maximum compression, 4 = fastest.
+  pub os: u8, // Operating system, 255 = unknown.
+  pub extra: Option<Vec<u8>>, // Extra field ( FEXTRA ).
+  pub filename: Option<Vec<u8>>, // Original file name, ISO 8859-1, zero terminated in header ( FNAME ).
+  pub comment: Option<Vec<u8>>, // File comment, ISO 8859-1, zero terminated in header ( FCOMMENT ).
+  pub hcrc: bool, // Header has a CRC16 ( FHCRC ).
+}
+
+// Flag bits.
+pub const FTEXT : u8 = 1;
+pub const FHCRC : u8 = 2;
+pub const FEXTRA : u8 = 4;
+pub const FNAME : u8 = 8;
+pub const FCOMMENT : u8 = 16;
+
+impl GzipHeader
+{
+  pub fn new() -> GzipHeader
+  {
+    GzipHeader
+    {
+      text: false,
+      mtime: 0,
+      xfl: 0,
+      os: 255,
+      extra: None,
+      filename: None,
+      comment: None,
+      hcrc: false
+    }
+  }
+
+  /// Get the header as bytes.
+  pub fn to_bytes( &self ) -> Vec<u8>
+  {
+    let mut flags = 0;
+    if self.text { flags |= FTEXT; }
+    if self.hcrc { flags |= FHCRC; }
+    if self.extra.is_some() { flags |= FEXTRA; }
+    if self.filename.is_some() { flags |= FNAME; }
+    if self.comment.is_some() { flags |= FCOMMENT; }
+
+    let mut b = vec![ 0x1f, 0x8b, 8, flags ];
+    b.extend_from_slice( &self.mtime.to_le_bytes() );
+    b.push( self.xfl );
+    b.push( self.os );
+    if let Some( x ) = &self.extra
+    {
+      b.extend_from_slice( &( x.len() as u16 ).to_le_bytes() );
+      b.extend_from_slice( x );
+    }
+    if let Some( x ) = &self.filename { b.extend_from_slice( x ); b.push( 0 ); }
+    if let Some( x ) = &self.comment { b.extend_from_slice( x ); b.push( 0 ); }
+    if self.hcrc
+    {
+      let crc = crc32( &b ) as u16;
+      b.extend_from_slice( &crc.to_le_bytes() );
+    }
+    b
+  }
+}
@@ -0,0 +1,348 @@
+//! Alternative LZ77 match finders, selected by compress::Options::match_finder ( see matcher::Finder ).
+//! Like Matcher, positions are held encoded ( see ENCODE_POSITION ), and link vectors are as long as the input.
+
+use crate::compress;
+use crate::matcher::{self,Match,MatchFinder,MIN_MATCH,MAX_MATCH,ENCODE_POSITION};
+
+/// Hash of the n bytes at input[ position.. ], in the range 0..1 << bits.
+fn hash( input: &[u8], position: usize, n: usize, bits: usize ) -> usize
+{
+  let mut x = 0;
+  for i in 0..n { x |= ( input[ position + i ] as u64 ) << ( 8 * i ); }
+  ( x.wrapping_mul( 0x9e3779b97f4a7c15 ) >> ( 64 - bits ) ) as usize
+}
+
+/// Number of hash bits for input of about len bytes.
+fn hash_bits( len: usize ) -> usize
+{
+  let bits = ( usize::BITS - ( len * 2 ).leading_zeros() ) as usize;
+  bits.clamp( 10, 18 )
+}
+
+/// Length of the match between input[ old.. ] and input[ position.. ], up to avail.
+fn match_length( input: &[u8], old: usize, position: usize, avail: usize ) -> usize
+{
+  let mut n = 0;
+  while n < avail && input[ old + n ] == input[ position + n ] { n += 1; }
+  n
+}
+
+/// Follow the chain of positions from entry, appending the matches longer than best to output ( see MatchFinder::matches ).
+/// Returns the longest match length.
+fn chain_matches( input: &[u8], position: usize, mut entry: usize, link: &[usize], probe_max: usize, mut best: usize,
+  output: &mut Vec<( u16, u16 )> ) -> usize
+{
+  let avail = std::cmp::min( input.len() - position, MAX_MATCH );
+  let mut probes = probe_max;
+  while entry > position && probes > 0 && best < avail
+  {
+    let old = entry - ENCODE_POSITION;
+    if input[ old + best ] == input[ position + best ]
+    {
+      let n = match_length( input, old, position, avail );
+      if n > best
+      {
+        best = n;
+        output.push( ( n as u16, ( position - old ) as u16 ) );
+      }
+    }
+    entry = link[ old ];
+    probes -= 1;
+  }
+  best
+}
+
+/// Encoded positions adjusted for the first n positions being discarded ( see MatchFinder::slide ).
+fn slide_positions( v: &mut [usize], n: usize )
+{
+  v.iter_mut().for_each( | x | *x = if *x >= n + ENCODE_POSITION { *x - n } else { 0 } );
+}
+
+/// Hash chains keyed on the first n ( 4 or 5 ) bytes. Fewer positions share a chain than with 3 byte hashing, so searching
+/// is faster, but matches shorter than n bytes are only found by chance.
+pub struct HashChains
+{
+  n: usize, // Number of bytes hashed.
+  bits: usize,
+  head: Vec<usize>, // Latest position for each hash.
+  link: Vec<usize>, // Previous position with the same hash as each position.
+  probe_max: usize,
+  lazy_match: bool
+}
+
+impl HashChains
+{
+  pub fn new( n: usize, len: usize, opts: &compress::Options ) -> HashChains
+  {
+    let bits = hash_bits( len );
+    HashChains{ n, bits, head: vec![ 0; 1 << bits ], link: Vec::new(), probe_max: opts.probe_max, lazy_match: opts.lazy_match }
+  }
+
+  /// Insert position, returning the previous position with the same hash ( 0 if none, or position is too near the end of input ).
+  fn update( &mut self, input: &[u8], position: usize ) -> usize
+  {
+    if position + self.n > input.len() { return 0; }
+    if self.link.len() <= position { self.link.resize( input.len(), 0 ); }
+    let h = hash( input, position, self.n, self.bits );
+    let entry = self.head[ h ];
+    self.head[ h ] = position + ENCODE_POSITION;
+    self.link[ position ] = entry;
+    entry
+  }
+}
+
+impl MatchFinder for HashChains
+{
+  fn insert( &mut self, input: &[u8], position: usize )
+  {
+    self.update( input, position );
+  }
+
+  fn matches( &mut self, input: &[u8], position: usize, output: &mut Vec<( u16, u16 )> )
+  {
+    let entry = self.update( input, position );
+    chain_matches( input, position, entry, &self.link, self.probe_max, MIN_MATCH - 1, output );
+  }
+
+  fn find( &mut self, input: &[u8], position: usize, end: usize, output: &mut dyn FnMut( Match ) ) -> usize
+  {
+    let lazy = self.lazy_match;
+    matcher::parse( self, input, position, end, lazy, output )
+  }
+
+  fn reset( &mut self )
+  {
+    self.head.iter_mut().for_each( |x| *x = 0 );
+    self.link.clear();
+  }
+
+  fn reusable( &self, len: usize, opts: &compress::Options ) -> bool
+  {
+    self.bits == hash_bits( len ) && self.probe_max == opts.probe_max && self.lazy_match == opts.lazy_match
+  }
+
+  fn slide( &mut self, n: usize )
+  {
+    slide_positions( &mut self.head, n );
+    self.link.drain( 0..std::cmp::min( n, self.link.len() ) );
+    slide_positions( &mut self.link, n );
+  }
+}
+
+/// Two hash tables : the latest position for each 3 byte hash, and chains for 4 byte hashes. The 4 byte chains are searched
+/// first, and if they give no match, the latest position with the same 3 byte hash is tried, so short matches are still found.
+pub struct Hash34
+{
+  bits: usize,
+  head3: Vec<usize>, // Latest position for each 3 byte hash.
+  head4: Vec<usize>, // Latest position for each 4 byte hash.
+  link: Vec<usize>, // Previous position with the same 4 byte hash as each position.
+  probe_max: usize,
+  lazy_match: bool
+}
+
+impl Hash34
+{
+  pub fn new( len: usize, opts: &compress::Options ) -> Hash34
+  {
+    let bits = hash_bits( len );
+    Hash34
+    {
+      bits,
+      head3: vec![ 0; 1 << bits ],
+      head4: vec![ 0; 1 << bits ],
+      link: Vec::new(),
+      probe_max: opts.probe_max,
+      lazy_match: opts.lazy_match
+    }
+  }
+
+  /// Insert position, returning the previous positions with the same 3 byte and 4 byte hashes.
+  fn update( &mut self, input: &[u8], position: usize ) -> ( usize, usize )
+  {
+    let mut result = ( 0, 0 );
+    if position + 3 <= input.len()
+    {
+      let h = hash( input, position, 3, self.bits );
+      result.0 = self.head3[ h ];
+      self.head3[ h ] = position + ENCODE_POSITION;
+    }
+    if position + 4 <= input.len()
+    {
+      if self.link.len() <= position { self.link.resize( input.len(), 0 ); }
+      let h = hash( input, position, 4, self.bits );
+      result.1 = self.head4[ h ];
+      self.head4[ h ] = position + ENCODE_POSITION;
+      self.link[ position ] = result.1;
+    }
+    result
+  }
+}
+
+impl MatchFinder for Hash34
+{
+  fn insert( &mut self, input: &[u8], position: usize )
+  {
+    self.update( input, position );
+  }
+
+  fn matches( &mut self, input: &[u8], position: usize, output: &mut Vec<( u16, u16 )> )
+  {
+    let ( entry3, entry4 ) = self.update( input, position );
+    if chain_matches( input, position, entry4, &self.link, self.probe_max, MIN_MATCH - 1, output ) < MIN_MATCH && entry3 > position
+    {
+      let old = entry3 - ENCODE_POSITION;
+      let avail = std::cmp::min( input.len() - position, MAX_MATCH );
+      let n = match_length( input, old, position, avail );
+      if n >= MIN_MATCH { output.push( ( n as u16, ( position - old ) as u16 ) ); }
+    }
+  }
+
+  fn find( &mut self, input: &[u8], position: usize, end: usize, output: &mut dyn FnMut( Match ) ) -> usize
+  {
+    let lazy = self.lazy_match;
+    matcher::parse( self, input, position, end, lazy, output )
+  }
+
+  fn reset( &mut self )
+  {
+    self.head3.iter_mut().for_each( |x| *x = 0 );
+    self.head4.iter_mut().for_each( |x| *x = 0 );
+    self.link.clear();
+  }
+
+  fn reusable( &self, len: usize, opts: &compress::Options ) -> bool
+  {
+    self.bits == hash_bits( len ) && self.probe_max == opts.probe_max && self.lazy_match == opts.lazy_match
+  }
+
+  fn slide( &mut self, n: usize )
+  {
+    slide_positions( &mut self.head3, n );
+    slide_positions( &mut self.head4, n );
+    self.link.drain( 0..std::cmp::min( n, self.link.len() ) );
+    slide_positions( &mut self.link, n );
+  }
+}
+
+/// Binary trees of the positions with the same 4 byte hash, like LZMA's bt4. Each tree is ordered by the bytes following
+/// the positions ( newest at the root ), so each step of the search halves the candidates, and long matches are found
+/// quickly. Every position is searched when inserted ( to keep the tree ordered ), so updating is slower than hash chains.
+pub struct BinaryTree
+{
+  bits: usize,
+  head: Vec<usize>, // Root of the tree for each hash.
+  son: Vec<usize>, // Left ( smaller ) and right ( greater ) subtrees of each position.
+  depth_max: usize, // Maximum number of tree nodes visited ( probe_max ).
+  lazy_match: bool
+}
+
+impl BinaryTree
+{
+  pub fn new( len: usize, opts: &compress::Options ) -> BinaryTree
+  {
+    let bits = hash_bits( len );
+    BinaryTree{ bits, head: vec![ 0; 1 << bits ], son: Vec::new(), depth_max: opts.probe_max, lazy_match: opts.lazy_match }
+  }
+
+  /// Insert position as the root of its tree, appending the matches found to output if it is Some.
+  /// Nodes smaller than position are moved to its left subtree, greater nodes to its right subtree, and nodes too far
+  /// back to be matched are removed.
+  fn update( &mut self, input: &[u8], position: usize, mut output: Option<&mut Vec<( u16, u16 )>> )
+  {
+    if position + 4 > input.len() { return; }
+    if self.son.len() <= 2 * position { self.son.resize( 2 * input.len(), 0 ); }
+    let h = hash( input, position, 4, self.bits );
+    let mut entry = self.head[ h ];
+    self.head[ h ] = position + ENCODE_POSITION;
+
+    let avail = std::cmp::min( input.len() - position, MAX_MATCH );
+    let mut left = 2 * position; // Where the next node smaller than position is attached.
+    let mut right = 2 * position + 1; // Where the next node greater than position is attached.
+    let ( mut left_len, mut right_len ) = ( 0, 0 ); // Bytes known to match the nodes either side.
+    let mut best = MIN_MATCH - 1;
+    let mut depth = self.depth_max;
+    loop
+    {
+      if entry <= position || depth == 0
+      {
+        self.son[ left ] = 0;
+        self.son[ right ] = 0;
+        return;
+      }
+      depth -= 1;
+      let old = entry - ENCODE_POSITION;
+      let skip = std::cmp::min( left_len, right_len );
+      let n = skip + match_length( input, old + skip, position + skip, avail - skip );
+      if n > best
+      {
+        best = n;
+        if let Some( out ) = output.as_mut() { out.push( ( n as u16, ( position - old ) as u16 ) ); }
+      }
+      if n == avail && avail < MAX_MATCH
+      {
+        // The input ends before the order of position and old is known ( more input may follow after a flush, see 
+        // Compressor::flush_mode ), so old and its subtrees are removed from the tree.
+        self.son[ left ] = 0;
+        self.son[ right ] = 0;
+        return;
+      }
+      if n == avail
+      {
+        // position replaces old in the tree.
+        self.son[ left ] = self.son[ 2 * old ];
+        self.son[ right ] = self.son[ 2 * old + 1 ];
+        return;
+      }
+      if input[ old + n ] < input[ position + n ]
+      {
+        self.son[ left ] = entry;
+        left = 2 * old + 1;
+        entry = self.son[ left ];
+        left_len = n;
+      } else {
+        self.son[ right ] = entry;
+        right = 2 * old;
+        entry = self.son[ right ];
+        right_len = n;
+      }
+    }
+  }
+}
+
+impl MatchFinder for BinaryTree
+{
+  fn insert( &mut self, input: &[u8], position: usize )
+  {
+    self.update( input, position, None );
+  }
+
+  fn matches( &mut self, input: &[u8], position: usize, output: &mut Vec<( u16, u16 )> )
+  {
+    self.update( input, position, Some( output ) );
+  }
+
+  fn find( &mut self, input: &[u8], position: usize, end: usize, output: &mut dyn FnMut( Match ) ) -> usize
+  {
+    let lazy = self.lazy_match;
+    matcher::parse( self, input, position, end, lazy, output )
+  }
+
+  fn reset( &mut self )
+  {
+    self.head.iter_mut().for_each( |x| *x = 0 );
+    self.son.clear();
+  }
+
+  fn reusable( &self, len: usize, opts: &compress::Options ) -> bool
+  {
+    self.bits == hash_bits( len ) && self.depth_max == opts.probe_max && self.lazy_match == opts.lazy_match
+  }
+
+  fn slide( &mut self, n: usize )
+  {
+    slide_positions( &mut self.head, n );
+    self.son.drain( 0..std::cmp::min( 2 * n, self.son.len() ) );
+    slide_positions( &mut self.son, n );
+  }
+}
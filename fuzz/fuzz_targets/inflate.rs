@@ -0,0 +1,37 @@
+// Inflate arbitrary bytes in each container with each API. Errors are expected, panics are not.
+// Run with: cargo fuzz run inflate
+
+#![no_main]
+#![allow(dead_code)]
+
+use std::io::Read;
+use libfuzzer_sys::fuzz_target;
+#[path = "../../compress.rs"] mod compress;
+#[path = "../../bit.rs"] mod bit;
+#[path = "../../huffman.rs"] mod huffman;
+#[path = "../../matcher.rs"] mod matcher;
+#[path = "../../finder.rs"] mod finder;
+#[path = "../../block.rs"] mod block;
+#[path = "../../inflate.rs"] mod inflate;
+#[path = "../../container.rs"] mod container;
+#[path = "../../optimal.rs"] mod optimal;
+#[path = "../../split.rs"] mod split;
+#[path = "../../checksum.rs"] mod checksum;
+
+use container::Container;
+use inflate::{InflateOptions,Inflater};
+
+fuzz_target!( |data: &[u8]|
+{
+  for container in [ Container::Raw, Container::Zlib, Container::Gzip ]
+  {
+    let mut opts = InflateOptions::new();
+    opts.container = container;
+    opts.max_output = 1 << 24; // Limit memory use for highly compressed input.
+    let _ = inflate::try_inflate_with( data, &opts );
+    let mut ob = vec![ 0; 0x10000 ];
+    let _ = inflate::inflate_into_with( data, &mut ob, &opts );
+    let mut ub = Vec::new();
+    let _ = Inflater::with_options( data, &opts ).read_to_end( &mut ub );
+  }
+} );
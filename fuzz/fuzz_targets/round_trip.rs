@@ -0,0 +1,39 @@
+// Compress arbitrary input and check it inflates to the input. The first two bytes select the options.
+// Run with: cargo fuzz run round_trip
+
+#![no_main]
+#![allow(dead_code)]
+
+use libfuzzer_sys::fuzz_target;
+#[path = "../../compress.rs"] mod compress;
+#[path = "../../bit.rs"] mod bit;
+#[path = "../../huffman.rs"] mod huffman;
+#[path = "../../matcher.rs"] mod matcher;
+#[path = "../../finder.rs"] mod finder;
+#[path = "../../block.rs"] mod block;
+#[path = "../../inflate.rs"] mod inflate;
+#[path = "../../container.rs"] mod container;
+#[path = "../../optimal.rs"] mod optimal;
+#[path = "../../split.rs"] mod split;
+#[path = "../../checksum.rs"] mod checksum;
+
+use compress::{Config,Level};
+use container::Container;
+
+fuzz_target!( |data: &[u8]|
+{
+  if data.len() < 2 { return; }
+  let ( a, b, input ) = ( data[ 0 ], data[ 1 ], &data[ 2.. ] );
+  let mut c = Config::with_level( Level( a % 10 ) );
+  c.options.dynamic_block_size = a & 0x10 != 0;
+  c.options.lazy_match = a & 0x20 != 0;
+  c.options.optimal_passes = ( a >> 6 ) as usize;
+  c.options.block_size = 0x100 << ( b & 7 );
+  c.options.match_finder = matcher::FINDERS[ ( b >> 5 ) as usize % matcher::FINDERS.len() ];
+  c.container = [ Container::Raw, Container::Zlib, Container::Gzip ][ ( b >> 3 ) as usize % 3 ];
+
+  let cb = compress::compress( input, &mut c );
+  let mut opts = inflate::InflateOptions::new();
+  opts.container = c.container;
+  assert!( inflate::try_inflate_with( &cb, &opts ).unwrap() == input );
+} );
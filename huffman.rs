@@ -0,0 +1,436 @@
+//! Length-limited canonical Huffman coding for general alphabets ( not just the RFC 1951 alphabets ).
+//!
+//! A canonical code is fully described by the code length of each symbol ( RFC 1951 section 3.2.2 ), code length 0
+//! meaning the symbol is not used. Codes are written to a BitStream in RFC 1951 bit order ( the first bit of a code
+//! is the least significant bit written ), and read back using inflate::InputBitStream.
+//!
+//! bit::BitCoder and the inflate decoder are built on this module.
+
+use std::io::BufRead;
+use crate::bit::BitStream;
+use crate::inflate::{InputBitStream,reverse};
+
+/// Maximum code length.
+pub const MAX_BITS : usize = 32;
+
+/// Maximum number of symbols in an alphabet.
+pub const MAX_SYMBOLS : usize = 1 << 24;
+
+/// Reasons Huffman coding can fail.
+#[derive(Debug,PartialEq)]
+pub enum HuffmanError
+{
+  TooManySymbols, // Alphabet has more than MAX_SYMBOLS symbols.
+  InvalidLimit, // Limit is zero, more than MAX_BITS, or too small for the number of symbols used.
+  InvalidLengths, // Code lengths are over-subscribed, or a length is more than MAX_BITS.
+  InvalidCode, // Input has a code that is not assigned to any symbol.
+  Truncated, // Input ended before the end of a code.
+}
+
+impl std::fmt::Display for HuffmanError
+{
+  fn fmt( &self, f: &mut std::fmt::Formatter ) -> std::fmt::Result
+  {
+    f.write_str( match self
+    {
+      HuffmanError::TooManySymbols => "Too many symbols",
+      HuffmanError::InvalidLimit => "Invalid code length limit",
+      HuffmanError::InvalidLengths => "Invalid code lengths",
+      HuffmanError::InvalidCode => "Invalid Huffman code",
+      HuffmanError::Truncated => "Unexpected end of input",
+    } )
+  }
+}
+
+impl std::error::Error for HuffmanError {}
+
+/// Compute optimal code lengths of at most limit bits from the frequency of each symbol.
+/// Unused symbols get length 0. If only one symbol is used, it gets a 1 bit code.
+pub fn code_lengths( freq: &[u32], limit: usize ) -> Result<Vec<u8>, HuffmanError>
+{
+  if freq.len() > MAX_SYMBOLS { return Err( HuffmanError::TooManySymbols ); }
+  if limit == 0 || limit > MAX_BITS { return Err( HuffmanError::InvalidLimit ); }
+
+  let mut bits = vec![ 0; freq.len() ];
+
+  // Leaves are ( frequency, symbol ), cheapest first.
+  let mut leaves : Vec<( u64, usize )> = Vec::new();
+  for ( sym, f ) in freq.iter().enumerate()
+  {
+    if *f > 0 { leaves.push( ( *f as u64, sym ) ); }
+  }
+  leaves.sort_unstable();
+
+  let n = leaves.len();
+  if n == 1 { bits[ leaves[ 0 ].1 ] = 1; }
+  if n < 2 { return Ok( bits ); }
+  if ( n - 1 ) >> limit != 0 { return Err( HuffmanError::InvalidLimit ); }
+
+  // Usually a Huffman code is within the limit, otherwise the package-merge algorithm is used.
+  if huffman( &leaves, limit, &mut bits ) { return Ok( bits ); }
+  bits.iter_mut().for_each( |b| *b = 0 );
+
+  // No optimal code is longer than n - 1 bits, so there is no need for more passes than that.
+  let passes = std::cmp::min( limit, n - 1 );
+
+  // Each pass pairs up the cheapest items from the leaves and the packages made by the previous pass.
+  // Items are ( weight, node ), a node less than n is a leaf, otherwise it is a package with children pairs[ node - n ].
+  let mut pairs : Vec<( usize, usize )> = Vec::new();
+  let mut merged : Vec<( u64, usize )> = Vec::new();
+  for _ in 0..passes
+  {
+    let mut next = Vec::with_capacity( n );
+    let ( mut li, mut mi ) = ( 0, 0 );
+    for _ in 0..( n + merged.len() ) / 2
+    {
+      let left = cheapest( &leaves, &merged, &mut li, &mut mi );
+      let right = cheapest( &leaves, &merged, &mut li, &mut mi );
+      next.push( ( left.0 + right.0, n + pairs.len() ) );
+      pairs.push( ( left.1, right.1 ) );
+    }
+    merged = next;
+  }
+
+  // The code length of a symbol is the number of times its leaf occurs in the final packages.
+  let mut stack : Vec<usize> = merged.iter().map( |m| m.1 ).collect();
+  while let Some( node ) = stack.pop()
+  {
+    if node < n
+    {
+      bits[ leaves[ node ].1 ] += 1;
+    } else {
+      let ( left, right ) = pairs[ node - n ];
+      stack.push( left );
+      stack.push( right );
+    }
+  }
+  Ok( bits )
+}
+
+// Set bits to the lengths of a Huffman code for leaves, returning false if a code is longer than limit.
+// As leaves are sorted, the packages are made in order of weight, so a queue holds them rather than a heap.
+fn huffman( leaves: &[( u64, usize )], limit: usize, bits: &mut [u8] ) -> bool
+{
+  let n = leaves.len();
+  let mut pairs : Vec<( usize, usize )> = Vec::with_capacity( n - 1 );
+  let mut merged : Vec<( u64, usize )> = Vec::with_capacity( n - 1 );
+  let ( mut li, mut mi ) = ( 0, 0 );
+  for _ in 1..n
+  {
+    let left = cheapest( leaves, &merged, &mut li, &mut mi );
+    let right = cheapest( leaves, &merged, &mut li, &mut mi );
+    merged.push( ( left.0 + right.0, n + pairs.len() ) );
+    pairs.push( ( left.1, right.1 ) );
+  }
+
+  // Packages are made after their children, so the depth of each node is known from the root down.
+  let mut depth = vec![ 0; n - 1 ];
+  for p in ( 0..n - 1 ).rev()
+  {
+    let d = depth[ p ] + 1;
+    if d > limit { return false; }
+    for node in [ pairs[ p ].0, pairs[ p ].1 ]
+    {
+      if node < n { bits[ leaves[ node ].1 ] = d as u8; } else { depth[ node - n ] = d; }
+    }
+  }
+  true
+}
+
+// Take the cheapest item from leaves or merged ( a leaf if weights are equal ).
+fn cheapest( leaves: &[( u64, usize )], merged: &[( u64, usize )], li: &mut usize, mi: &mut usize ) -> ( u64, usize )
+{
+  if *mi < merged.len() && ( *li == leaves.len() || merged[ *mi ].0 < leaves[ *li ].0 )
+  {
+    *mi += 1;
+    merged[ *mi - 1 ]
+  } else {
+    *li += 1;
+    ( leaves[ *li - 1 ].0, *li - 1 )
+  }
+}
+
+/// Number of codes of each length ( index 0 is unused ), checking the code lengths are valid.
+fn count_lengths( lengths: &[u8] ) -> Result<Vec<usize>, HuffmanError>
+{
+  if lengths.len() > MAX_SYMBOLS { return Err( HuffmanError::TooManySymbols ); }
+  let mut bl_count = vec![ 0; MAX_BITS + 1 ];
+  for len in lengths
+  {
+    if *len as usize > MAX_BITS { return Err( HuffmanError::InvalidLengths ); }
+    bl_count[ *len as usize ] += 1;
+  }
+  bl_count[ 0 ] = 0;
+
+  // Check the code is not over-subscribed ( an incomplete code is allowed, the unused codes are invalid input ).
+  let mut left : i64 = 1;
+  for count in &bl_count[ 1.. ]
+  {
+    left = ( left << 1 ) - *count as i64;
+    if left < 0 { return Err( HuffmanError::InvalidLengths ); }
+  }
+  Ok( bl_count )
+}
+
+/// Compute the canonical code for each symbol from the code lengths, with the bits of each code reversed
+/// ready to be written to a BitStream.
+pub fn canonical_codes( lengths: &[u8] ) -> Result<Vec<u32>, HuffmanError>
+{
+  let bl_count = count_lengths( lengths )?;
+
+  // Code below is from RFC 1951 page 7.
+  let mut next_code = vec![ 0; MAX_BITS + 1 ];
+  let mut code = 0;
+  for bits in 1..=MAX_BITS
+  {
+    code = ( code + bl_count[ bits - 1 ] ) << 1;
+    next_code[ bits ] = code;
+  }
+
+  let mut codes = Vec::with_capacity( lengths.len() );
+  for len in lengths
+  {
+    let len = *len as usize;
+    codes.push( reverse( next_code[ len ], len ) as u32 );
+    next_code[ len ] += 1;
+  }
+  Ok( codes )
+}
+
+/// Write code lengths to output.
+/// Format: 3 bits w, the bit width of the longest code length ( 0 if no symbol is used, in which case nothing follows ).
+/// Then runs of equal lengths, each written as the length ( w bits ) and the run length minus one,
+/// in groups of 4 bits ( 3 bits of count, least significant first, and a bit that is set if another group follows ).
+/// The number of symbols is not written, it must be known when the lengths are read.
+pub fn write_lengths( lengths: &[u8], output: &mut BitStream )
+{
+  let max = *lengths.iter().max().unwrap_or( &0 );
+  let w = 8 - max.leading_zeros() as u8;
+  output.write( 3, w as u64 );
+  if w == 0 { return; }
+
+  let mut i = 0;
+  while i < lengths.len()
+  {
+    let len = lengths[ i ];
+    let mut j = i + 1;
+    while j < lengths.len() && lengths[ j ] == len { j += 1; }
+    output.write( w, len as u64 );
+    let mut run = j - i - 1;
+    loop
+    {
+      let more = run >= 8;
+      output.write( 4, ( run & 7 ) as u64 | if more { 8 } else { 0 } );
+      run >>= 3;
+      if !more { break; }
+    }
+    i = j;
+  }
+}
+
+/// Read code lengths for nsym symbols written by write_lengths.
+pub fn read_lengths<R: BufRead>( input: &mut InputBitStream<R>, nsym: usize ) -> Result<Vec<u8>, HuffmanError>
+{
+  if nsym > MAX_SYMBOLS { return Err( HuffmanError::TooManySymbols ); }
+  let w = input.get_bits( 3 );
+  if w > 6 { return Err( HuffmanError::InvalidLengths ); }
+
+  let mut lengths = Vec::with_capacity( nsym );
+  if w == 0
+  {
+    lengths.resize( nsym, 0 );
+  }
+  while lengths.len() < nsym
+  {
+    let len = input.get_bits( w );
+    if len > MAX_BITS { return Err( HuffmanError::InvalidLengths ); }
+    let mut run = 0;
+    let mut shift = 0;
+    loop
+    {
+      let group = input.get_bits( 4 );
+      run |= ( group & 7 ) << shift;
+      shift += 3;
+      if group & 8 == 0 { break; }
+      if shift > 24 || input.truncated() { return Err( HuffmanError::InvalidLengths ); }
+    }
+    if input.truncated() { return Err( HuffmanError::Truncated ); }
+    if run >= nsym - lengths.len() { return Err( HuffmanError::InvalidLengths ); }
+    lengths.resize( lengths.len() + run + 1, len as u8 );
+  }
+  if input.truncated() { return Err( HuffmanError::Truncated ); }
+  Ok( lengths )
+}
+
+/// Writes symbols to a BitStream.
+pub struct Encoder
+{
+  pub lengths: Vec<u8>, // Code length for each symbol ( 0 if the symbol is not used ).
+  pub codes: Vec<u32>, // Code for each symbol ( bits reversed ).
+}
+
+impl Encoder
+{
+  /// Encoder for the canonical code with the given code lengths.
+  pub fn new( lengths: Vec<u8> ) -> Result<Encoder, HuffmanError>
+  {
+    let codes = canonical_codes( &lengths )?;
+    Ok( Encoder { lengths, codes } )
+  }
+
+  /// Encoder for an optimal code with codes of at most limit bits, given the frequency of each symbol.
+  pub fn from_frequencies( freq: &[u32], limit: usize ) -> Result<Encoder, HuffmanError>
+  {
+    Encoder::new( code_lengths( freq, limit )? )
+  }
+
+  /// Write the code lengths to output ( see write_lengths ).
+  pub fn write_lengths( &self, output: &mut BitStream )
+  {
+    write_lengths( &self.lengths, output );
+  }
+
+  /// Write the code for sym to output ( sym must have a non-zero code length ).
+  pub fn encode( &self, sym: usize, output: &mut BitStream )
+  {
+    debug_assert!( self.lengths[ sym ] > 0 );
+    output.write( self.lengths[ sym ], self.codes[ sym ] as u64 );
+  }
+}
+
+/// Maximum number of bits for the table lookup.
+const PEEK : usize = 10;
+
+/// Reads symbols from an InputBitStream.
+// Codes of up to peekbits are decoded with a single table lookup, longer codes are decoded a bit at a time
+// from the counts of codes of each length ( as in zlib's puff.c ).
+// Decoding gives an entry for the symbol, which is the symbol unless the Decoder was made by with_entries.
+pub struct Decoder
+{
+  maxbits: usize, // Length of the longest code.
+  peekbits: usize, // Bit length of the table lookup ( not greater than PEEK ).
+  lookup: Vec<u32>, // Entry << 8 | code length, or 0 if the code is longer than peekbits ( or not assigned ).
+  count: Vec<usize>, // Number of codes of each length.
+  sorted: Vec<u32>, // Entries in code order.
+  long: ( usize, usize ), // First code of length peekbits + 1, and its index in sorted.
+  complete: bool, // Every code is assigned to a symbol.
+}
+
+impl Decoder
+{
+  /// Decoder for the canonical code with the given code lengths.
+  pub fn new( lengths: &[u8] ) -> Result<Decoder, HuffmanError>
+  {
+    Decoder::with_entries( lengths, |sym| sym as u32 )
+  }
+
+  /// Decoder that gives entry( sym ) rather than sym for each symbol, so one lookup can give everything a caller needs
+  /// to know about a symbol ( entries must be less than 1 << 24 ).
+  pub fn with_entries<F: Fn( usize ) -> u32>( lengths: &[u8], entry: F ) -> Result<Decoder, HuffmanError>
+  {
+    let count = count_lengths( lengths )?;
+    let codes = canonical_codes( lengths )?;
+    let maxbits = *lengths.iter().max().unwrap_or( &0 ) as usize;
+    let peekbits = std::cmp::min( maxbits, PEEK );
+
+    let mut left : i64 = 1;
+    let mut first = 0;
+    let mut offset = vec![ 0; maxbits + 2 ]; // Index in sorted of the first code of each length.
+    for len in 1..=maxbits
+    {
+      left = ( left << 1 ) - count[ len ] as i64;
+      offset[ len + 1 ] = offset[ len ] + count[ len ];
+      if len <= peekbits { first = ( first + count[ len ] ) << 1; }
+    }
+    let long = ( first, offset[ peekbits + 1 ] );
+
+    let mut lookup = vec![ 0; 1 << peekbits ];
+    let mut sorted = vec![ 0; offset[ maxbits + 1 ] ];
+    for ( sym, len ) in lengths.iter().enumerate()
+    {
+      let len = *len as usize;
+      if len == 0 { continue; }
+      let e = entry( sym );
+      debug_assert!( e < 1 << 24 );
+      sorted[ offset[ len ] ] = e;
+      offset[ len ] += 1;
+      if len <= peekbits
+      {
+        let e = e << 8 | len as u32;
+        let mut i = codes[ sym ] as usize;
+        while i < lookup.len()
+        {
+          lookup[ i ] = e;
+          i += 1 << len;
+        }
+      }
+    }
+    Ok( Decoder { maxbits, peekbits, lookup, count, sorted, long, complete: left == 0 } )
+  }
+
+  /// Length of the longest code ( 0 if no symbol is used ).
+  pub fn max_bits( &self ) -> usize
+  {
+    self.maxbits
+  }
+
+  /// Whether every code is assigned to a symbol ( otherwise some input is not valid ).
+  pub fn complete( &self ) -> bool
+  {
+    self.complete
+  }
+
+  /// Read code lengths for nsym symbols ( see write_lengths ), returning the Decoder for the code.
+  pub fn read<R: BufRead>( input: &mut InputBitStream<R>, nsym: usize ) -> Result<Decoder, HuffmanError>
+  {
+    Decoder::new( &read_lengths( input, nsym )? )
+  }
+
+  /// Read a symbol ( or its entry, see with_entries ) from input.
+  pub fn decode<R: BufRead>( &self, input: &mut InputBitStream<R> ) -> Result<usize, HuffmanError>
+  {
+    if self.maxbits == 0 { return Err( HuffmanError::InvalidCode ); }
+    let e = self.decode_entry( input );
+    if input.truncated() { return Err( HuffmanError::Truncated ); }
+    e.map( |e| e as usize ).ok_or( HuffmanError::InvalidCode )
+  }
+
+  /// Read the entry for a symbol from input, or None if the code is not assigned.
+  /// Unlike decode, this does not check whether input is truncated.
+  #[inline]
+  pub fn decode_entry<R: BufRead>( &self, input: &mut InputBitStream<R> ) -> Option<u32>
+  {
+    let e = self.lookup[ input.peek( self.peekbits ) ];
+    if e != 0
+    {
+      input.advance( ( e & 255 ) as usize );
+      Some( e >> 8 )
+    } else {
+      self.decode_long( input )
+    }
+  }
+
+  // Decode a code a bit at a time ( canonical codes of each length are consecutive numbers ).
+  // Codes of up to peekbits are in the lookup table, so decoding starts with the first peekbits bits.
+  #[inline(never)] // Long codes are rare, keeping them out of line lets decode_entry be inlined in a decode loop.
+  fn decode_long<R: BufRead>( &self, input: &mut InputBitStream<R> ) -> Option<u32>
+  {
+    let bits = input.peek( self.maxbits );
+    let mut code = reverse( bits & ( ( 1 << self.peekbits ) - 1 ), self.peekbits ) << 1;
+    let ( mut first, mut index ) = self.long;
+    for len in self.peekbits + 1..=self.maxbits
+    {
+      code |= ( bits >> ( len - 1 ) ) & 1;
+      let count = self.count[ len ];
+      if code < first + count
+      {
+        input.advance( len );
+        return Some( self.sorted[ index + code - first ] );
+      }
+      index += count;
+      first = ( first + count ) << 1;
+      code <<= 1;
+    }
+    None
+  }
+}
@@ -1,317 +1,1300 @@
-/// RFC 1951 inflate ( de-compress ).
+//! RFC 1951 inflate ( de-compress ).
 
+use std::io::{BufRead,BufReader,Read};
+use crate::container::{Container,ZlibHeader,GzipHeader,FTEXT,FHCRC,FEXTRA,FNAME,FCOMMENT};
+use crate::huffman;
+
+/// Panics if data is not valid, see try_inflate.
 pub fn inflate( data: &[u8] ) -> Vec<u8>
 {
-  let mut input = InputBitStream::new( &data );
+  match try_inflate( data )
+  {
+    Ok( output ) => output,
+    Err( e ) => panic!( "{}", e )
+  }
+}
+
+/// Inflate data, returning an error if it is not a valid RFC 1950 stream.
+pub fn try_inflate( data: &[u8] ) -> Result<Vec<u8>, InflateError>
+{
+  try_inflate_with( data, &InflateOptions::new() )
+}
+
+/// Inflate data in the container format specified by opts.
+pub fn try_inflate_with( data: &[u8], opts: &InflateOptions ) -> Result<Vec<u8>, InflateError>
+{
+  Ok( try_inflate_stream( data, opts )?.0 )
+}
+
+/// Inflate the stream at the start of data, returning the output and the number of bytes of data used.
+/// data[ used.. ] is the data following the stream ( if any ).
+pub fn try_inflate_stream( data: &[u8], opts: &InflateOptions ) -> Result<( Vec<u8>, usize ), InflateError>
+{
   let mut output = Vec::with_capacity( 2 * data.len() );
-  let _flags = input.get_bits( 16 );
-  loop
+  let mut d = Decoder::new( data, opts );
+  d.decode( &mut output, usize::MAX )?;
+  output.drain( 0..d.preset );
+  Ok( ( output, d.input.used() ) )
+}
+
+/// Decode data in the container format specified by opts, listing every block header and symbol ( for debugging ).
+/// If the data is not valid, the items decoded before the error are returned with the error.
+pub fn trace( data: &[u8], opts: &InflateOptions ) -> ( Vec<TraceItem>, Option<InflateError> )
+{
+  let mut output = TraceOutput{ out: Vec::new(), items: Vec::new() };
+  let mut d = Decoder::new( data, opts );
+  let error = d.decode( &mut output, usize::MAX ).err();
+  ( output.items, error )
+}
+
+/// Inflate RFC 1950 data into output, returning the number of bytes written.
+/// Fails with OutputOverflow if the decompressed data does not fit.
+pub fn inflate_into( data: &[u8], output: &mut [u8] ) -> Result<usize, InflateError>
+{
+  inflate_into_with( data, output, &InflateOptions::new() )
+}
+
+/// Inflate data in the container format specified by opts into output, returning the number of bytes written.
+pub fn inflate_into_with( data: &[u8], output: &mut [u8], opts: &InflateOptions ) -> Result<usize, InflateError>
+{
+  let n = opts.dictionary.len();
+  let mut out = SliceOutput{ buf: output, len: 0, dict: &opts.dictionary[ n - std::cmp::min( n, WINDOW ).. ], dict_len: 0 };
+  let mut d = Decoder::new( data, opts );
+  d.decode( &mut out, usize::MAX )?;
+  Ok( out.len )
+}
+
+/// Inflate RFC 1950 data, appending at most max bytes to output and returning the number of bytes appended.
+/// On failure output is left unchanged.
+pub fn inflate_append( data: &[u8], output: &mut Vec<u8>, max: usize ) -> Result<usize, InflateError>
+{
+  inflate_append_with( data, output, max, &InflateOptions::new() )
+}
+
+/// Inflate data in the container format specified by opts, appending at most max bytes to output.
+pub fn inflate_append_with( data: &[u8], output: &mut Vec<u8>, max: usize, opts: &InflateOptions ) -> Result<usize, InflateError>
+{
+  let start = output.len();
+  let n = opts.dictionary.len();
+  let mut out = AppendOutput{ out: output, start, max, dict: &opts.dictionary[ n - std::cmp::min( n, WINDOW ).. ], dict_len: 0 };
+  let mut d = Decoder::new( data, opts );
+  match d.decode( &mut out, usize::MAX )
+  {
+    Ok( () ) => Ok( output.len() - start ),
+    Err( e ) => { output.truncate( start ); Err( e ) }
+  }
+}
+
+/// Read the RFC 1950 header at the start of data.
+pub fn zlib_header( data: &[u8] ) -> Result<ZlibHeader, InflateError>
+{
+  read_zlib_header( &mut InputBitStream::new( data ) )
+}
+
+/// Options for inflate.
+/// The limits protect against "decompression bombs" ( small inputs that expand to a very large output ), usize::MAX is no limit.
+#[derive(Clone)]
+pub struct InflateOptions
+{
+  pub container: Container,
+  pub dictionary: Vec<u8>, // Preset dictionary, used if the zlib header has FDICT set ( or for Raw, if not empty ).
+  pub max_output: usize, // Maximum size of decompressed data.
+  pub max_ratio: usize, // Maximum ratio of decompressed size to compressed input size ( checked once output exceeds 64K ).
+  pub max_blocks: usize, // Maximum number of RFC 1951 blocks.
+  pub partial: bool, // Input may end between blocks ( at a compressor flush ), in which case the output so far is returned.
+  pub multi_member: bool, // Decode any gzip members that follow the first one ( as written by cat a.gz b.gz ), Gzip only.
+}
+
+impl InflateOptions
+{
+  pub fn new() -> InflateOptions
   {
-    let last_block = input.get_bit();
-    let block_type = input.get_bits( 2 );
-    match block_type
+    InflateOptions
+    { 
+      container: Container::Zlib, 
+      dictionary: Vec::new(), 
+      max_output: usize::MAX, 
+      max_ratio: usize::MAX, 
+      max_blocks: usize::MAX,
+      partial: false,
+      multi_member: false
+    }
+  }
+}
+
+/// A block header or symbol decoded by trace. position is the offset in bits of the block header from the start of the input.
+#[derive(Debug,Clone,PartialEq)]
+pub enum TraceItem
+{
+  Stored{ position: usize, last: bool, len: usize }, // Block of len uncompressed bytes.
+  Fixed{ position: usize, last: bool }, // Block encoded with the fixed Huffman codes.
+  Dynamic // Block encoded with Huffman codes defined by the code lengths in the header.
+  { 
+    position: usize, 
+    last: bool, 
+    lit_bits: Vec<u8>, // Code lengths of the literal/length symbols ( HLIT + 257 ).
+    dist_bits: Vec<u8>, // Code lengths of the distance symbols ( HDIST + 1 ).
+    len_bits: Vec<u8> // Code lengths of the code length symbols, in the order sent ( HCLEN + 4 ).
+  },
+  Literal( u8 ),
+  Match{ length: usize, distance: usize },
+  EndOfBlock,
+}
+
+impl std::fmt::Display for TraceItem
+{
+  fn fmt( &self, f: &mut std::fmt::Formatter ) -> std::fmt::Result
+  {
+    let last = | last: &bool | if *last { " ( last )" } else { "" };
+    match self
     {
-      2 => dyn_block( &mut input, &mut output ),
-      1 => fixed_block( &mut input, &mut output ),
-      0 => copy_block( &mut input, &mut output ),
-      _ => ()
+      TraceItem::Stored{ position, last: l, len } => write!( f, "stored block at bit {}{}, {} bytes", position, last( l ), len ),
+      TraceItem::Fixed{ position, last: l } => write!( f, "fixed block at bit {}{}", position, last( l ) ),
+      TraceItem::Dynamic{ position, last: l, lit_bits, dist_bits, len_bits } => 
+        write!( f, "dynamic block at bit {}{}, {} literal/length codes, {} distance codes, {} code length codes", 
+          position, last( l ), lit_bits.len(), dist_bits.len(), len_bits.len() ),
+      TraceItem::Literal( b ) if b.is_ascii_graphic() || *b == b' ' => write!( f, "literal '{}'", *b as char ),
+      TraceItem::Literal( b ) => write!( f, "literal 0x{:02x}", b ),
+      TraceItem::Match{ length, distance } => write!( f, "match length {} distance {}", length, distance ),
+      TraceItem::EndOfBlock => f.write_str( "end of block" ),
     }
-    if last_block != 0 { break; }
-  }  
-  // Check the checksum.
-  input.pad( 8 );
-  let check_sum = input.get_bits(32) as u32;
-  if crate::compress::adler32( &output ) != check_sum { panic!( "Bad checksum" ) }
-  output
+  }
 }
 
-/// Decode block encoded with dynamic Huffman codes.
-fn dyn_block( input: &mut InputBitStream, output: &mut Vec<u8> )
+/// Limits that can be exceeded ( see InflateOptions ).
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Limit { Output, Ratio, Blocks }
+
+/// Reasons inflate can fail.
+#[derive(Debug)]
+pub enum InflateError
 {
-  let n_lit = 257 + input.get_bits( 5 );
-  let n_dist = 1 + input.get_bits( 5 );
-  let n_len = 4 + input.get_bits( 4 );
+  Truncated, // Input ended before the end of the stream.
+  InvalidHeader, // Container header is not valid.
+  InvalidBlockType, // Block type 3 ( reserved ).
+  InvalidHuffman, // Code lengths are over-subscribed or incomplete, or an undefined code was used.
+  BadDistance, // LZ77 copy distance is greater than the output length.
+  BadStoredLength, // Length of uncompressed block does not match the complement.
+  ChecksumMismatch, // Checksum ( or length ) in trailer does not match output.
+  WrongDictionary( u32 ), // Stream requires a preset dictionary with the given Adler-32 ( DICTID ), which was not supplied.
+  OutputOverflow, // Decompressed data does not fit in the output buffer.
+  LimitExceeded( Limit ), // A limit set in InflateOptions was exceeded.
+  Io( std::io::Error ), // Error reading input.
+}
 
-  // The lengths of the main Huffman codes (lit,dist) are themselves decoded by LenDecoder.
-  let mut len = LenDecoder::new( n_len, input );
-  let lit : BitDecoder = len.get_decoder( n_lit, input );
-  let dist : BitDecoder = len.get_decoder( n_dist, input ); 
+impl std::fmt::Display for InflateError
+{
+  fn fmt( &self, f: &mut std::fmt::Formatter ) -> std::fmt::Result
+  {
+    f.write_str( match self
+    {
+      InflateError::Truncated => "Unexpected end of input",
+      InflateError::InvalidHeader => "Invalid header",
+      InflateError::InvalidBlockType => "Invalid block type",
+      InflateError::InvalidHuffman => "Invalid Huffman code",
+      InflateError::BadDistance => "Bad distance",
+      InflateError::BadStoredLength => "Bad stored block length",
+      InflateError::ChecksumMismatch => "Bad checksum",
+      InflateError::OutputOverflow => "Output buffer too small",
+      InflateError::LimitExceeded( Limit::Output ) => "Output size limit exceeded",
+      InflateError::LimitExceeded( Limit::Ratio ) => "Compression ratio limit exceeded",
+      InflateError::LimitExceeded( Limit::Blocks ) => "Block count limit exceeded",
+      InflateError::WrongDictionary( id ) => return write!( f, "Preset dictionary required, DICTID={:08x}", id ),
+      InflateError::Io( e ) => return e.fmt( f ),
+    } )
+  }
+}
 
-  loop
+impl std::error::Error for InflateError {}
+
+impl InflateError
+{
+  /// A copy of the error, so it can be returned again ( an io error is copied as its kind and message ).
+  fn again( &self ) -> InflateError
   {
-    let x : usize = lit.decode( input );
-    match x
+    match self
     {
-      0..=255 => output.push( x as u8 ),
-      256 => break,
-      _ => // LZ77 match code - replicate earlier output.
-      {
-        let mc = x - 257;
-        let length = MATCH_OFF[ mc ] as usize + input.get_bits( MATCH_EXTRA[ mc ] as usize );
-        let dc = dist.decode( input );
-        let distance = DIST_OFF[ dc ] as usize + input.get_bits( DIST_EXTRA[ dc ] as usize );
-        copy( output, distance, length ); 
-      }
+      InflateError::Truncated => InflateError::Truncated,
+      InflateError::InvalidHeader => InflateError::InvalidHeader,
+      InflateError::InvalidBlockType => InflateError::InvalidBlockType,
+      InflateError::InvalidHuffman => InflateError::InvalidHuffman,
+      InflateError::BadDistance => InflateError::BadDistance,
+      InflateError::BadStoredLength => InflateError::BadStoredLength,
+      InflateError::ChecksumMismatch => InflateError::ChecksumMismatch,
+      InflateError::WrongDictionary( id ) => InflateError::WrongDictionary( *id ),
+      InflateError::OutputOverflow => InflateError::OutputOverflow,
+      InflateError::LimitExceeded( l ) => InflateError::LimitExceeded( *l ),
+      InflateError::Io( e ) => InflateError::Io( std::io::Error::new( e.kind(), e.to_string() ) ),
     }
   }
-} // end do_dyn
+}
 
-/// Copy length bytes from output ( at specified distance ) to output.
-fn copy( output: &mut Vec<u8>, distance: usize, mut length: usize )
+impl From<InflateError> for std::io::Error
 {
-  let mut i = output.len() - distance;
-  while length > 0
+  fn from( e: InflateError ) -> std::io::Error
   {
-    output.push( output[ i ] );
-    i += 1;
-    length -= 1;
+    let kind = match e
+    {
+      InflateError::Io( e ) => return e,
+      InflateError::Truncated => std::io::ErrorKind::UnexpectedEof,
+      _ => std::io::ErrorKind::InvalidData
+    };
+    std::io::Error::new( kind, e )
   }
 }
 
-/// Decode length-limited Huffman codes.
-// For speed, a lookup table is used to compute symbols from the variable length codes ( rather than reading single bits ).
-// To keep the lookup table small, codes longer than PEEK bits are looked up in two operations.
-struct BitDecoder
+/// Streaming inflate, compressed input is read from inp as decompressed bytes are requested.
+/// Only the last 32K of output is retained ( for LZ77 copies ), so large streams can be decoded without holding them in memory.
+///
+/// Each read returns once a block has been decoded, so output before a compressor flush is available without waiting for
+/// the input that follows. If partial is set in the options, read returns 0 when the input ends between blocks, and
+/// decoding continues if more input becomes available.
+///
+/// Once read returns an error, every later read returns the same error. The exception is an error reading the input
+/// ( such as WouldBlock from a non-blocking reader ) which occurs before any of the input bits are needed, which is 
+/// usually the case, after which read can be called again to continue decoding.
+///
+/// Example:
+/// let mut inf = inflate::Inflater::new( std::fs::File::open( "data.z" )? );
+/// std::io::copy( &mut inf, &mut std::io::stdout() )?;
+pub struct Inflater<R: Read>
 {
-  nsym: usize, // The number of symbols.
-  bits: Vec<u8>, // The length in bits of the code that represents each symbol.
-  maxbits: usize, // The length in bits of the longest code.
-  peekbits: usize, // The bit length for the first lookup ( not greater than PEEK ).
-  lookup: Vec<usize> // The table used to look up a symbol from a code.
+  dec: Decoder<BufReader<R>>,
+  window: Vec<u8>, // Recent output, including output not yet read.
+  read: usize, // Number of bytes of window that have been read.
 }
 
-/// Maximum number of bits for first lookup.
-const PEEK : usize = 8; 
+/// Size of history needed for LZ77 copies.
+const WINDOW : usize = 0x8000;
 
-impl BitDecoder
+/// Amount of output decoded by each Inflater step.
+const STEP : usize = 0x8000;
+
+impl<R: Read> Inflater<R>
 {
-  fn new( nsym: usize ) -> BitDecoder
+  pub fn new( inp: R ) -> Inflater<R>
   {
-    BitDecoder 
-    { 
-      nsym,
-      bits: vec![0; nsym],
-      maxbits: 0,
-      peekbits: 0,
-      lookup: Vec::new()
-    }
+    Inflater::with_options( inp, &InflateOptions::new() )
   }
 
-  /// The main function : get a decoded symbol from the input bit stream.
-  /// Codes of up to PEEK bits are looked up in a single operation.
-  /// Codes of more than PEEK bits are looked up in two steps.
-  fn decode( &self, input: &mut InputBitStream ) -> usize
+  pub fn with_options( inp: R, opts: &InflateOptions ) -> Inflater<R>
   {
-    let mut sym = self.lookup[ input.peek( self.peekbits ) ];
-    if sym >= self.nsym
+    Inflater
     {
-      sym = self.lookup[ sym - self.nsym + ( input.peek( self.maxbits ) >> self.peekbits ) ];
-    }  
-    input.advance( self.bits[ sym ] as usize );
-    sym
+      dec: Decoder::new( BufReader::new( inp ), opts ),
+      window: Vec::new(),
+      read: 0,
+    }
   }
 
-  fn init_lookup( &mut self )
+  /// The zlib header, once it has been read ( container must be Zlib ).
+  pub fn zlib_header( &self ) -> Option<&ZlibHeader>
   {
-    let mut max_bits : usize = 0; 
-    for bp in &self.bits 
-    { 
-      let bits = *bp as usize;
-      if bits > max_bits { max_bits = bits; } 
+    self.dec.zlib.as_ref()
+  }
+
+  /// The gzip header, once it has been read ( container must be Gzip ).
+  /// For multiple members, this is the header of the member being decoded.
+  pub fn gzip_header( &self ) -> Option<&GzipHeader>
+  {
+    self.dec.gzip.as_ref()
+  }
+
+  /// Number of bytes of input used so far.
+  pub fn consumed( &self ) -> usize
+  {
+    self.dec.input.used()
+  }
+
+  /// The input following the end of the stream ( once read has returned 0 ), including any input read ahead.
+  pub fn into_remainder( self ) -> std::io::Chain<std::io::Cursor<Vec<u8>>, BufReader<R>>
+  {
+    let ( ahead, inp ) = self.dec.input.into_remainder();
+    std::io::Read::chain( std::io::Cursor::new( ahead ), inp )
+  }
+
+  /// Decode more output, unless the end of stream has been reached.
+  fn step( &mut self ) -> std::io::Result<()>
+  {
+    // Discard output which has been read and is no longer needed as history.
+    let discard = std::cmp::min( self.read, self.window.len().saturating_sub( WINDOW ) );
+    if discard >= WINDOW
+    {
+      self.window.drain( 0..discard );
+      self.read -= discard;
     }
+    let limit = self.window.len() + STEP;
+    self.dec.stop_at_block = true;
+    let result = self.dec.decode( &mut self.window, limit );
 
-    self.maxbits = max_bits;
-    self.peekbits = if max_bits > PEEK { PEEK } else { max_bits };
-    self.lookup.resize( 1 << self.peekbits, 0 );
+    // Skip the preset dictionary, and after an error that cannot be recovered from, any output not yet read.
+    self.read += self.dec.preset;
+    self.dec.preset = 0;
+    if self.dec.failed() { self.read = self.window.len(); }
+    result?;
+    Ok(())
+  }
+}
 
-    // Code below is from rfc1951 page 7.
+impl<R: Read> Read for Inflater<R>
+{
+  fn read( &mut self, buf: &mut [u8] ) -> std::io::Result<usize>
+  {
+    while self.read == self.window.len() && !self.dec.done()
+    {
+      self.step()?;
+      if self.dec.waiting { break; }
+    }
+    let n = std::cmp::min( buf.len(), self.window.len() - self.read );
+    buf[..n].copy_from_slice( &self.window[ self.read .. self.read + n ] );
+    self.read += n;
+    Ok( n )
+  }
+}
 
-    // bl_count is the number of codes of length N, N >= 1.
-    let mut bl_count : Vec<usize> = vec![ 0; max_bits + 1 ];
+/// Inflate state, allows decoding to be suspended when enough output has been produced.
+struct Decoder<R: BufRead>
+{
+  input: InputBitStream<R>,
+  opts: InflateOptions,
+  state: State,
+  last_block: bool,
+  check: u32, // Checksum of output so far ( for the current member ).
+  len: usize, // Length of output so far.
+  member_len: usize, // Length of output before the current member.
+  preset: usize, // Length of preset dictionary placed at start of output ( not part of the decompressed data ).
+  blocks: usize, // Number of blocks started.
+  stop_at_block: bool, // Return from decode at the end of a block if there is output ( so input after the block is not needed ).
+  waiting: bool, // Decoding stopped between blocks at the end of input ( opts.partial is set ).
+  zlib: Option<ZlibHeader>,
+  gzip: Option<GzipHeader>,
+}
 
-    for sym in 0..self.nsym { bl_count[ self.bits[ sym ] as usize ] += 1; }
+enum State { Header, BlockHeader, Stored( usize ), Fixed, Dynamic( Box<( huffman::Decoder, huffman::Decoder )> ), Trailer, Done, Failed( InflateError ) }
 
-    let mut next_code : Vec<usize> = vec![ 0; max_bits + 1 ];
-    let mut code = 0; 
-    bl_count[ 0 ] = 0;
+impl<R: BufRead> Decoder<R>
+{
+  fn new( inp: R, opts: &InflateOptions ) -> Decoder<R>
+  {
+    Decoder
+    {
+      input: InputBitStream::new( inp ),
+      opts: opts.clone(),
+      state: State::Header,
+      last_block: false,
+      check: opts.container.check_init(),
+      len: 0,
+      member_len: 0,
+      preset: 0,
+      blocks: 0,
+      stop_at_block: false,
+      waiting: false,
+      zlib: None,
+      gzip: None,
+    }
+  }
 
-    for i in 0..max_bits
+  /// Decode until output length reaches limit or the end of stream. After an error, later calls return the same error,
+  /// except for an error reading input which occurs before the input bits are needed ( decoding can then continue ).
+  fn decode<O: Output>( &mut self, output: &mut O, limit: usize ) -> Result<(), InflateError>
+  {
+    if let State::Failed( e ) = &self.state { return Err( e.again() ); }
+    let mut start = output.len(); // Start of output not yet included in check.
+    let result = self.decode_to( output, limit, &mut start );
+    self.update_check( output.tail( start ) );
+    if let Err( e ) = &result
     {
-      code = ( code + bl_count[ i ] ) << 1;
-      next_code[ i + 1 ] = code;
+      // After an error reading input the state is where decoding can continue, unless zero bits were used in place of 
+      // the input, in which case it is Done.
+      if !matches!( e, InflateError::Io( _ ) ) || matches!( self.state, State::Done )
+      {
+        self.state = State::Failed( e.again() );
+      }
     }
+    result
+  }
 
-    for sym in 0..self.nsym
+  fn decode_to<O: Output>( &mut self, output: &mut O, limit: usize, start: &mut usize ) -> Result<(), InflateError>
+  {
+    let first = *start;
+    self.waiting = false;
+    while output.len() < limit
     {
-      let length = self.bits[ sym ] as usize;
-      if length != 0
+      let input = &mut self.input;
+      let bounds = Bounds
+      { 
+        limit, 
+        max: start.saturating_add( self.opts.max_output.saturating_sub( self.len ) ), 
+        start: *start, 
+        done: self.len, 
+        ratio: self.opts.max_ratio 
+      };
+      self.state = match std::mem::replace( &mut self.state, State::Done )
       {
-        self.setup_code( sym, length, next_code[ length ] );
-        next_code[ length ] += 1;
+        State::Header =>
+        {
+          let dictionary = &self.opts.dictionary;
+          let use_dictionary = match self.opts.container
+          {
+            Container::Raw => !dictionary.is_empty(),
+            Container::Zlib => 
+            {
+              let h = read_zlib_header( input )?;
+              let result = h.dictid.is_some();
+              if let Some( id ) = h.dictid
+              {
+                if dictionary.is_empty() || crate::checksum::adler32( dictionary ) != id 
+                { 
+                  return Err( InflateError::WrongDictionary( id ) ); 
+                }
+              }
+              self.zlib = Some( h );
+              result
+            }
+            Container::Gzip => { self.gzip = Some( read_gzip_header( input )? ); false }
+          };
+          if use_dictionary
+          {
+            // Only the last 32K of the dictionary can be referenced.
+            let n = dictionary.len();
+            output.preset( &dictionary[ n - std::cmp::min( n, WINDOW ).. ] );
+            self.preset = output.len() - *start;
+            *start = output.len();
+          }
+          State::BlockHeader
+        }
+        State::BlockHeader =>
+        {
+          self.waiting = self.opts.partial && input.exhausted();
+          if self.waiting || self.stop_at_block && output.len() > first
+          {
+            self.state = State::BlockHeader;
+            break;
+          }
+          if let Err( e ) = input.fill( 3 )
+          {
+            self.state = State::BlockHeader;
+            return Err( InflateError::Io( e ) );
+          }
+          self.blocks += 1;
+          if self.blocks > self.opts.max_blocks { return Err( InflateError::LimitExceeded( Limit::Blocks ) ); }
+          let position = input.position();
+          let last = input.get_bit() != 0;
+          self.last_block = last;
+          match input.get_bits( 2 )
+          {
+            2 => 
+            { 
+              let ( lit, dist, item ) = dyn_header( input, position, last )?; 
+              output.trace( || item );
+              State::Dynamic( Box::new( ( lit, dist ) ) ) 
+            }
+            1 => 
+            { 
+              output.trace( || TraceItem::Fixed{ position, last } ); 
+              State::Fixed 
+            }
+            0 => 
+            { 
+              let len = copy_block( input )?;
+              output.trace( || TraceItem::Stored{ position, last, len } ); 
+              State::Stored( len ) 
+            }
+            _ => return Err( InflateError::InvalidBlockType )
+          }
+        }
+        State::Stored( mut n ) =>
+        {
+          let result = copy_bytes( input, output, &mut n, &bounds );
+          self.state = State::Stored( n );
+          result?;
+          if n > 0 { State::Stored( n ) } else { self.end_block() }
+        }
+        State::Fixed =>
+        {
+          let ( lit, dist ) = fixed_decoders();
+          self.state = State::Fixed;
+          if huff_block( input, output, lit, dist, &bounds )? { self.end_block() } else { State::Fixed }
+        }
+        State::Dynamic( d ) =>
+        {
+          match huff_block( input, output, &d.0, &d.1, &bounds )
+          {
+            Ok( true ) => self.end_block(),
+            Ok( false ) => State::Dynamic( d ),
+            Err( e ) => { self.state = State::Dynamic( d ); return Err( e ); }
+          }
+        }
+        State::Trailer =>
+        {
+          self.update_check( output.tail( *start ) );
+          *start = output.len();
+          self.read_trailer()?;
+          if self.opts.multi_member && self.opts.container == Container::Gzip && self.input.gzip_follows()
+          {
+            self.check = self.opts.container.check_init();
+            self.member_len = self.len;
+            State::Header
+          } else {
+            State::Done
+          }
+        }
+        State::Done | State::Failed( _ ) => break
+      };
+      if let Some( e ) = self.input.error.take() 
+      { 
+        // Zero bits were used in place of the input, so decoding cannot continue.
+        self.state = State::Done;
+        return Err( InflateError::Io( e ) ); 
       }
+      if self.input.truncated() { return Err( InflateError::Truncated ); }
     }
+    Ok(())
+  }
+
+  /// Has the end of stream been reached?
+  fn done( &self ) -> bool
+  {
+    matches!( self.state, State::Done )
+  }
+
+  /// Has decoding failed with an error that is returned by every later call?
+  fn failed( &self ) -> bool
+  {
+    matches!( self.state, State::Failed( _ ) )
+  }
+
+  /// State following the end of a block.
+  fn end_block( &self ) -> State
+  {
+    if self.last_block { State::Trailer } else { State::BlockHeader }
+  }
+
+  fn update_check( &mut self, output: &[u8] )
+  {
+    self.check = self.opts.container.check_update( self.check, output );
+    self.len += output.len();
   }
 
-  fn setup_code( &mut self, sym: usize, len: usize, mut code: usize )
+  /// Read the container trailer and verify the checksum.
+  fn read_trailer( &mut self ) -> Result<(), InflateError>
   {
-    if len <= self.peekbits
+    let input = &mut self.input;
+    input.pad( 8 );
+    let ok = match self.opts.container
     {
-      let diff = self.peekbits - len;
-      code <<= diff;
-      for i in code..code + (1 << diff)
+      Container::Raw => true,
+      Container::Zlib => input.get_bits( 32 ) as u32 == self.check.swap_bytes(), // Most significant byte first.
+      Container::Gzip => 
       {
-        // lookup index is reversed to match InputBitStream::peek
-        self.lookup[ reverse( i, self.peekbits ) ] = sym;
+        let crc = input.get_bits( 32 ) as u32;
+        let len = input.get_bits( 32 ) as u32;
+        crc == self.check && len == ( self.len - self.member_len ) as u32
       }
-    } else { // Secondary lookup required      
-      let peekbits2 = self.maxbits - self.peekbits;
+    };
+    if input.truncated() { return Err( InflateError::Truncated ); }
+    if !ok { return Err( InflateError::ChecksumMismatch ); }
+    Ok(())
+  }
+}
+
+/// Read RFC 1950 header.
+fn read_zlib_header<R: BufRead>( input: &mut InputBitStream<R> ) -> Result<ZlibHeader, InflateError>
+{
+  let cmf = input.get_bits( 8 );
+  let flg = input.get_bits( 8 );
+  let cm = cmf & 15; // Compression method, 8 = deflate.
+  let cinfo = cmf >> 4; // Base-2 logarithm of the window size, minus 8.
+  let fdict = flg & 32 != 0;
+
+  let dictid = if fdict { Some( ( input.get_bits( 32 ) as u32 ).swap_bytes() ) } else { None }; // Most significant byte first.
+  if input.truncated() { return Err( InflateError::Truncated ); }
+  if cm != 8 || cinfo > 7 || !( cmf << 8 | flg ).is_multiple_of( 31 ) { return Err( InflateError::InvalidHeader ); }
+
+  Ok( ZlibHeader{ window_size: 1 << ( cinfo + 8 ), level: ( flg >> 6 ) as u8, dictid } )
+}
+
+/// Read RFC 1952 header.
+fn read_gzip_header<R: BufRead>( input: &mut InputBitStream<R> ) -> Result<GzipHeader, InflateError>
+{
+  let mut h = Vec::new(); // Header bytes, for FHCRC.
 
-      // Split code into peekbits portion ( key ) and remainder ( code).
-      let diff1 = len - self.peekbits;
-      let key = reverse( code >> diff1, self.peekbits );
-      code &= ( 1 << diff1 ) - 1;
+  let fixed = get_bytes( input, 10, &mut h );
+  if input.truncated() { return Err( InflateError::Truncated ); }
+  if fixed[ 0 ] != 0x1f || fixed[ 1 ] != 0x8b || fixed[ 2 ] != 8 { return Err( InflateError::InvalidHeader ); }
+  let flags = fixed[ 3 ];
+  let mut result = GzipHeader::new();
+  result.text = flags & FTEXT != 0;
+  result.mtime = u32::from_le_bytes( [ fixed[ 4 ], fixed[ 5 ], fixed[ 6 ], fixed[ 7 ] ] );
+  result.xfl = fixed[ 8 ];
+  result.os = fixed[ 9 ];
 
-      // Get the base for the secondary lookup.
-      let mut base = self.lookup[ key ];
-      if base == 0 // Secondary lookup not yet allocated for this key.
+  if flags & FEXTRA != 0
+  {
+    let x = get_bytes( input, 2, &mut h );
+    let n = x[ 0 ] as usize | ( x[ 1 ] as usize ) << 8;
+    result.extra = Some( get_bytes( input, n, &mut h ) );
+  }
+  if flags & FNAME != 0 { result.filename = Some( get_string( input, &mut h )? ); }
+  if flags & FCOMMENT != 0 { result.comment = Some( get_string( input, &mut h )? ); }
+  if flags & FHCRC != 0
+  {
+    result.hcrc = true;
+    let crc = crate::checksum::crc32( &h ) as u16;
+    let x = get_bytes( input, 2, &mut h );
+    if input.truncated() { return Err( InflateError::Truncated ); }
+    if crc != ( x[ 0 ] as u16 | ( x[ 1 ] as u16 ) << 8 ) { return Err( InflateError::InvalidHeader ); }
+  }
+  if input.truncated() { return Err( InflateError::Truncated ); }
+  Ok( result )
+}
+
+/// Get n bytes of input, also appending them to h.
+fn get_bytes<R: BufRead>( input: &mut InputBitStream<R>, n: usize, h: &mut Vec<u8> ) -> Vec<u8>
+{
+  let start = h.len();
+  for _i in 0..n { h.push( input.get_bits( 8 ) as u8 ); }
+  h[ start.. ].to_vec()
+}
+
+/// Get zero terminated string, also appending the bytes read to h.
+fn get_string<R: BufRead>( input: &mut InputBitStream<R>, h: &mut Vec<u8> ) -> Result<Vec<u8>, InflateError>
+{
+  let mut result = Vec::new();
+  loop
+  {
+    let b = input.get_bits( 8 ) as u8;
+    h.push( b );
+    if input.truncated() { return Err( InflateError::Truncated ); }
+    if b == 0 { return Ok( result ); }
+    result.push( b );
+  }
+}
+
+/// Read the code lengths for a block encoded with dynamic Huffman codes, returning decoders for literal/length and distance codes,
+/// and the trace item for the block.
+fn dyn_header<R: BufRead>( input: &mut InputBitStream<R>, position: usize, last: bool ) 
+  -> Result<( huffman::Decoder, huffman::Decoder, TraceItem ), InflateError>
+{
+  let n_lit = 257 + input.get_bits( 5 );
+  let n_dist = 1 + input.get_bits( 5 );
+  let n_len = 4 + input.get_bits( 4 );
+
+  // The lengths of the main Huffman codes (lit,dist) are themselves decoded by LenDecoder.
+  let mut len = LenDecoder::new( n_len, input )?;
+  let lit_bits = len.get_lengths( n_lit, input )?;
+  let lit = decoder( &lit_bits, Alphabet::LitLen )?;
+  let dist_bits = len.get_lengths( n_dist, input )?; 
+  let dist = decoder( &dist_bits, Alphabet::Dist )?;
+  let len_bits = CLEN_ALPHABET[ ..n_len ].iter().map( |i| len.bits[ *i as usize ] ).collect();
+  Ok( ( lit, dist, TraceItem::Dynamic{ position, last, lit_bits, dist_bits, len_bits } ) )
+}
+
+/// Decoders for blocks encoded with fixed Huffman codes ( RFC 1951 page 12 ), built when first used.
+fn fixed_decoders() -> &'static ( huffman::Decoder, huffman::Decoder )
+{
+  static FIXED : std::sync::OnceLock<( huffman::Decoder, huffman::Decoder )> = std::sync::OnceLock::new();
+  FIXED.get_or_init( ||
+  {
+    let lit = decoder( &crate::block::FIXED_LIT_BITS, Alphabet::LitLen ).unwrap();
+    let dist = decoder( &[ 5; 32 ], Alphabet::Dist ).unwrap();
+    ( lit, dist )
+  } )
+}
+
+/// Decode symbols using lit and dist until end of block ( result is true ) or output length reaches limit ( result is false ).
+fn huff_block<R: BufRead, O: Output>( input: &mut InputBitStream<R>, output: &mut O, lit: &huffman::Decoder, dist: &huffman::Decoder, b: &Bounds ) 
+  -> Result<bool, InflateError>
+{
+  while output.len() < b.limit && !input.truncated()
+  {
+    input.fill( MAX_SYMBOL_BITS ).map_err( InflateError::Io )?;
+    let e = decode( lit, input );
+    match e & KIND
+    {
+      LITERAL => 
       {
-        base = self.lookup.len();
-        self.lookup.resize( base + ( 1 << peekbits2 ), 0 );
-        self.lookup[ key ] = self.nsym + base;
-      } else {
-        base -= self.nsym;
+        b.check( output.len() + 1, input.consumed )?;
+        output.push( value( e ) as u8 )?;
+        output.trace( || TraceItem::Literal( value( e ) as u8 ) );
+      }
+      END => 
+      {
+        output.trace( || TraceItem::EndOfBlock );
+        return Ok( true );
       }
+      BASE => // LZ77 match code - replicate earlier output.
+      {
+        let length = value( e ) + input.get_bits( extra( e ) );
+        let e = decode( dist, input );
+        if e & KIND != BASE { return Err( InflateError::InvalidHuffman ); }
+        let distance = value( e ) + input.get_bits( extra( e ) );
+        b.check( output.len() + length, input.consumed )?;
+        output.copy( distance, length )?; 
+        output.trace( || TraceItem::Match{ length, distance } );
+      }
+      _ => return Err( InflateError::InvalidHuffman )
+    }
+  }
+  Ok( false )
+}
 
-      // Set the secondary lookup values.
-      let diff = self.maxbits - len;
-      code <<= diff;
-      for i in code..code + (1<<diff)
-      { 
-        self.lookup[ base + reverse( i, peekbits2 ) ] = sym;
+/// Most bits used by a symbol : a length code and its extra bits, then a distance code and its extra bits.
+const MAX_SYMBOL_BITS : usize = 15 + 5 + 15 + 13;
+
+/// Limits on output for the block decoders, in terms of output positions.
+struct Bounds
+{
+  limit: usize, // Decoding is suspended when output reaches this length.
+  max: usize, // Output may not exceed this length ( max_output ).
+  start: usize, // Output position where the decompressed length was done.
+  done: usize, // Decompressed length at start.
+  ratio: usize, // Maximum ratio of decompressed length to input consumed.
+}
+
+/// Output below this size is not subject to the ratio limit.
+const RATIO_MIN : usize = 0x10000;
+
+impl Bounds
+{
+  /// Check that output may be extended to length len, consumed is the number of input bytes read so far.
+  fn check( &self, len: usize, consumed: usize ) -> Result<(), InflateError>
+  {
+    if len > self.max { return Err( InflateError::LimitExceeded( Limit::Output ) ); }
+    let total = len - self.start + self.done;
+    if total > RATIO_MIN && total > self.ratio.saturating_mul( consumed ) 
+    { 
+      return Err( InflateError::LimitExceeded( Limit::Ratio ) ); 
+    }
+    Ok(())
+  }
+}
+
+/// Destination for decompressed data.
+trait Output
+{
+  /// Length of output so far ( including any preset dictionary ).
+  fn len( &self ) -> usize;
+
+  /// Output from position start ( which is not less than the length of any preset dictionary ).
+  fn tail( &self, start: usize ) -> &[u8];
+
+  fn push( &mut self, b: u8 ) -> Result<(), InflateError>;
+
+  fn extend( &mut self, data: &[u8] ) -> Result<(), InflateError>;
+
+  /// Copy length bytes from output ( at specified distance ) to output.
+  fn copy( &mut self, distance: usize, length: usize ) -> Result<(), InflateError>;
+
+  /// Place a preset dictionary before the output, so copies can refer to it.
+  fn preset( &mut self, dictionary: &[u8] );
+
+  /// Record a block header or symbol ( only TraceOutput does this, so the item is only made when needed ).
+  fn trace<F: FnOnce() -> TraceItem>( &mut self, _item: F ) {}
+}
+
+impl Output for Vec<u8>
+{
+  fn len( &self ) -> usize { self.len() }
+
+  fn tail( &self, start: usize ) -> &[u8] { &self[ start.. ] }
+
+  fn push( &mut self, b: u8 ) -> Result<(), InflateError>
+  {
+    self.push( b );
+    Ok(())
+  }
+
+  fn extend( &mut self, data: &[u8] ) -> Result<(), InflateError>
+  {
+    self.extend_from_slice( data );
+    Ok(())
+  }
+
+  /// If the source overlaps the bytes being copied, the copied section doubles in length each step.
+  fn copy( &mut self, distance: usize, length: usize ) -> Result<(), InflateError>
+  {
+    if distance > self.len() { return Err( InflateError::BadDistance ); }
+    let start = self.len() - distance;
+    if distance == 1
+    {
+      let b = self[ start ];
+      self.resize( self.len() + length, b );
+      return Ok(());
+    }
+    self.reserve( length );
+    let mut left = length;
+    while left > 0
+    {
+      let n = std::cmp::min( left, self.len() - start );
+      self.extend_from_within( start..start + n );
+      left -= n;
+    }
+    Ok(())
+  }
+
+  fn preset( &mut self, dictionary: &[u8] ) { self.extend_from_slice( dictionary ); }
+}
+
+/// Output that also records the block headers and symbols decoded, see trace.
+struct TraceOutput
+{
+  out: Vec<u8>,
+  items: Vec<TraceItem>,
+}
+
+impl Output for TraceOutput
+{
+  fn len( &self ) -> usize { self.out.len() }
+
+  fn tail( &self, start: usize ) -> &[u8] { &self.out[ start.. ] }
+
+  fn push( &mut self, b: u8 ) -> Result<(), InflateError> { Output::push( &mut self.out, b ) }
+
+  fn extend( &mut self, data: &[u8] ) -> Result<(), InflateError> { Output::extend( &mut self.out, data ) }
+
+  fn copy( &mut self, distance: usize, length: usize ) -> Result<(), InflateError> { self.out.copy( distance, length ) }
+
+  fn preset( &mut self, dictionary: &[u8] ) { self.out.preset( dictionary ); }
+
+  fn trace<F: FnOnce() -> TraceItem>( &mut self, item: F ) { self.items.push( item() ); }
+}
+
+/// Output to a fixed size buffer. A preset dictionary is not stored in the buffer, but is treated as preceding it.
+struct SliceOutput<'a>
+{
+  buf: &'a mut [u8],
+  len: usize, // Number of bytes of buf written.
+  dict: &'a [u8], // Preset dictionary that may be used.
+  dict_len: usize, // Length of preset dictionary in use ( 0 or dict.len() ).
+}
+
+impl <'a> Output for SliceOutput<'a>
+{
+  fn len( &self ) -> usize { self.dict_len + self.len }
+
+  fn tail( &self, start: usize ) -> &[u8] { &self.buf[ start - self.dict_len..self.len ] }
+
+  fn push( &mut self, b: u8 ) -> Result<(), InflateError>
+  {
+    if self.len == self.buf.len() { return Err( InflateError::OutputOverflow ); }
+    self.buf[ self.len ] = b;
+    self.len += 1;
+    Ok(())
+  }
+
+  fn extend( &mut self, data: &[u8] ) -> Result<(), InflateError>
+  {
+    if data.len() > self.buf.len() - self.len { return Err( InflateError::OutputOverflow ); }
+    self.buf[ self.len..self.len + data.len() ].copy_from_slice( data );
+    self.len += data.len();
+    Ok(())
+  }
+
+  fn copy( &mut self, distance: usize, length: usize ) -> Result<(), InflateError>
+  {
+    if distance > self.len() { return Err( InflateError::BadDistance ); }
+    if length > self.buf.len() - self.len { return Err( InflateError::OutputOverflow ); }
+    if distance > self.len // Source starts in the preset dictionary.
+    {
+      let from = self.len() - distance;
+      for i in from..from + length
+      {
+        self.buf[ self.len ] = if i < self.dict_len { self.dict[ i ] } else { self.buf[ i - self.dict_len ] };
+        self.len += 1;
+      }
+      return Ok(());
+    }
+    let start = self.len - distance;
+    let mut left = length;
+    while left > 0
+    {
+      let n = std::cmp::min( left, self.len - start );
+      self.buf.copy_within( start..start + n, self.len );
+      self.len += n;
+      left -= n;
+    }
+    Ok(())
+  }
+
+  fn preset( &mut self, _dictionary: &[u8] ) { self.dict_len = self.dict.len(); }
+}
+
+/// Output appended to a Vec, which grows as output is produced, up to max bytes. A preset dictionary is treated as
+/// preceding the output ( as for SliceOutput ).
+struct AppendOutput<'a>
+{
+  out: &'a mut Vec<u8>,
+  start: usize, // Length of out before output was appended.
+  max: usize, // Maximum number of bytes to append.
+  dict: &'a [u8], // Preset dictionary that may be used.
+  dict_len: usize, // Length of preset dictionary in use ( 0 or dict.len() ).
+}
+
+impl AppendOutput<'_>
+{
+  /// Fail with OutputOverflow unless n more bytes can be appended.
+  fn room( &self, n: usize ) -> Result<(), InflateError>
+  {
+    if n > self.max - ( self.out.len() - self.start ) { Err( InflateError::OutputOverflow ) } else { Ok(()) }
+  }
+}
+
+impl Output for AppendOutput<'_>
+{
+  fn len( &self ) -> usize { self.dict_len + self.out.len() - self.start }
+
+  fn tail( &self, start: usize ) -> &[u8] { &self.out[ self.start + start - self.dict_len.. ] }
+
+  fn push( &mut self, b: u8 ) -> Result<(), InflateError>
+  {
+    self.room( 1 )?;
+    self.out.push( b );
+    Ok(())
+  }
+
+  fn extend( &mut self, data: &[u8] ) -> Result<(), InflateError>
+  {
+    self.room( data.len() )?;
+    self.out.extend_from_slice( data );
+    Ok(())
+  }
+
+  fn copy( &mut self, distance: usize, length: usize ) -> Result<(), InflateError>
+  {
+    if distance > self.len() { return Err( InflateError::BadDistance ); }
+    self.room( length )?;
+    let from = self.len() - distance;
+    if from < self.dict_len // Source starts in the preset dictionary.
+    {
+      for i in from..from + length
+      {
+        let b = if i < self.dict_len { self.dict[ i ] } else { self.out[ self.start + i - self.dict_len ] };
+        self.out.push( b );
       }
-    }    
+      return Ok(());
+    }
+    let start = self.start + from - self.dict_len;
+    self.out.reserve( length );
+    let mut left = length;
+    while left > 0
+    {
+      let n = std::cmp::min( left, self.out.len() - start );
+      self.out.extend_from_within( start..start + n );
+      left -= n;
+    }
+    Ok(())
   }
-} // end impl BitDecoder
 
-/// Decodes an array of lengths, returning a new BitDecoder.  
+  fn preset( &mut self, _dictionary: &[u8] ) { self.dict_len = self.dict.len(); }
+}
+
+/// The symbol alphabets, which determine the decoded entry for each symbol.
+#[derive(Clone,Copy)]
+enum Alphabet { CodeLength, LitLen, Dist }
+
+// A decoded entry combines the kind of symbol, the number of extra bits and a value, so 
+// a single lookup gives everything needed to decode a symbol. An entry of zero is an undefined code.
+const KIND : u32 = 3; // Kind of symbol ( bits 0..1 ).
+const INVALID : u32 = 0; // Undefined code, or symbol that is not valid.
+const LITERAL : u32 = 1; // value is a literal byte ( or a code length ).
+const BASE : u32 = 2; // value is the base of a match length or distance, extra bits follow.
+const END : u32 = 3; // End of block.
+// Number of extra bits is bits 2..5, value is bits 8..23.
+
+fn value( e: u32 ) -> usize { ( e >> 8 ) as usize }
+fn extra( e: u32 ) -> usize { ( ( e >> 2 ) & 15 ) as usize }
+
+/// The decoded entry for a symbol.
+fn entry( alphabet: Alphabet, sym: usize ) -> u32
+{
+  let ( kind, extra, value ) = match alphabet
+  {
+    Alphabet::CodeLength => ( LITERAL, 0, sym ),
+    Alphabet::LitLen => match sym
+    {
+      0..=255 => ( LITERAL, 0, sym ),
+      256 => ( END, 0, 0 ),
+      257..=285 => ( BASE, MATCH_EXTRA[ sym - 257 ], MATCH_OFF[ sym - 257 ] as usize ),
+      _ => ( INVALID, 0, 0 )
+    }
+    Alphabet::Dist => match sym
+    {
+      0..=29 => ( BASE, DIST_EXTRA[ sym ], DIST_OFF[ sym ] as usize ),
+      _ => ( INVALID, 0, 0 )
+    }
+  };
+  kind | ( extra as u32 ) << 2 | ( value as u32 ) << 8
+}
+
+/// Decoder for the given code lengths, checking the code is complete unless it is a single one bit code ( or has no codes ).
+fn decoder( bits: &[u8], alphabet: Alphabet ) -> Result<huffman::Decoder, InflateError>
+{
+  let d = huffman::Decoder::with_entries( bits, |sym| entry( alphabet, sym ) ).map_err( |_| InflateError::InvalidHuffman )?;
+  if !d.complete() && d.max_bits() > 1 { return Err( InflateError::InvalidHuffman ); }
+  Ok( d )
+}
+
+/// Get a decoded entry from the input bit stream ( INVALID if the code is not assigned ).
+fn decode<R: BufRead>( d: &huffman::Decoder, input: &mut InputBitStream<R> ) -> u32
+{
+  d.decode_entry( input ).unwrap_or( INVALID )
+}
+
+/// Decodes an array of lengths.  
 /// There are special codes for repeats, and repeats of zeros, per RFC 1951 page 13.
 struct LenDecoder
 {
   plenc: u8, // previous length code ( which can be repeated )
+  have_plenc: bool, // a length has been decoded, so code 16 has a length to repeat
   rep: usize,   // repeat
-  bd: BitDecoder
+  bits: Vec<u8>, // The code lengths of the code length symbols.
+  bd: huffman::Decoder
 }
 
 impl LenDecoder
 {
-  fn new(  n_len: usize, input: &mut InputBitStream ) -> LenDecoder
+  fn new<R: BufRead>( n_len: usize, input: &mut InputBitStream<R> ) -> Result<LenDecoder, InflateError>
   {
-    let mut result = LenDecoder { plenc: 0, rep:0, bd: BitDecoder::new( 19 ) };
-
     // Read the array of 3-bit code lengths (used to encode the main code lengths ) from input.
+    let mut bits = vec![ 0; 19 ];
     for i in CLEN_ALPHABET.iter().take( n_len )
     { 
-      result.bd.bits[ *i as usize ] = input.get_bits(3) as u8; 
+      bits[ *i as usize ] = input.get_bits(3) as u8; 
     }
-    result.bd.init_lookup();
-    result
+    let bd = decoder( &bits, Alphabet::CodeLength )?;
+    Ok( LenDecoder { plenc: 0, have_plenc: false, rep:0, bits, bd } )
   }
 
-  fn get_decoder( &mut self, nsym: usize, input: &mut InputBitStream ) -> BitDecoder
+  fn get_lengths<R: BufRead>( &mut self, nsym: usize, input: &mut InputBitStream<R> ) -> Result<Vec<u8>, InflateError>
   {
-    let mut result = BitDecoder::new( nsym );
-    let bits = &mut result.bits;
+    let mut bits = vec![ 0; nsym ];
     let mut i = 0;
+    if self.rep > nsym { return Err( InflateError::InvalidHuffman ); }
     while self.rep > 0 { bits[ i ] = self.plenc; i += 1; self.rep -= 1; }
     while i < nsym
     { 
-      let lenc = self.bd.decode( input ) as u8;
+      let e = decode( &self.bd, input );
+      if input.truncated() { return Err( InflateError::Truncated ); }
+      if e & KIND != LITERAL { return Err( InflateError::InvalidHuffman ); }
+      let lenc = value( e ) as u8;
       if lenc < 16 
       {
         bits[ i ] = lenc; 
         i += 1; 
         self.plenc = lenc; 
       } else {
-        if lenc == 16 { self.rep = 3 + input.get_bits(2); }
+        if lenc == 16 
+        { 
+          if !self.have_plenc { return Err( InflateError::InvalidHuffman ); }
+          self.rep = 3 + input.get_bits(2); 
+        }
         else if lenc == 17 { self.rep = 3 + input.get_bits(3); self.plenc=0; }
         else if lenc == 18 { self.rep = 11 + input.get_bits(7); self.plenc=0; } 
         while i < nsym && self.rep > 0 { bits[ i ] = self.plenc; i += 1; self.rep -= 1; }
       }
+      self.have_plenc = true;
     }
-    result.init_lookup();
-    result
+    Ok( bits )
   }
 } // end impl LenDecoder
 
-/// For reading bits from input array of bytes.
-struct InputBitStream<'a>
+/// For reading bits from input, least significant bit first ( RFC 1951 bit order ).
+// If the input ends, zero bits are supplied and overrun is incremented, so truncation can be detected later ( see truncated ).
+// An error reading the input is also recorded and zero bits supplied, unless it is found by fill, before the bits are needed.
+pub struct InputBitStream<R: BufRead>
 {
-  data: &'a [u8], // Input data.
-  pos: usize, // Position in input data.
-  buf: usize, // Bit buffer.
+  inp: R, // Input data.
+  buf: u64, // Bit buffer.
   got: usize, // Number of bits in buffer.
+  overrun: usize, // Number of bytes supplied past the end of input.
+  consumed: usize, // Number of bytes read from input.
+  error: Option<std::io::Error>, // Error from reading inp ( zero bits were supplied in place of the input ).
 }
 
-impl <'a> InputBitStream<'a>
+impl <R: BufRead> InputBitStream<R>
 {
-  fn new( data: &'a [u8] ) -> InputBitStream
+  /// Read bits from inp.
+  pub fn new( inp: R ) -> InputBitStream<R>
   {
-    InputBitStream { data, pos: 0, buf: 1, got: 0 }
+    InputBitStream { inp, buf: 0, got: 0, overrun: 0, consumed: 0, error: None }
   } 
 
-  // Get n bits of input ( but do not advance ).
-  fn peek( &mut self, n: usize ) -> usize
+  // Get next byte of input.
+  fn next_byte( &mut self ) -> u8
+  {
+    loop
+    {
+      match self.inp.fill_buf()
+      {
+        Ok( b ) if !b.is_empty() => 
+        {
+          let result = b[ 0 ];
+          self.inp.consume( 1 );
+          self.consumed += 1;
+          return result;
+        }
+        Err( e ) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+        Err( e ) => { if self.error.is_none() { self.error = Some( e ); } }
+        Ok( _ ) => ()
+      }
+      self.overrun += 1;
+      return 0;
+    }
+  }
+
+  /// Get n bits of input ( but do not advance ), n is at most 32.
+  pub fn peek( &mut self, n: usize ) -> usize
+  {
+    if self.got < n { self.refill( n ); }
+    ( self.buf & ( ( 1 << n ) - 1 ) ) as usize
+  }
+
+  // Fill the bit buffer to at least n bits. If 8 bytes of input are available, the buffer is filled to at least 56 bits 
+  // in one operation. Otherwise input is read a byte at a time, reading no more input than needed.
+  fn refill( &mut self, n: usize )
   {
+    if let Ok( b ) = self.inp.fill_buf()
+    {
+      if b.len() >= 8
+      {
+        let bytes = ( 63 - self.got ) / 8;
+        let w = u64::from_le_bytes( [ b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7] ] );
+        self.buf |= ( w & ( ( 1 << ( bytes * 8 ) ) - 1 ) ) << self.got;
+        self.got += bytes * 8;
+        self.inp.consume( bytes );
+        self.consumed += bytes;
+      }
+    }
     while self.got < n
     {
-      // Not necessary to check index, considering adler32 checksum is 32 bits.
-      self.buf |= ( self.data[ self.pos ] as usize ) << self.got;
-      self.pos += 1;
+      self.buf |= ( self.next_byte() as u64 ) << self.got;
       self.got += 8;
     }
-    self.buf & ( ( 1 << n ) - 1 )
   }
 
-  // Advance n bits.
-  fn advance( &mut self, n:usize )
+  /// Read input until the bit buffer has at least n bits ( n is at most 56 ) or the input ends. An error reading the 
+  /// input is returned before any bits are needed, so decoding can continue if reading is retried.
+  fn fill( &mut self, n: usize ) -> std::io::Result<()>
+  {
+    while self.got < n && self.overrun == 0
+    {
+      let b = match self.inp.fill_buf()
+      {
+        Ok( b ) => b,
+        Err( e ) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+        Err( e ) => return Err( e )
+      };
+      if b.is_empty() { break; }
+      let bytes = std::cmp::min( ( 63 - self.got ) / 8, b.len() );
+      if b.len() >= 8
+      {
+        let w = u64::from_le_bytes( [ b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7] ] );
+        self.buf |= ( w & ( ( 1 << ( bytes * 8 ) ) - 1 ) ) << self.got;
+      } else {
+        for ( i, x ) in b[ ..bytes ].iter().enumerate() { self.buf |= ( *x as u64 ) << ( self.got + 8 * i ); }
+      }
+      self.got += bytes * 8;
+      self.inp.consume( bytes );
+      self.consumed += bytes;
+    }
+    Ok(())
+  }
+
+  /// Advance n bits ( n must not exceed the bits obtained by peek ).
+  pub fn advance( &mut self, n:usize )
   { 
     self.buf >>= n;
     self.got -= n;
   }
 
-  // Get a single bit.
-  fn get_bit( &mut self ) -> usize
+  /// Get a single bit.
+  pub fn get_bit( &mut self ) -> usize
   {
     if self.got == 0 { self.peek( 1 ); }
-    let result = self.buf & 1;
+    let result = ( self.buf & 1 ) as usize;
     self.advance( 1 );
     result
   }
 
-  // Get n bits of input.
-  fn get_bits( &mut self, n: usize ) -> usize
+  /// Get n bits of input, n is at most 32.
+  pub fn get_bits( &mut self, n: usize ) -> usize
   { 
     let result = self.peek( n );
     self.advance( n );
     result
   }
 
-  // Get n bits of input, reversed.
-  fn get_huff( &mut self, mut n: usize ) -> usize 
-  { 
-    let mut result = 0; 
-    while n > 0
-    { 
-      result = ( result << 1 ) + self.get_bit(); 
-      n -= 1;
-    }
+  /// Move to n-bit boundary ( n a power of 2 ).
+  pub fn pad( &mut self, n: usize )
+  {  
+    self.advance( self.got % n );
+  }
+
+  /// Have bits past the end of input been used?
+  pub fn truncated( &self ) -> bool
+  {
+    self.overrun * 8 > self.got
+  }
+
+  /// Number of bits of input used.
+  pub fn position( &self ) -> usize
+  {
+    ( self.consumed + self.overrun ) * 8 - self.got
+  }
+
+  /// Number of bytes of input used, not counting whole bytes read ahead into the bit buffer.
+  pub fn used( &self ) -> usize
+  {
+    self.consumed - ( self.got / 8 ).saturating_sub( self.overrun )
+  }
+
+  // The whole bytes read ahead into the bit buffer, and the input.
+  fn into_remainder( self ) -> ( Vec<u8>, R )
+  {
+    let n = ( self.got / 8 ).saturating_sub( self.overrun );
+    let ahead = ( 0..n ).map( |i| ( self.buf >> ( self.got % 8 + 8 * i ) ) as u8 ).collect();
+    ( ahead, self.inp )
+  }
+
+  // At a byte boundary, does the input continue with a gzip header ( magic bytes 0x1f, 0x8b )?
+  fn gzip_follows( &mut self ) -> bool
+  {
+    let result = self.peek( 16 ) == 0x8b1f;
+    self.discard_overrun();
     result
   }
 
-  // Move to n-bit boundary ( n a power of 2 ).
-  fn pad( &mut self, n: usize )
-  {  
-    self.got -= self.got % n;
+  // Discard the zero bits supplied past the end of input.
+  fn discard_overrun( &mut self )
+  {
+    self.got = self.got.saturating_sub( self.overrun * 8 );
+    self.buf &= ( 1 << self.got ) - 1;
+    self.overrun = 0;
+  }
+
+  // Called between blocks, has the input ended, with the bits left too few for a complete block ( the smallest is 
+  // 10 bits ) or all zero ( the start of the empty stored block written by a sync flush )? 
+  // The zero bits supplied past the end of input are discarded, so decoding can continue if more input becomes available.
+  fn exhausted( &mut self ) -> bool
+  {
+    self.discard_overrun();
+    while self.got < 16
+    {
+      match self.inp.fill_buf()
+      {
+        Ok( b ) if !b.is_empty() =>
+        {
+          self.buf |= ( b[ 0 ] as u64 ) << self.got;
+          self.got += 8;
+          self.inp.consume( 1 );
+          self.consumed += 1;
+        }
+        Ok( _ ) => return self.got < 10 || self.buf == 0,
+        Err( _ ) => return false
+      }
+    }
+    false
   }
 } // end impl InputBitStream
 
@@ -328,48 +1311,53 @@ pub fn reverse( mut x:usize, mut n: usize ) -> usize
   result
 } 
 
-/// Copy uncompressed block to output.
-fn copy_block( input: &mut InputBitStream, output: &mut Vec<u8> )
+/// Read the header of an uncompressed block, returning the number of bytes in the block.
+fn copy_block<R: BufRead>( input: &mut InputBitStream<R> ) -> Result<usize, InflateError>
 {
   input.pad( 8 ); // Move to 8-bit boundary.
-  let mut n = input.get_bits( 16 );
-  let _n1 = input.get_bits( 16 );
-  while n > 0 { output.push( input.data[ input.pos ] ); n -= 1; input.pos += 1; }
+  let n = input.get_bits( 16 );
+  let n1 = input.get_bits( 16 );
+  if input.truncated() { return Err( InflateError::Truncated ); }
+  if n != n1 ^ 0xffff { return Err( InflateError::BadStoredLength ); }
+  Ok( n )
 }
 
-/// Decode block encoded with fixed (pre-defined) Huffman codes.
-fn fixed_block( input: &mut InputBitStream, output: &mut Vec<u8> ) // RFC1951 page 12.
+/// Copy n bytes of uncompressed input to output, stopping if output length reaches the limit. On return n is the number of 
+/// bytes not copied ( including when there is an error reading the input ).
+fn copy_bytes<R: BufRead, O: Output>( input: &mut InputBitStream<R>, output: &mut O, n: &mut usize, b: &Bounds ) 
+  -> Result<(), InflateError>
 {
-  loop
+  let limit = b.limit;
+  b.check( output.len() + std::cmp::min( *n, limit.saturating_sub( output.len() ) ), input.consumed + *n )?;
+  // First any whole bytes remaining in the bit buffer.
+  while *n > 0 && input.got >= 8 && output.len() < limit
+  {
+    output.push( input.get_bits( 8 ) as u8 )?;
+    *n -= 1;
+  }
+  while *n > 0 && output.len() < limit
   {
-    // 0 to 23 ( 7 bits ) => 256 - 279; 48 - 191 ( 8 bits ) => 0 - 143; 
-    // 192 - 199 ( 8 bits ) => 280 - 287; 400..511 ( 9 bits ) => 144 - 255
-    let mut x = input.get_huff( 7 ); // Could be optimised. 
-    if x <= 23 
+    let avail = match input.inp.fill_buf() 
     { 
-      x += 256; 
-    } else {
-      x = ( x << 1 ) + input.get_bit();
-      if x <= 191 { x -= 48; }
-      else if x <= 199 { x += 88; }
-      else { x = ( x << 1 ) + input.get_bit() - 256; }
-    }
-
-    match x
+      Ok( b ) => b, 
+      Err( e ) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+      Err( e ) => return Err( InflateError::Io( e ) )
+    };
+    if avail.is_empty()
     {
-      0..=255 => { output.push( x as u8 ); }
-      256 => { break; } 
-      _ => // 257 <= x && x <= 285 
-      { 
-        x -= 257;
-        let length = MATCH_OFF[x] as usize + input.get_bits( MATCH_EXTRA[ x ] as usize );
-        let dcode = input.get_huff( 5 );
-        let distance = DIST_OFF[dcode] as usize + input.get_bits( DIST_EXTRA[dcode] as usize );
-        copy( output, distance, length );
-      }
+      // End of input, let next_byte record it.
+      output.push( input.get_bits( 8 ) as u8 )?;
+      *n -= 1;
+      continue;
     }
+    let take = std::cmp::min( std::cmp::min( *n, avail.len() ), limit - output.len() );
+    output.extend( &avail[..take] )?;
+    input.inp.consume( take );
+    input.consumed += take;
+    *n -= take;
   }
-} // end fixed_block
+  Ok(())
+}
 
 // RFC 1951 constants.
 
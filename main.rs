@@ -1,109 +1,499 @@
-﻿// use rand::Rng;
-use std::time::Instant;
+﻿use std::io::{BufRead,BufReader,IsTerminal,Read,Write};
+use std::fs::File;
 
+#[allow(dead_code)] // Library API, not all of it is used by the command line tool.
 mod compress;
 mod bit;
-mod col;
+#[allow(dead_code)]
+mod huffman;
 mod matcher;
+mod finder;
 mod block;
+#[allow(dead_code)]
 mod inflate;
+mod old_inflate;
+mod container;
+mod optimal;
+mod split;
+mod checksum;
+#[allow(dead_code)]
+mod zip;
+mod bench;
+#[cfg(test)]
+mod tests;
 
-fn main() 
+use compress::{Config,Level};
+use container::Container;
+
+const USAGE : &str = "Usage: flate3 [OPTION]... [FILE]...
+Compress or decompress FILEs ( by default, compress FILEs in place ).
+With no FILE, or when FILE is -, read standard input.
+
+  -c, --stdout        write on standard output, keep original files unchanged
+  -d, --decompress    decompress
+  -f, --force         overwrite existing output files, write compressed data to a terminal
+  -k, --keep          keep ( don't delete ) input files
+  -t, --test          test compressed file integrity
+  -v, --verbose       report compression ratio, and progress for large files
+  -S, --suffix=SUF    use suffix SUF on compressed files
+  -p, --threads=N     compress with N threads
+  -0 .. -9            compression level, -0 = store only, -1 = fastest, -9 = best ( default -6 )
+      --store         same as -0
+      --fast          same as -1
+      --best          same as -9
+      --optimal=N     use optimal parsing with N passes ( slow )
+      --gzip          gzip ( RFC 1952 ) format, suffix .gz ( the default )
+  -z, --zlib          zlib ( RFC 1950 ) format, suffix .zz
+      --raw           raw deflate ( RFC 1951 ) format, suffix .deflate
+      --stats         report the blocks written on standard error when compressing
+      --trace         list the block headers and symbols of compressed FILEs on standard output ( implies -t )
+  -K, --zip           zip archive with a single entry, suffix .zip
+      --bench         benchmark every level and option on FILEs ( directories are read recursively ), output CSV
+      --json          output benchmark results as JSON
+      --repeat=N      time each benchmark as the fastest of N runs ( default 3 )
+  -h, --help          display this help and exit
+
+When decompressing, the format is detected from the data unless it is given ( raw deflate must be given ).";
+
+fn main()
 {
- let n = 100;
+  let args = match Args::parse( std::env::args().skip( 1 ) )
+  {
+    Ok( args ) => args,
+    Err( e ) =>
+    {
+      eprintln!( "flate3: {}\nTry 'flate3 --help' for more information.", e );
+      std::process::exit( 1 );
+    }
+  };
+  if args.help { println!( "{}", USAGE ); return; }
+  if args.bench
+  {
+    let mut c = bench::BenchConfig::new();
+    c.json = args.json;
+    if let Some( n ) = args.repeat { c.repeat = n; }
+    match bench::run( &args.files, &c, &mut std::io::stdout().lock() )
+    {
+      Ok( 0 ) => return,
+      Ok( n ) => eprintln!( "flate3: {} benchmark configurations did not round trip", n ),
+      Err( e ) => eprintln!( "flate3: {}", e )
+    }
+    std::process::exit( 1 );
+  }
 
- {
-   let start = Instant::now();
-   flate_test(n);
-   println!( "flate2 test completed ok, n={} time elapsed={} milli sec.", n, start.elapsed().as_millis() );
- }
+  let mut ok = true;
+  for name in &args.files
+  {
+    if let Err( e ) = process( name, &args )
+    {
+      eprintln!( "flate3: {}: {}", name, e );
+      ok = false;
+    }
+  }
+  if !ok { std::process::exit( 1 ); }
+}
 
- {
-   let mut config = compress::Config::new();
-   // config.options.dynamic_block_size = true;
-   // config.options.block_size = 0x1000;
-   // config.options.probe_max = 5;
+/// Compressed file format.
+#[derive(Clone,Copy,PartialEq)]
+enum Format
+{
+  Deflate( Container ),
+  Zip,
+}
 
-   // config.options.lazy_match = false;
+impl Format
+{
+  /// Default suffix for compressed files.
+  fn suffix( self ) -> &'static str
+  {
+    match self
+    {
+      Format::Deflate( Container::Gzip ) => ".gz",
+      Format::Deflate( Container::Zlib ) => ".zz",
+      Format::Deflate( Container::Raw ) => ".deflate",
+      Format::Zip => ".zip",
+    }
+  }
 
+  /// Detect the format of compressed data from the first bytes ( raw deflate cannot be detected ).
+  fn detect( b: &[u8] ) -> Option<Format>
+  {
+    if b.starts_with( &[ 0x1f, 0x8b ] ) { Some( Format::Deflate( Container::Gzip ) ) }
+    else if b.starts_with( b"PK\x03\x04" ) || b.starts_with( b"PK\x05\x06" ) { Some( Format::Zip ) }
+    else if b.len() >= 2 && b[ 0 ] & 15 == 8 && ( ( b[ 0 ] as u16 ) << 8 | b[ 1 ] as u16 ).is_multiple_of( 31 ) { Some( Format::Deflate( Container::Zlib ) ) }
+    else { None }
+  }
+}
 
-   let data = [ 1,2,3,4,1,2,3 ];
-   let cb : Vec<u8> = compress::compress( &data, &mut config );
-   let _ub : Vec<u8> = inflate::inflate( &cb );
+/// Suffixes recognised when decompressing.
+const SUFFIXES : [&str; 4] = [ ".gz", ".zz", ".deflate", ".zip" ];
 
-   let start = Instant::now();
-   test( n, &mut config );
-   println!( "flate3 test completed ok, n={} time elapsed={} milli sec.", n, start.elapsed().as_millis() );
- }
+/// Command line arguments.
+struct Args
+{
+  decompress: bool,
+  test: bool,
+  stdout: bool,
+  keep: bool,
+  force: bool,
+  verbose: bool,
+  help: bool,
+  bench: bool,
+  json: bool,
+  stats: bool,
+  trace: bool,
+  repeat: Option<usize>,
+  level: Level,
+  optimal_passes: usize,
+  threads: usize,
+  format: Option<Format>, // Output format, or input format if given when decompressing.
+  suffix: Option<String>,
+  files: Vec<String>, // Input files, "-" is standard input ( or corpus for benchmarks ).
 }
 
-pub fn test( n:usize, c: &mut compress::Config )
+impl Args
 {
-/*
-    check( &[1,2,3,4], &[120,156,5,128,1,9,0,0,0,130,40,253,191,89,118,12,11,0,24,0], c );
-    check( &[0,0,0,0,1,2,3,4], &[120,156,13,192,5,1,0,0,0,194,48,172,127,102,62,193,233,14,11,0,28,0], c );
-    check( &[1,2,3,4,1,2,3,4,1,2,3,4,1,1,4,1,2,3,4], &[], c );
-    let mut t : Vec<u8> = Vec::new();
- 
-    let mut rng = rand::thread_rng();
-    for _i in 0..10000 
-    { 
-      // t.push( ( ( i % 256 ) | ( i % 13 ) ) as u8 ); 
-      // t.push( ( i % 13 ) as u8 );
-      t.push( rng.gen() ); 
+  fn parse<I: Iterator<Item=String>>( mut it: I ) -> Result<Args, String>
+  {
+    let mut a = Args
+    {
+      decompress: false,
+      test: false,
+      stdout: false,
+      keep: false,
+      force: false,
+      verbose: false,
+      help: false,
+      bench: false,
+      json: false,
+      stats: false,
+      trace: false,
+      repeat: None,
+      level: Level::DEFAULT,
+      optimal_passes: 0,
+      threads: 1,
+      format: None,
+      suffix: None,
+      files: Vec::new(),
+    };
+    let number = | s: Option<String>, what: &str | -> Result<usize, String>
+    {
+      s.as_deref().and_then( |s| s.parse().ok() ).ok_or( format!( "{} requires a number", what ) )
+    };
+    let mut options = true;
+    while let Some( arg ) = it.next()
+    {
+      if !options || arg == "-" || !arg.starts_with( '-' ) { a.files.push( arg ); continue; }
+      if arg == "--" { options = false; continue; }
+      if let Some( long ) = arg.strip_prefix( "--" )
+      {
+        // Values may follow '=' or be the next argument.
+        let ( long, mut value ) = match long.split_once( '=' )
+        {
+          Some( ( l, v ) ) => ( l, Some( v.to_string() ) ),
+          None => ( long, None )
+        };
+        let mut next_value = || value.take().or_else( || it.next() );
+        match long
+        {
+          "stdout" | "to-stdout" => a.stdout = true,
+          "decompress" | "uncompress" => a.decompress = true,
+          "force" => a.force = true,
+          "keep" => a.keep = true,
+          "test" => a.test = true,
+          "verbose" => a.verbose = true,
+          "help" => a.help = true,
+          "bench" => a.bench = true,
+          "json" => a.json = true,
+          "stats" => a.stats = true,
+          "trace" => { a.trace = true; a.test = true; }
+          "repeat" => a.repeat = Some( number( next_value(), "option --repeat" )? ),
+          "store" => a.level = Level::NONE,
+          "fast" => a.level = Level::FASTEST,
+          "best" => a.level = Level::BEST,
+          "gzip" => a.format = Some( Format::Deflate( Container::Gzip ) ),
+          "zlib" => a.format = Some( Format::Deflate( Container::Zlib ) ),
+          "raw" => a.format = Some( Format::Deflate( Container::Raw ) ),
+          "zip" => a.format = Some( Format::Zip ),
+          "suffix" => a.suffix = Some( next_value().ok_or( "option --suffix requires an argument" )? ),
+          "threads" => a.threads = number( next_value(), "option --threads" )?,
+          "optimal" => a.optimal_passes = number( next_value(), "option --optimal" )?,
+          _ => return Err( format!( "unrecognized option '{}'", arg ) )
+        }
+        continue;
+      }
+      let mut chars = arg[ 1.. ].chars();
+      while let Some( c ) = chars.next()
+      {
+        match c
+        {
+          'c' => a.stdout = true,
+          'd' => a.decompress = true,
+          'f' => a.force = true,
+          'k' => a.keep = true,
+          't' => a.test = true,
+          'v' => a.verbose = true,
+          'h' => a.help = true,
+          'z' => a.format = Some( Format::Deflate( Container::Zlib ) ),
+          'K' => a.format = Some( Format::Zip ),
+          '0'..='9' => a.level = Level( c as u8 - b'0' ),
+          'S' | 'p' =>
+          {
+            // Value is the rest of the argument, or the next argument.
+            let rest : String = chars.by_ref().collect();
+            let value = if rest.is_empty() { it.next() } else { Some( rest ) };
+            if c == 'S' { a.suffix = Some( value.ok_or( "option -S requires an argument" )? ); }
+            else { a.threads = number( value, "option -p" )?; }
+          }
+          _ => return Err( format!( "invalid option -- '{}'", c ) )
+        }
+      }
     }
-    check( &t, &[], c );
-*/
-  let f = std::fs::read( "C:\\PdfFiles\\FreeSans.ttf" ).unwrap();
-  check( n, &f, &[], c );
+    if a.suffix.as_deref() == Some( "" ) { return Err( "suffix must not be empty".to_string() ); }
+    if a.files.is_empty() && !a.bench { a.files.push( "-".to_string() ); }
+    Ok( a )
+  }
+
+  /// Compression configuration for the level and options.
+  fn config( &self, container: Container ) -> Config
+  {
+    let mut c = Config::with_level( self.level );
+    c.options.optimal_passes = self.optimal_passes;
+    c.threads = self.threads;
+    c.container = container;
+    c
+  }
 }
 
-pub fn check( n:usize, inp: &[u8], chk: &[u8], c: &mut compress::Config )
+fn error( msg: &str ) -> std::io::Error
 {
-  let mut csize = 0;
+  std::io::Error::other( msg )
+}
 
-  for _loop in 0..n
+/// Compress, decompress or test one file ( "-" is standard input ).
+fn process( name: &str, args: &Args ) -> std::io::Result<()>
+{
+  let decompress = args.decompress || args.test;
+  let stdin = name == "-";
+  let ( input, meta ) : ( Box<dyn Read>, Option<std::fs::Metadata> ) = if stdin
+  {
+    ( Box::new( std::io::stdin() ), None )
+  } else {
+    let meta = std::fs::metadata( name )?;
+    if meta.is_dir() { return Err( error( "is a directory -- ignored" ) ); }
+    ( Box::new( File::open( name )? ), Some( meta ) )
+  };
+
+  // Name of output file ( None for standard output or when testing ).
+  let out_name = if stdin || args.stdout || args.test
+  {
+    None
+  } else if decompress {
+    let strip = | s: &str | name.strip_suffix( s ).filter( |n| !n.is_empty() && !n.ends_with( '/' ) );
+    let n = match &args.suffix
+    {
+      Some( s ) => strip( s ),
+      None => SUFFIXES.iter().find_map( |s| strip( s ) )
+    };
+    Some( n.ok_or( error( "unknown suffix -- ignored" ) )?.to_string() )
+  } else {
+    let suffix = args.suffix.as_deref().unwrap_or( args.format.unwrap_or( Format::Deflate( Container::Gzip ) ).suffix() );
+    if name.ends_with( suffix ) { return Err( error( &format!( "already has {} suffix -- unchanged", suffix ) ) ); }
+    Some( name.to_string() + suffix )
+  };
+  if out_name.is_none() && !decompress && !args.force && std::io::stdout().is_terminal()
   {
-    let cb = compress::compress( inp, c );
-    csize = cb.len();
+    return Err( error( "compressed data not written to a terminal, use -f to force compression" ) );
+  }
 
-    for i in 0..chk.len()
+  let out : Box<dyn Write> = match &out_name
+  {
+    None if args.test => Box::new( std::io::sink() ),
+    None => Box::new( std::io::stdout().lock() ),
+    Some( n ) =>
     {
-      // println!( "i={} b={}", i, cb[i] );
-      // if chk[i] != cb[i] { println!( "Failed at i={}", i ); }
-      assert_eq!( chk[i], cb[i] );
+      if !args.force && std::path::Path::new( n ).exists() { return Err( error( &format!( "{} already exists, not overwritten", n ) ) ); }
+      Box::new( std::io::BufWriter::new( File::create( n )? ) )
     }
+  };
+  let display = if stdin { "stdin" } else { name };
+  let total = meta.as_ref().map_or( 0, |m| m.len() );
+  let mut input = BufReader::new( Progress{ inner: input, done: 0, total, name: display, show: args.verbose, shown: None } );
+  let mut out = Counter{ inner: out, count: 0 };
+
+  let result = if decompress
+  {
+    decompress_stream( &mut input, &mut out, args )
+  } else {
+    compress_stream( &mut input, &mut out, name, meta.as_ref(), args )
+  };
+  let result = result.and_then( |_| out.flush() );
+  let p = input.into_inner();
+  if p.shown.is_some() { eprint!( "\r" ); }
+  if let Err( e ) = result
+  {
+    if let Some( n ) = &out_name { let _ = std::fs::remove_file( n ); }
+    return Err( e );
+  }
+
+  let remove = out_name.is_some() && !args.keep;
+  if let ( Some( n ), Some( m ) ) = ( &out_name, &meta ) { std::fs::set_permissions( n, m.permissions() )?; }
+  if remove { std::fs::remove_file( name )?; }
 
-/*
-    let inf = inflate::inflate( &cb );
-    for i in 0..inp.len()
+  if args.verbose
+  {
+    let ( plain, packed ) = if decompress { ( out.count, p.done ) } else { ( p.done, out.count ) };
+    let saved = if plain == 0 { 0.0 } else { 100.0 * ( plain as f64 - packed as f64 ) / plain as f64 };
+    match &out_name
     {
-      assert_eq!( inf[i], inp[i] );
+      _ if args.test => eprintln!( "{}:\t OK", display ),
+      Some( n ) if remove => eprintln!( "{}:\t{:5.1}% -- replaced with {}", display, saved, n ),
+      Some( n ) => eprintln!( "{}:\t{:5.1}% -- created {}", display, saved, n ),
+      None => eprintln!( "{}:\t{:5.1}%", display, saved ),
     }
-*/
   }
+  Ok(())
+}
 
-  println!( "flate3 compressed size={}", csize );
+/// Compress input to out. The file name and modification time ( from meta ) are recorded in gzip and zip output.
+fn compress_stream<R: Read, W: Write>( input: &mut R, out: &mut W, name: &str, meta: Option<&std::fs::Metadata>, args: &Args )
+  -> std::io::Result<()>
+{
+  let base = std::path::Path::new( name ).file_name().map_or( name.to_string(), |n| n.to_string_lossy().into_owned() );
+  let mtime = meta.and_then( |m| m.modified().ok() )
+    .and_then( |t| t.duration_since( std::time::UNIX_EPOCH ).ok() ).map_or( 0, |d| d.as_secs() );
+  match args.format.unwrap_or( Format::Deflate( Container::Gzip ) )
+  {
+    Format::Zip =>
+    {
+      let mut data = Vec::new();
+      input.read_to_end( &mut data )?;
+      let mut z = zip::ZipWriter::new( out, args.level );
+      z.config = args.config( Container::Raw );
+      z.time = zip::dos_time( mtime );
+      z.add_file( &base, &data )?;
+      z.finish()?;
+    }
+    Format::Deflate( container ) =>
+    {
+      let mut c = args.config( container );
+      if meta.is_some()
+      {
+        c.gzip.filename = Some( base.into_bytes() );
+        c.gzip.mtime = mtime as u32;
+      }
+      if c.threads > 1 || args.stats
+      {
+        // Parallel compression ( and statistics ) needs all the input.
+        let mut data = Vec::new();
+        input.read_to_end( &mut data )?;
+        if args.stats { c.stats = Some( compress::CompressStats::new() ); }
+        out.write_all( &compress::compress( &data, &mut c ) )?;
+        if let Some( stats ) = &c.stats { eprintln!( "{}", stats ); }
+      } else {
+        let mut comp = compress::Compressor::new( out, &c );
+        std::io::copy( input, &mut comp )?;
+        comp.finish()?;
+      }
+    }
+  }
+  Ok(())
 }
 
-fn flate_test( n:usize )
+/// Decompress input to out ( out is a sink when testing ).
+fn decompress_stream<R: BufRead, W: Write>( input: &mut R, out: &mut W, args: &Args ) -> std::io::Result<()>
 {
-  let f = std::fs::read( "C:\\PdfFiles\\FreeSans.ttf" ).unwrap();
+  let format = match args.format
+  {
+    Some( f ) => f,
+    None => Format::detect( input.fill_buf()? ).ok_or( error( "not in a recognised compressed format" ) )?
+  };
+  match format
+  {
+    Format::Zip =>
+    {
+      let mut data = Vec::new();
+      input.read_to_end( &mut data )?;
+      let invalid = | e: zip::ZipError | std::io::Error::new( std::io::ErrorKind::InvalidData, e );
+      let a = zip::Archive::new( &data ).map_err( invalid )?;
+      let files : Vec<&zip::Entry> = a.entries().iter().filter( |e| !e.is_dir() ).collect();
+      if files.is_empty() { return Err( error( "zip archive has no files" ) ); }
+      if files.len() > 1 && !args.test { return Err( error( "zip archive has more than one entry" ) ); }
+      for e in files { out.write_all( &a.read( e ).map_err( invalid )? )?; }
+    }
+    Format::Deflate( container ) =>
+    {
+      let mut opts = inflate::InflateOptions::new();
+      opts.container = container;
+      opts.multi_member = true; // Like gunzip, decompress concatenated gzip files.
+      if args.trace
+      {
+        let mut data = Vec::new();
+        input.read_to_end( &mut data )?;
+        let ( items, err ) = inflate::trace( &data, &opts );
+        let mut so = std::io::stdout().lock();
+        for item in items { writeln!( so, "{}", item )?; }
+        return match err { Some( e ) => Err( e.into() ), None => Ok(()) };
+      }
+      let mut inf = inflate::Inflater::with_options( input, &opts );
+      std::io::copy( &mut inf, out )?;
+      if inf.into_remainder().read( &mut [ 0 ] )? > 0 { eprintln!( "flate3: trailing garbage ignored" ); }
+    }
+  }
+  Ok(())
+}
 
-  use flate2::write::GzEncoder;
-  use flate2::Compression;
-  use std::io::prelude::*;
+/// Inputs larger than this have their progress reported ( when verbose ).
+const PROGRESS_MIN : u64 = 0x1000000;
 
-  let mut csize = 0;
+/// Reader which counts the bytes read, reporting progress as a percentage of total on stderr if show is set.
+struct Progress<'a, R: Read>
+{
+  inner: R,
+  done: u64, // Bytes read so far.
+  total: u64, // Size of input ( 0 if not known ).
+  name: &'a str,
+  show: bool,
+  shown: Option<u64>, // Percentage last reported.
+}
 
-  for _i in 0..n
+impl<'a, R: Read> Read for Progress<'a, R>
+{
+  fn read( &mut self, buf: &mut [u8] ) -> std::io::Result<usize>
   {
-    let mut encoder = GzEncoder::new(Vec::new(), Compression::default() /*new(9)*/ );
-    encoder.write_all(&f).unwrap();
-    let compressed_bytes = encoder.finish().unwrap();
-    csize = compressed_bytes.len();
+    let n = self.inner.read( buf )?;
+    self.done += n as u64;
+    if self.show && self.total > PROGRESS_MIN
+    {
+      let pct = std::cmp::min( self.done * 100 / self.total, 100 );
+      if self.shown != Some( pct )
+      {
+        eprint!( "\r{}: {}%", self.name, pct );
+        self.shown = Some( pct );
+      }
+    }
+    Ok( n )
   }
-  println!( "flate2 compressed size={}", csize );
- 
 }
 
+/// Writer which counts the bytes written.
+struct Counter<W: Write>
+{
+  inner: W,
+  count: u64,
+}
+
+impl<W: Write> Write for Counter<W>
+{
+  fn write( &mut self, buf: &[u8] ) -> std::io::Result<usize>
+  {
+    let n = self.inner.write( buf )?;
+    self.count += n as u64;
+    Ok( n )
+  }
+
+  fn flush( &mut self ) -> std::io::Result<()>
+  {
+    self.inner.flush()
+  }
+}
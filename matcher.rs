@@ -1,5 +1,5 @@
-use crossbeam::channel::Sender;
 use crate::compress;
+use crate::finder::{HashChains,Hash34,BinaryTree};
 
 pub struct Match
 {
@@ -8,34 +8,152 @@ pub struct Match
   pub distance: u16
 }
 
-pub fn find( input: &[u8], output: Sender<Match>, opts: &compress::Options )
+/// Find matches for input[start..], input[..start] is a preset dictionary ( or history ).
+pub fn find<F: FnMut( Match )>( input: &[u8], start: usize, output: &mut F, opts: &compress::Options )
 {
   let len = input.len();
   if len > MIN_MATCH
   {
-    let mut m = Matcher::new( len, opts );
-    m.find( input, output );
+    if opts.match_finder == Finder::HashChain
+    {
+      let mut m = Matcher::new( len, opts );
+      m.prime( input, start );
+      m.find( input, start, len - 2, output );
+    } else {
+      let mut m = new_finder( len, opts );
+      m.prime( input, start );
+      m.find( input, start, len - 2, output );
+    }
+  }
+}
+
+/// The match finders, see compress::Options::match_finder.
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Finder
+{
+  HashChain, // Chains of positions with the same 3 byte hash ( Matcher, the default ).
+  Hash4, // Chains of positions with the same 4 byte hash, faster but 3 byte matches are not found ( finder::HashChains ).
+  Hash5, // As Hash4 with 5 byte hashes, fastest for long repeats.
+  Hash34, // Latest position for each 3 byte hash, and chains for 4 byte hashes ( finder::Hash34 ).
+  BinaryTree, // Binary trees of positions with the same 4 byte hash, like LZMA's bt4, finds longer matches ( finder::BinaryTree ).
+}
+
+/// All the match finders.
+pub const FINDERS : [Finder; 5] = [ Finder::HashChain, Finder::Hash4, Finder::Hash5, Finder::Hash34, Finder::BinaryTree ];
+
+/// Match finder for input of about len bytes ( used to size hash tables ), as specified by opts.
+pub fn new_finder( len: usize, opts: &compress::Options ) -> Box<dyn MatchFinder>
+{
+  match opts.match_finder
+  {
+    Finder::HashChain => Box::new( Matcher::new( len, opts ) ),
+    Finder::Hash4 => Box::new( HashChains::new( 4, len, opts ) ),
+    Finder::Hash5 => Box::new( HashChains::new( 5, len, opts ) ),
+    Finder::Hash34 => Box::new( Hash34::new( len, opts ) ),
+    Finder::BinaryTree => Box::new( BinaryTree::new( len, opts ) ),
+  }
+}
+
+/// Finds LZ77 matches. Positions are inserted in order ( by insert, matches or find ), and matches refer to earlier positions.
+pub trait MatchFinder : Send
+{
+  /// Insert position into the finder without finding matches.
+  fn insert( &mut self, input: &[u8], position: usize );
+
+  /// Insert position, and append the matches starting at position to output as ( length, distance ) pairs.
+  /// Lengths are increasing, each distance is the smallest found for lengths up to the length. Used for optimal parsing.
+  fn matches( &mut self, input: &[u8], position: usize, output: &mut Vec<( u16, u16 )> );
+
+  /// LZ77 compression. Matches starting in input[position..end] are sent to output, input[..position] is history 
+  /// that has already been processed. The position reached is returned ( a match may extend past end ).
+  fn find( &mut self, input: &[u8], position: usize, end: usize, output: &mut dyn FnMut( Match ) ) -> usize;
+
+  /// Insert positions before end ( without finding matches ), used for a preset dictionary.
+  fn prime( &mut self, input: &[u8], end: usize )
+  {
+    for position in 0..end { self.insert( input, position ); }
+  }
+
+  /// Forget all positions, so later matches do not refer to earlier input.
+  fn reset( &mut self );
+
+  /// After reset, would the finder find the same matches as a new finder for input of about len bytes with opts?
+  fn reusable( &self, len: usize, opts: &compress::Options ) -> bool;
+
+  /// Discard the first n positions of input, positions held are adjusted accordingly.
+  fn slide( &mut self, n: usize );
+}
+
+/// Greedy ( or if lazy is set, lazy ) parsing of input[position..end] using the longest match from m at each position,
+/// for MatchFinder::find. The position reached is returned.
+pub fn parse( m: &mut dyn MatchFinder, input: &[u8], mut position: usize, end: usize, lazy: bool, output: &mut dyn FnMut( Match ) ) -> usize
+{
+  let limit = input.len().saturating_sub( 2 );
+  let mut found = Vec::new();
+  let mut longest = | m: &mut dyn MatchFinder, position: usize |
+  {
+    found.clear();
+    m.matches( input, position, &mut found );
+    found.last().map_or( ( 0, 0 ), |&( length, distance )| ( length as usize, distance as usize ) )
+  };
+  while position < end
+  {
+    let ( mut length, mut distance ) = longest( m, position );
+    position += 1;
+    if length < MIN_MATCH { continue; }
+    let mut next = position; // Next position to be inserted.
+
+    // Lazy matching, see Matcher::find.
+    while lazy && position < end
+    {
+      let ( length2, distance2 ) = longest( m, position );
+      next = position + 1;
+      if length2 > length || length2 == length && distance2 < distance
+      {
+        length = length2;
+        distance = distance2;
+        position += 1;
+      }
+      else { break; }
+    }
+    output( Match{ position: position - 1, length: length as u16, distance: distance as u16 } );
+
+    // Insert the positions in the rest of the match.
+    let match_end = position - 1 + length;
+    for p in next..std::cmp::min( match_end, limit ) { m.insert( input, p ); }
+    position = match_end;
   }
+  position
 }
 
 // RFC 1951 match ( LZ77 ) limits.
-const MIN_MATCH : usize = 3; // The smallest match eligible for LZ77 encoding.
-const MAX_MATCH : usize = 258; // The largest match eligible for LZ77 encoding.
+pub const MIN_MATCH : usize = 3; // The smallest match eligible for LZ77 encoding.
+pub const MAX_MATCH : usize = 258; // The largest match eligible for LZ77 encoding.
 const MAX_DISTANCE : usize = 0x8000; // The largest distance backwards in input from current position that can be encoded.
-const ENCODE_POSITION : usize = MAX_DISTANCE + 1;
 
-struct Matcher
+/// Positions held by the finders are encoded by adding ENCODE_POSITION, so 0 is no position, and an encoded position
+/// entry can be used from position if entry > position ( the distance is not too far ).
+pub const ENCODE_POSITION : usize = MAX_DISTANCE + 1;
+
+/// Number of input bytes needed after a position before matches starting there can be found.
+pub const LOOKAHEAD : usize = MAX_MATCH + MIN_MATCH;
+
+/// Amount of history kept by the streaming compressor.
+pub const WINDOW : usize = MAX_DISTANCE;
+
+pub struct Matcher
 {
   hash_shift: usize,
   hash_mask: usize,
   hash_table: Vec<usize>,
+  link: Vec<usize>,
   probe_max: usize, 
   lazy_match: bool
 }
 
 impl Matcher
 {
-  fn new( len: usize, opts: &compress::Options ) -> Matcher
+  pub fn new( len: usize, opts: &compress::Options ) -> Matcher
   {
     let hash_shift = calc_hash_shift( len * 2 );
     let hash_mask = ( 1 << ( MIN_MATCH * hash_shift ) ) - 1;
@@ -44,23 +162,26 @@ impl Matcher
       hash_shift,
       hash_mask,
       hash_table: vec![ 0; hash_mask + 1 ],
+      link: Vec::new(),
       probe_max: opts.probe_max,
       lazy_match: opts.lazy_match
     } 
   }
 
-  fn find( &mut self, input: &[u8], output: Sender<Match> ) // LZ77 compression.
+  /// LZ77 compression. Matches starting in input[position..end] are sent to output, input[..position] is history 
+  /// that has already been processed. The position reached is returned ( a match may extend past end ).
+  pub fn find<F: FnMut( Match )>( &mut self, input: &[u8], mut position: usize, end: usize, output: &mut F ) -> usize
   {
+    if position >= end { return position; }
     let limit = input.len() - 2;
 
-    let mut link : Vec<usize> = vec!(0; limit);
-
-    let mut position = 0; // position in input.
+    let mut link = std::mem::take( &mut self.link );
+    if link.len() < limit { link.resize( limit, 0 ); }
 
     // hash will be hash of three bytes starting at position.
-    let mut hash = ( ( input[ 0 ] as usize ) << self.hash_shift ) + input[ 1 ] as usize;
+    let mut hash = ( ( input[ position ] as usize ) << self.hash_shift ) + input[ position + 1 ] as usize;
 
-    while position < limit
+    while position < end
     {
       hash = ( ( hash << self.hash_shift ) + input[ position + 2 ] as usize ) & self.hash_mask;        
       let mut hash_entry = self.hash_table[ hash ];
@@ -80,7 +201,7 @@ impl Matcher
       // "Lazy matching" RFC 1951 p.15 : if there are overlapping matches, there is a choice over which of the match to use.
       // Example: "abc012bc345.... abc345". Here abc345 can be encoded as either [abc][345] or as a[bc345].
       // Since a range typically needs more bits to encode than a single literal, choose the latter.
-      while position < limit
+      while position < end
       {
         hash = ( ( hash << self.hash_shift ) + input[ position + 2 ] as usize ) & self.hash_mask;          
         hash_entry = self.hash_table[ hash ];
@@ -101,7 +222,7 @@ impl Matcher
         else { break; }
       }
 
-      output.send( Match{ position:position-1, length:match1 as u16, distance:distance1 as u16 } ).unwrap();
+      output( Match{ position:position-1, length:match1 as u16, distance:distance1 as u16 } );
 
       let mut copy_end = position - 1 + match1;
       if copy_end > limit { copy_end = limit; }
@@ -117,12 +238,14 @@ impl Matcher
         position += 1;
       }
     }
+    self.link = link;
+    position
   }
 
   // best_match finds the best match starting at position. 
   // old_position is from hash table, link [] is linked list of older positions.
 
-  fn best_match( &mut self, input: &[u8], position: usize, mut old_position: usize, link: &mut Vec<usize> ) -> ( usize, usize )
+  fn best_match( &mut self, input: &[u8], position: usize, mut old_position: usize, link: &mut [usize] ) -> ( usize, usize )
   { 
     let mut avail = input.len() - position;
     if avail > MAX_MATCH { avail = MAX_MATCH; }
@@ -168,6 +291,98 @@ impl Matcher
   }
 } // end impl Matcher
 
+impl MatchFinder for Matcher
+{
+  fn insert( &mut self, input: &[u8], position: usize )
+  {
+    if position + MIN_MATCH > input.len() { return; }
+    if self.link.len() <= position { self.link.resize( input.len() - 2, 0 ); }
+    let mut hash = ( ( input[ position ] as usize ) << self.hash_shift ) + input[ position + 1 ] as usize;
+    hash = ( ( hash << self.hash_shift ) + input[ position + 2 ] as usize ) & self.hash_mask;
+    self.link[ position ] = self.hash_table[ hash ];
+    self.hash_table[ hash ] = position + ENCODE_POSITION;
+  }
+
+  fn matches( &mut self, input: &[u8], position: usize, output: &mut Vec<( u16, u16 )> )
+  {
+    let limit = input.len() - 2;
+    if self.link.len() < limit { self.link.resize( limit, 0 ); }
+
+    let mut hash = ( ( input[ position ] as usize ) << self.hash_shift ) + input[ position + 1 ] as usize;
+    hash = ( ( hash << self.hash_shift ) + input[ position + 2 ] as usize ) & self.hash_mask;
+    let hash_entry = self.hash_table[ hash ];
+    self.hash_table[ hash ] = position + ENCODE_POSITION;
+    self.link[ position ] = hash_entry;
+    if position >= hash_entry { return; }
+
+    let mut avail = input.len() - position;
+    if avail > MAX_MATCH { avail = MAX_MATCH; }
+
+    let mut old_position = hash_entry - ENCODE_POSITION;
+    let mut best_match = MIN_MATCH - 1;
+    let mut probe_max: usize = self.probe_max;
+    while probe_max > 0
+    {
+      if input[ old_position + best_match ] == input[ position + best_match ]
+      {
+        let mut mat = 0;
+        while mat < avail && input[ position + mat ] == input[ old_position + mat ]
+        {
+          mat += 1;
+        }
+        if mat > best_match
+        {
+          best_match = mat;
+          output.push( ( mat as u16, ( position - old_position ) as u16 ) );
+          if best_match == avail { break; }
+        }
+      }
+      old_position = self.link[ old_position ];
+      if old_position <= position { break; }
+      old_position -= ENCODE_POSITION;
+      probe_max -= 1;
+    }
+  }
+
+  fn find( &mut self, input: &[u8], position: usize, end: usize, output: &mut dyn FnMut( Match ) ) -> usize
+  {
+    Matcher::find( self, input, position, end, &mut |m| output( m ) )
+  }
+
+  fn prime( &mut self, input: &[u8], end: usize )
+  {
+    let end = std::cmp::min( end, input.len().saturating_sub( 2 ) );
+    if end == 0 { return; }
+    if self.link.len() < end { self.link.resize( end, 0 ); }
+    let mut hash = ( ( input[ 0 ] as usize ) << self.hash_shift ) + input[ 1 ] as usize;
+    for position in 0..end
+    {
+      hash = ( ( hash << self.hash_shift ) + input[ position + 2 ] as usize ) & self.hash_mask;
+      self.link[ position ] = self.hash_table[ hash ];
+      self.hash_table[ hash ] = position + ENCODE_POSITION;
+    }
+  }
+
+  fn reset( &mut self )
+  {
+    self.hash_table.iter_mut().for_each( |x| *x = 0 );
+    self.link.clear();
+  }
+
+  fn reusable( &self, len: usize, opts: &compress::Options ) -> bool
+  {
+    self.hash_shift == calc_hash_shift( len * 2 ) && self.probe_max == opts.probe_max && self.lazy_match == opts.lazy_match
+  }
+
+  fn slide( &mut self, n: usize )
+  {
+    let adjust = | x: &mut usize | *x = if *x >= n + ENCODE_POSITION { *x - n } else { 0 };
+    self.hash_table.iter_mut().for_each( adjust );
+    self.link.drain( 0..std::cmp::min( n, self.link.len() ) );
+    self.link.iter_mut().for_each( adjust );
+  }
+}
+
 fn calc_hash_shift( n: usize ) -> usize
 {
   let mut p = 1;
@@ -0,0 +1,456 @@
+//! The inflate decode loop as it was before it was reworked ( input refilled a byte at a time, fixed codes decoded a bit
+//! at a time, and copies made a byte at a time ), kept only so the benchmarks can measure the difference.
+
+use std::io::BufRead;
+use crate::inflate::{InflateError,reverse,CLEN_ALPHABET,MATCH_OFF,MATCH_EXTRA,DIST_OFF,DIST_EXTRA};
+
+/// Inflate a zlib ( RFC 1950 ) stream.
+pub fn inflate( data: &[u8] ) -> Result<Vec<u8>, InflateError>
+{
+  let mut output = Vec::with_capacity( 2 * data.len() );
+  let input = &mut InputBitStream::new( data );
+  let cmf = input.get_bits( 8 );
+  let flg = input.get_bits( 8 );
+  if cmf & 15 != 8 || cmf >> 4 > 7 || flg & 32 != 0 || !( cmf << 8 | flg ).is_multiple_of( 31 ) 
+  { 
+    return Err( InflateError::InvalidHeader ); 
+  }
+  loop
+  {
+    let last = input.get_bit() != 0;
+    match input.get_bits( 2 )
+    {
+      2 => 
+      { 
+        let ( lit, dist ) = dyn_header( input )?;
+        huff_block( input, &mut output, &lit, &dist )?;
+      }
+      1 => fixed_block( input, &mut output )?,
+      0 => 
+      {
+        let n = copy_block( input )?;
+        copy_bytes( input, &mut output, n );
+      }
+      _ => return Err( InflateError::InvalidBlockType )
+    }
+    if input.truncated() { return Err( InflateError::Truncated ); }
+    if last { break; }
+  }
+  input.pad( 8 );
+  let check = input.get_bits( 32 ) as u32;
+  if input.truncated() { return Err( InflateError::Truncated ); }
+  if check.swap_bytes() != crate::checksum::adler32( &output ) { return Err( InflateError::ChecksumMismatch ); }
+  Ok( output )
+}
+
+/// Read the code lengths for a block encoded with dynamic Huffman codes, returning decoders for literal/length and distance codes.
+fn dyn_header<R: BufRead>( input: &mut InputBitStream<R> ) -> Result<( BitDecoder, BitDecoder ), InflateError>
+{
+  let n_lit = 257 + input.get_bits( 5 );
+  let n_dist = 1 + input.get_bits( 5 );
+  let n_len = 4 + input.get_bits( 4 );
+
+  // The lengths of the main Huffman codes (lit,dist) are themselves decoded by LenDecoder.
+  let mut len = LenDecoder::new( n_len, input )?;
+  let lit : BitDecoder = len.get_decoder( n_lit, input )?;
+  let dist : BitDecoder = len.get_decoder( n_dist, input )?; 
+  Ok( ( lit, dist ) )
+}
+
+/// Decode symbols using lit and dist until end of block.
+fn huff_block<R: BufRead>( input: &mut InputBitStream<R>, output: &mut Vec<u8>, lit: &BitDecoder, dist: &BitDecoder ) 
+  -> Result<(), InflateError>
+{
+  while !input.truncated()
+  {
+    let x : usize = lit.decode( input );
+    match x
+    {
+      0..=255 => output.push( x as u8 ),
+      256 => break,
+      _ => // LZ77 match code - replicate earlier output.
+      {
+        let mc = x - 257;
+        if mc >= MATCH_EXTRA.len() { return Err( InflateError::InvalidHuffman ); }
+        let length = MATCH_OFF[ mc ] as usize + input.get_bits( MATCH_EXTRA[ mc ] as usize );
+        let dc = dist.decode( input );
+        if dc >= DIST_OFF.len() { return Err( InflateError::InvalidHuffman ); }
+        let distance = DIST_OFF[ dc ] as usize + input.get_bits( DIST_EXTRA[ dc ] as usize );
+        copy( output, distance, length )?; 
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Copy length bytes from output ( at specified distance ) to output.
+fn copy( output: &mut Vec<u8>, distance: usize, mut length: usize ) -> Result<(), InflateError>
+{
+  if distance > output.len() { return Err( InflateError::BadDistance ); }
+  let mut i = output.len() - distance;
+  while length > 0
+  {
+    output.push( output[ i ] );
+    i += 1;
+    length -= 1;
+  }
+  Ok(())
+}
+
+/// Decode length-limited Huffman codes.
+// For speed, a lookup table is used to compute symbols from the variable length codes ( rather than reading single bits ).
+// To keep the lookup table small, codes longer than PEEK bits are looked up in two operations.
+struct BitDecoder
+{
+  nsym: usize, // The number of symbols.
+  bits: Vec<u8>, // The length in bits of the code that represents each symbol.
+  maxbits: usize, // The length in bits of the longest code.
+  peekbits: usize, // The bit length for the first lookup ( not greater than PEEK ).
+  lookup: Vec<usize> // The table used to look up a symbol from a code.
+}
+
+/// Maximum number of bits for first lookup.
+const PEEK : usize = 8; 
+
+impl BitDecoder
+{
+  fn new( nsym: usize ) -> BitDecoder
+  {
+    BitDecoder 
+    { 
+      nsym,
+      bits: vec![0; nsym],
+      maxbits: 0,
+      peekbits: 0,
+      lookup: Vec::new()
+    }
+  }
+
+  /// The main function : get a decoded symbol from the input bit stream.
+  /// Codes of up to PEEK bits are looked up in a single operation.
+  /// Codes of more than PEEK bits are looked up in two steps.
+  fn decode<R: BufRead>( &self, input: &mut InputBitStream<R> ) -> usize
+  {
+    let mut sym = self.lookup[ input.peek( self.peekbits ) ];
+    if sym >= self.nsym
+    {
+      sym = self.lookup[ sym - self.nsym + ( input.peek( self.maxbits ) >> self.peekbits ) ];
+    }  
+    let n = self.bits[ sym ] as usize;
+    if n == 0 { return usize::MAX; } // Undefined code ( the code is incomplete or has no symbols ).
+    input.advance( n );
+    sym
+  }
+
+  /// Set up the lookup table from bits, checking the code lengths are valid.
+  fn init_lookup( &mut self ) -> Result<(), InflateError>
+  {
+    let mut max_bits : usize = 0; 
+    for bp in &self.bits 
+    { 
+      let bits = *bp as usize;
+      if bits > max_bits { max_bits = bits; } 
+    }
+
+    self.maxbits = max_bits;
+    self.peekbits = if max_bits > PEEK { PEEK } else { max_bits };
+    self.lookup.resize( 1 << self.peekbits, 0 );
+
+    // Code below is from rfc1951 page 7.
+
+    // bl_count is the number of codes of length N, N >= 1.
+    let mut bl_count : Vec<usize> = vec![ 0; max_bits + 1 ];
+
+    for sym in 0..self.nsym { bl_count[ self.bits[ sym ] as usize ] += 1; }
+
+    // Check the code is not over-subscribed, and is complete unless it is a single one bit code ( or has no codes ).
+    let mut left : isize = 1;
+    for count in &bl_count[ 1.. ]
+    {
+      left = ( left << 1 ) - *count as isize;
+      if left < 0 { return Err( InflateError::InvalidHuffman ); }
+    }
+    if left > 0 && max_bits > 1 { return Err( InflateError::InvalidHuffman ); }
+
+    let mut next_code : Vec<usize> = vec![ 0; max_bits + 1 ];
+    let mut code = 0; 
+    bl_count[ 0 ] = 0;
+
+    for i in 0..max_bits
+    {
+      code = ( code + bl_count[ i ] ) << 1;
+      next_code[ i + 1 ] = code;
+    }
+
+    for sym in 0..self.nsym
+    {
+      let length = self.bits[ sym ] as usize;
+      if length != 0
+      {
+        self.setup_code( sym, length, next_code[ length ] );
+        next_code[ length ] += 1;
+      }
+    }
+    Ok(())
+  }
+
+  fn setup_code( &mut self, sym: usize, len: usize, mut code: usize )
+  {
+    if len <= self.peekbits
+    {
+      let diff = self.peekbits - len;
+      code <<= diff;
+      for i in code..code + (1 << diff)
+      {
+        // lookup index is reversed to match InputBitStream::peek
+        self.lookup[ reverse( i, self.peekbits ) ] = sym;
+      }
+    } else { // Secondary lookup required      
+      let peekbits2 = self.maxbits - self.peekbits;
+
+      // Split code into peekbits portion ( key ) and remainder ( code).
+      let diff1 = len - self.peekbits;
+      let key = reverse( code >> diff1, self.peekbits );
+      code &= ( 1 << diff1 ) - 1;
+
+      // Get the base for the secondary lookup.
+      let mut base = self.lookup[ key ];
+      if base == 0 // Secondary lookup not yet allocated for this key.
+      {
+        base = self.lookup.len();
+        self.lookup.resize( base + ( 1 << peekbits2 ), 0 );
+        self.lookup[ key ] = self.nsym + base;
+      } else {
+        base -= self.nsym;
+      }
+
+      // Set the secondary lookup values.
+      let diff = self.maxbits - len;
+      code <<= diff;
+      for i in code..code + (1<<diff)
+      { 
+        self.lookup[ base + reverse( i, peekbits2 ) ] = sym;
+      }
+    }    
+  }
+} // end impl BitDecoder
+
+/// Decodes an array of lengths, returning a new BitDecoder.  
+/// There are special codes for repeats, and repeats of zeros, per RFC 1951 page 13.
+struct LenDecoder
+{
+  plenc: u8, // previous length code ( which can be repeated )
+  rep: usize,   // repeat
+  bd: BitDecoder
+}
+
+impl LenDecoder
+{
+  fn new<R: BufRead>( n_len: usize, input: &mut InputBitStream<R> ) -> Result<LenDecoder, InflateError>
+  {
+    let mut result = LenDecoder { plenc: 0, rep:0, bd: BitDecoder::new( 19 ) };
+
+    // Read the array of 3-bit code lengths (used to encode the main code lengths ) from input.
+    for i in CLEN_ALPHABET.iter().take( n_len )
+    { 
+      result.bd.bits[ *i as usize ] = input.get_bits(3) as u8; 
+    }
+    result.bd.init_lookup()?;
+    Ok( result )
+  }
+
+  fn get_decoder<R: BufRead>( &mut self, nsym: usize, input: &mut InputBitStream<R> ) -> Result<BitDecoder, InflateError>
+  {
+    let mut result = BitDecoder::new( nsym );
+    let bits = &mut result.bits;
+    let mut i = 0;
+    if self.rep > nsym { return Err( InflateError::InvalidHuffman ); }
+    while self.rep > 0 { bits[ i ] = self.plenc; i += 1; self.rep -= 1; }
+    while i < nsym
+    { 
+      let lenc = self.bd.decode( input );
+      if input.truncated() { return Err( InflateError::Truncated ); }
+      if lenc > 18 { return Err( InflateError::InvalidHuffman ); }
+      let lenc = lenc as u8;
+      if lenc < 16 
+      {
+        bits[ i ] = lenc; 
+        i += 1; 
+        self.plenc = lenc; 
+      } else {
+        if lenc == 16 { self.rep = 3 + input.get_bits(2); }
+        else if lenc == 17 { self.rep = 3 + input.get_bits(3); self.plenc=0; }
+        else if lenc == 18 { self.rep = 11 + input.get_bits(7); self.plenc=0; } 
+        while i < nsym && self.rep > 0 { bits[ i ] = self.plenc; i += 1; self.rep -= 1; }
+      }
+    }
+    result.init_lookup()?;
+    Ok( result )
+  }
+} // end impl LenDecoder
+
+/// For reading bits from input.
+// If the input ends, zero bits are supplied and overrun is incremented, so truncation can be detected later ( see truncated ).
+struct InputBitStream<R: BufRead>
+{
+  inp: R, // Input data.
+  buf: usize, // Bit buffer.
+  got: usize, // Number of bits in buffer.
+  overrun: usize, // Number of bytes supplied past the end of input.
+}
+
+impl <R: BufRead> InputBitStream<R>
+{
+  fn new( inp: R ) -> InputBitStream<R>
+  {
+    InputBitStream { inp, buf: 0, got: 0, overrun: 0 }
+  } 
+
+  // Get next byte of input.
+  fn next_byte( &mut self ) -> u8
+  {
+    match self.inp.fill_buf()
+    {
+      Ok( b ) if !b.is_empty() => 
+      {
+        let result = b[ 0 ];
+        self.inp.consume( 1 );
+        result
+      }
+      _ => { self.overrun += 1; 0 }
+    }
+  }
+
+  // Get n bits of input ( but do not advance ).
+  fn peek( &mut self, n: usize ) -> usize
+  {
+    while self.got < n
+    {
+      self.buf |= ( self.next_byte() as usize ) << self.got;
+      self.got += 8;
+    }
+    self.buf & ( ( 1 << n ) - 1 )
+  }
+
+  // Advance n bits.
+  fn advance( &mut self, n:usize )
+  { 
+    self.buf >>= n;
+    self.got -= n;
+  }
+
+  // Get a single bit.
+  fn get_bit( &mut self ) -> usize
+  {
+    if self.got == 0 { self.peek( 1 ); }
+    let result = self.buf & 1;
+    self.advance( 1 );
+    result
+  }
+
+  // Get n bits of input.
+  fn get_bits( &mut self, n: usize ) -> usize
+  { 
+    let result = self.peek( n );
+    self.advance( n );
+    result
+  }
+
+  // Get n bits of input, reversed.
+  fn get_huff( &mut self, mut n: usize ) -> usize 
+  { 
+    let mut result = 0; 
+    while n > 0
+    { 
+      result = ( result << 1 ) + self.get_bit(); 
+      n -= 1;
+    }
+    result
+  }
+
+  // Move to n-bit boundary ( n a power of 2 ).
+  fn pad( &mut self, n: usize )
+  {  
+    self.advance( self.got % n );
+  }
+
+  // Have bits past the end of input been used?
+  fn truncated( &self ) -> bool
+  {
+    self.overrun * 8 > self.got
+  }
+} // end impl InputBitStream
+
+/// Read the header of an uncompressed block, returning the number of bytes in the block.
+fn copy_block<R: BufRead>( input: &mut InputBitStream<R> ) -> Result<usize, InflateError>
+{
+  input.pad( 8 ); // Move to 8-bit boundary.
+  let n = input.get_bits( 16 );
+  let n1 = input.get_bits( 16 );
+  if input.truncated() { return Err( InflateError::Truncated ); }
+  if n != n1 ^ 0xffff { return Err( InflateError::BadStoredLength ); }
+  Ok( n )
+}
+
+/// Copy n bytes of uncompressed input to output.
+fn copy_bytes<R: BufRead>( input: &mut InputBitStream<R>, output: &mut Vec<u8>, mut n: usize )
+{
+  // First any whole bytes remaining in the bit buffer.
+  while n > 0 && input.got >= 8
+  {
+    output.push( input.get_bits( 8 ) as u8 );
+    n -= 1;
+  }
+  while n > 0
+  {
+    let avail = match input.inp.fill_buf() { Ok( b ) => b, Err( _ ) => &[] };
+    if avail.is_empty()
+    {
+      // End of input, let next_byte record it.
+      output.push( input.get_bits( 8 ) as u8 );
+      n -= 1;
+      continue;
+    }
+    let take = std::cmp::min( n, avail.len() );
+    output.extend_from_slice( &avail[..take] );
+    input.inp.consume( take );
+    n -= take;
+  }
+}
+
+/// Decode block encoded with fixed (pre-defined) Huffman codes, until end of block.
+fn fixed_block<R: BufRead>( input: &mut InputBitStream<R>, output: &mut Vec<u8> ) -> Result<(), InflateError> // RFC1951 page 12.
+{
+  while !input.truncated()
+  {
+    // 0 to 23 ( 7 bits ) => 256 - 279; 48 - 191 ( 8 bits ) => 0 - 143; 
+    // 192 - 199 ( 8 bits ) => 280 - 287; 400..511 ( 9 bits ) => 144 - 255
+    let mut x = input.get_huff( 7 );
+    if x <= 23 
+    { 
+      x += 256; 
+    } else {
+      x = ( x << 1 ) + input.get_bit();
+      if x <= 191 { x -= 48; }
+      else if x <= 199 { x += 88; }
+      else { x = ( x << 1 ) + input.get_bit() - 256; }
+    }
+
+    match x
+    {
+      0..=255 => { output.push( x as u8 ); }
+      256 => break,
+      _ => // 257 <= x && x <= 287, 286 and 287 are not valid.
+      { 
+        x -= 257;
+        if x >= MATCH_EXTRA.len() { return Err( InflateError::InvalidHuffman ); }
+        let length = MATCH_OFF[x] as usize + input.get_bits( MATCH_EXTRA[ x ] as usize );
+        let dcode = input.get_huff( 5 );
+        if dcode >= DIST_OFF.len() { return Err( InflateError::InvalidHuffman ); }
+        let distance = DIST_OFF[dcode] as usize + input.get_bits( DIST_EXTRA[dcode] as usize );
+        copy( output, distance, length )?;
+      }
+    }
+  }
+  Ok(())
+} // end fixed_block
@@ -0,0 +1,175 @@
+use crate::compress;
+use crate::matcher::{self,Match,MatchFinder,MIN_MATCH,MAX_MATCH};
+use crate::block::{Block,FIXED_LIT_BITS};
+use crate::bit::BitStream;
+use crate::inflate::{MATCH_OFF,MATCH_EXTRA,DIST_OFF,DIST_EXTRA};
+
+/// Find matches for input[start..] using optimal parsing, input[..start] is a preset dictionary ( or history ).
+pub fn find<F: FnMut( Match )>( input: &[u8], start: usize, output: &mut F, opts: &compress::Options )
+{
+  let len = input.len();
+  if len > MIN_MATCH
+  {
+    let mut m = matcher::new_finder( len, opts );
+    m.prime( input, start );
+    parse( m.as_mut(), input, start, len, opts, output );
+  }
+}
+
+/// Optimal ( shortest path ) parsing. Matches within input[position..end] are sent to output, input[..position] is history.
+/// The input is parsed in chunks. For each chunk, the candidate matches at every position are found, then the cheapest
+/// sequence of literals and matches is found, with costs taken from the Huffman codes that would be used to encode
+/// the previous parse ( the first parse uses the fixed codes ). The parse is repeated up to opts.optimal_passes times ( while
+/// the estimated size decreases ), and the smallest is output. Matches do not extend past end, so end is returned.
+pub fn parse<F: FnMut( Match )>( m: &mut dyn MatchFinder, input: &[u8], mut position: usize, end: usize, opts: &compress::Options, output: &mut F ) -> usize
+{
+  let mut p = Parser::new();
+  while position < end
+  {
+    let chunk_end = std::cmp::min( position + CHUNK, end );
+    p.candidates( m, input, position, chunk_end );
+
+    let mut costs = Costs::fixed();
+    let mut best : Option<( usize, Vec<Match> )> = None;
+    for _pass in 0..std::cmp::max( opts.optimal_passes, 1 )
+    {
+      let mlist = p.shortest_path( input, position, chunk_end, &costs );
+
+      let mut b = Block::new( position, chunk_end - position, 0 );
+      b.init( input, &mlist );
+      let mut scratch = BitStream::new( 0 );
+      let size = b.dynamic_size( &mut scratch );
+      costs = Costs::from_block( &mut b, &mut scratch );
+
+      match &best
+      {
+        Some( ( best_size, _ ) ) if *best_size <= size => break, // No improvement.
+        _ => best = Some( ( size, mlist ) )
+      }
+    }
+    for mat in best.unwrap().1 { output( mat ); }
+    position = chunk_end;
+  }
+  end
+}
+
+/// Amount of input parsed at a time, larger chunks give better cost estimates but use more memory.
+const CHUNK : usize = 0x10000;
+
+/// Cost in bits of literals and matches.
+struct Costs
+{
+  lit: Vec<u32>, // Cost of each literal/length symbol.
+  dist: Vec<u32>, // Cost of each distance symbol.
+}
+
+impl Costs
+{
+  /// Costs from the RFC 1951 fixed Huffman codes.
+  fn fixed() -> Costs
+  {
+    Costs{ lit: FIXED_LIT_BITS.iter().map( |b| *b as u32 ).collect(), dist: vec![ 5; 32 ] }
+  }
+
+  /// Costs from the Huffman codes for a block. Unused symbols are given a cost of one more than the longest code.
+  fn from_block( b: &mut Block, scratch: &mut BitStream ) -> Costs
+  {
+    let ( lit, dist ) = b.code_bits( scratch );
+    let cost = | bits: &[u8], n: usize |
+    {
+      let unused = 1 + *bits.iter().max().unwrap_or( &0 ) as u32;
+      ( 0..n ).map( |i| match bits.get( i ) { Some( &b ) if b > 0 => b as u32, _ => unused } ).collect()
+    };
+    Costs{ lit: cost( lit, 288 ), dist: cost( dist, 32 ) }
+  }
+}
+
+/// Working storage for optimal parsing.
+struct Parser
+{
+  cand: Vec<( u16, u16 )>, // Candidate matches ( length, distance ) for all positions in the chunk.
+  cand_start: Vec<usize>, // Index into cand of the first candidate for each position ( plus one entry for the end ).
+  len_code: Vec<u8>, // Match code for each match length.
+  dist_code: Vec<u8>, // Distance code for each distance.
+  cost: Vec<u32>, // Cheapest cost to reach each position.
+  step: Vec<( u16, u16 )>, // ( length, distance ) of the last step on the cheapest path to each position, length 1 = literal.
+}
+
+impl Parser
+{
+  fn new() -> Parser
+  {
+    let mut len_code = vec![ 0; MAX_MATCH + 1 ];
+    for ( length, code ) in len_code.iter_mut().enumerate().skip( MIN_MATCH )
+    {
+      let mut mc = 0; while length as u16 >= MATCH_OFF[ mc ] { mc += 1; } mc -= 1;
+      *code = mc as u8;
+    }
+    let mut dist_code = vec![ 0; 0x8001 ];
+    let mut dc = 0;
+    for ( distance, code ) in dist_code.iter_mut().enumerate().skip( 1 )
+    {
+      while dc < 29 && distance >= DIST_OFF[ dc + 1 ] as usize { dc += 1; }
+      *code = dc as u8;
+    }
+    Parser{ cand: Vec::new(), cand_start: Vec::new(), len_code, dist_code, cost: Vec::new(), step: Vec::new() }
+  }
+
+  /// Find the candidate matches for positions start..end.
+  fn candidates( &mut self, m: &mut dyn MatchFinder, input: &[u8], start: usize, end: usize )
+  {
+    self.cand.clear();
+    self.cand_start.clear();
+    for position in start..end
+    {
+      self.cand_start.push( self.cand.len() );
+      if position + MIN_MATCH <= input.len() { m.matches( input, position, &mut self.cand ); }
+    }
+    self.cand_start.push( self.cand.len() );
+  }
+
+  /// Find the cheapest parse of input[start..end] given the costs.
+  fn shortest_path( &mut self, input: &[u8], start: usize, end: usize, costs: &Costs ) -> Vec<Match>
+  {
+    let n = end - start;
+    self.cost.clear();
+    self.cost.resize( n + 1, u32::MAX );
+    self.step.clear();
+    self.step.resize( n + 1, ( 0, 0 ) );
+    self.cost[ 0 ] = 0;
+
+    for i in 0..n
+    {
+      let here = self.cost[ i ];
+      let c = here + costs.lit[ input[ start + i ] as usize ];
+      if c < self.cost[ i + 1 ] { self.cost[ i + 1 ] = c; self.step[ i + 1 ] = ( 1, 0 ); }
+
+      let mut length = MIN_MATCH;
+      for &( mlen, distance ) in &self.cand[ self.cand_start[ i ]..self.cand_start[ i + 1 ] ]
+      {
+        let dc = self.dist_code[ distance as usize ] as usize;
+        let dcost = here + costs.dist[ dc ] + DIST_EXTRA[ dc ] as u32;
+        let max = std::cmp::min( mlen as usize, n - i );
+        while length <= max
+        {
+          let mc = self.len_code[ length ] as usize;
+          let c = dcost + costs.lit[ 257 + mc ] + MATCH_EXTRA[ mc ] as u32;
+          if c < self.cost[ i + length ] { self.cost[ i + length ] = c; self.step[ i + length ] = ( length as u16, distance ); }
+          length += 1;
+        }
+      }
+    }
+
+    // Trace the path back from the end.
+    let mut mlist = Vec::new();
+    let mut i = n;
+    while i > 0
+    {
+      let ( length, distance ) = self.step[ i ];
+      i -= length as usize;
+      if length > 1 { mlist.push( Match{ position: start + i, length, distance } ); }
+    }
+    mlist.reverse();
+    mlist
+  }
+}
@@ -0,0 +1,164 @@
+//! Block splitting. Each RFC 1951 block has its own Huffman codes, so splitting the input where the symbol frequencies change
+//! gives smaller output, provided the saving is more than the cost of the extra block header.
+
+use crate::matcher::Match;
+use crate::inflate::{MATCH_OFF,DIST_OFF};
+
+/// Smallest block produced by splitting.
+const MIN_BLOCK : usize = 0x400;
+
+/// Estimated size in bits of a block header ( not counting the code lengths ).
+const HEADER_BITS : f64 = 60.0;
+
+/// Estimated size in bits of the code length for a used symbol in the block header.
+const SYMBOL_BITS : f64 = 4.0;
+
+/// Get the end positions of blocks for input[start..end], mlist is the matches starting in input[start..end] ( in order ).
+/// The last end position is end ( but note a match may extend the last block past end ).
+/// Blocks are split recursively at the position which minimises the estimated size of the two parts,
+/// provided this is less than the estimated size of the unsplit block.
+pub fn split( input: &[u8], start: usize, end: usize, mlist: &[Match] ) -> Vec<usize>
+{
+  let items = items( input, start, end, mlist );
+  let mut result = Vec::new();
+  split_items( &items, end, &mut result );
+  result.push( end );
+  result
+}
+
+/// A literal or match.
+struct Item
+{
+  position: usize,
+  lit: u16, // Literal/length symbol.
+  dist: u8, // Distance symbol ( NO_DIST for a literal ).
+}
+
+const NO_DIST : u8 = 255;
+
+/// Get the literals and matches for input[start..end].
+fn items( input: &[u8], start: usize, end: usize, mlist: &[Match] ) -> Vec<Item>
+{
+  let mut result = Vec::with_capacity( end - start );
+  let mut position = start;
+  for mat in mlist
+  {
+    while position < mat.position
+    {
+      result.push( Item{ position, lit: input[ position ] as u16, dist: NO_DIST } );
+      position += 1;
+    }
+    let mut mc = 0; while mat.length >= MATCH_OFF[ mc ] { mc += 1; } mc -= 1;
+    let mut dc = 29; while mat.distance < DIST_OFF[ dc ] { dc -= 1; }
+    result.push( Item{ position, lit: 257 + mc as u16, dist: dc as u8 } );
+    position += mat.length as usize;
+  }
+  while position < end
+  {
+    result.push( Item{ position, lit: input[ position ] as u16, dist: NO_DIST } );
+    position += 1;
+  }
+  result
+}
+
+/// Split items ( which end at position end ), pushing the split positions to result.
+fn split_items( items: &[Item], end: usize, result: &mut Vec<usize> )
+{
+  if items.is_empty() { return; }
+  let start = items[ 0 ].position;
+  if end - start < 2 * MIN_BLOCK { return; }
+
+  let mut left = Hist::new();
+  let mut right = Hist::new();
+  for it in items { right.add( it ); }
+
+  // Move items from right to left, tracking the estimated size of the two parts.
+  let mut best = right.cost();
+  let mut best_split = 0;
+  for ( i, it ) in items.iter().enumerate()
+  {
+    if it.position - start >= MIN_BLOCK
+    {
+      if end - it.position < MIN_BLOCK { break; }
+      let cost = left.cost() + right.cost();
+      if cost < best { best = cost; best_split = i; }
+    }
+    left.add( it );
+    right.remove( it );
+  }
+
+  if best_split > 0
+  {
+    let position = items[ best_split ].position;
+    split_items( &items[ ..best_split ], position, result );
+    result.push( position );
+    split_items( &items[ best_split.. ], end, result );
+  }
+}
+
+/// Symbol frequencies, with the entropy ( estimated size ) maintained incrementally as symbols are added and removed.
+struct Hist
+{
+  lit: [u32; 288],
+  dist: [u32; 32],
+  lit_total: u32,
+  dist_total: u32,
+  sum: f64, // Sum of n * log2( n ) over the symbol counts n.
+  used: usize, // Number of symbols used.
+}
+
+impl Hist
+{
+  fn new() -> Hist
+  {
+    Hist{ lit: [ 0; 288 ], dist: [ 0; 32 ], lit_total: 0, dist_total: 0, sum: 0.0, used: 0 }
+  }
+
+  fn add( &mut self, it: &Item )
+  {
+    Hist::inc( &mut self.lit[ it.lit as usize ], &mut self.sum, &mut self.used );
+    self.lit_total += 1;
+    if it.dist != NO_DIST
+    {
+      Hist::inc( &mut self.dist[ it.dist as usize ], &mut self.sum, &mut self.used );
+      self.dist_total += 1;
+    }
+  }
+
+  fn remove( &mut self, it: &Item )
+  {
+    Hist::dec( &mut self.lit[ it.lit as usize ], &mut self.sum, &mut self.used );
+    self.lit_total -= 1;
+    if it.dist != NO_DIST
+    {
+      Hist::dec( &mut self.dist[ it.dist as usize ], &mut self.sum, &mut self.used );
+      self.dist_total -= 1;
+    }
+  }
+
+  fn inc( n: &mut u32, sum: &mut f64, used: &mut usize )
+  {
+    if *n == 0 { *used += 1; }
+    *sum += nlog( *n + 1 ) - nlog( *n );
+    *n += 1;
+  }
+
+  fn dec( n: &mut u32, sum: &mut f64, used: &mut usize )
+  {
+    *n -= 1;
+    if *n == 0 { *used -= 1; }
+    *sum += nlog( *n ) - nlog( *n + 1 );
+  }
+
+  /// Estimated size in bits of a block with these frequencies ( excluding extra bits ).
+  /// The entropy of n symbols with counts c is n * log2( n ) - sum( c * log2( c ) ).
+  fn cost( &self ) -> f64
+  {
+    nlog( self.lit_total ) + nlog( self.dist_total ) - self.sum + HEADER_BITS + SYMBOL_BITS * self.used as f64
+  }
+}
+
+fn nlog( n: u32 ) -> f64
+{
+  if n == 0 { 0.0 } else { n as f64 * ( n as f64 ).log2() }
+}
@@ -0,0 +1,1893 @@
+//! Round-trip and robustness tests for compress and inflate.
+//! Inputs are generated from a seeded random number generator, so failures are reproducible ( the seed is reported ).
+
+use std::io::{Read,Write};
+use crate::compress::{self,Config,Level,Options,Compressor,Flush,CompressStats};
+use crate::container::Container;
+use crate::inflate::{self,InflateOptions,InflateError,Inflater,InputBitStream,TraceItem};
+use crate::block::BlockType;
+use crate::matcher::{self,Finder,MAX_MATCH};
+use crate::huffman::{self,Encoder,Decoder,HuffmanError};
+use crate::bit::{BitStream,BitCoder};
+
+/// Small fast random number generator ( xorshift64* ).
+struct Rng( u64 );
+
+impl Rng
+{
+  fn new( seed: u64 ) -> Rng
+  {
+    Rng( seed.wrapping_mul( 0x9e3779b97f4a7c15 ) | 1 )
+  }
+
+  fn next( &mut self ) -> u64
+  {
+    self.0 ^= self.0 >> 12;
+    self.0 ^= self.0 << 25;
+    self.0 ^= self.0 >> 27;
+    self.0.wrapping_mul( 0x2545f4914f6cdd1d )
+  }
+
+  /// Random number in 0..n.
+  fn below( &mut self, n: usize ) -> usize
+  {
+    ( self.next() >> 16 ) as usize % n
+  }
+
+  fn bytes( &mut self, n: usize ) -> Vec<u8>
+  {
+    ( 0..n ).map( |_| self.next() as u8 ).collect()
+  }
+}
+
+/// Number of kinds of structured input.
+const KINDS : usize = 7;
+
+/// Input of about len bytes, of the given kind.
+fn structured( rng: &mut Rng, kind: usize, len: usize ) -> Vec<u8>
+{
+  let mut v = Vec::with_capacity( len + 300 );
+  match kind
+  {
+    0 => v = rng.bytes( len ), // Incompressible.
+    1 => while v.len() < len // Runs of a byte.
+    {
+      let b = rng.next() as u8;
+      let n = 1 + rng.below( 600 );
+      v.resize( v.len() + n, b );
+    }
+    2 => while v.len() < len // Text from a small vocabulary.
+    {
+      let words = [ "the", "quick", "brown", "fox", "jumps", "over", "lazy", "dog", "and", "then", "some" ];
+      v.extend_from_slice( words[ rng.below( words.len() ) ].as_bytes() );
+      v.push( if rng.below( 10 ) == 0 { b'\n' } else { b' ' } );
+    }
+    3 => // Random pattern repeated with a random period ( matches at a fixed distance, including long matches ).
+    {
+      let n = 1 + rng.below( 300 );
+      let p = rng.bytes( n );
+      while v.len() < len { v.push( p[ v.len() % p.len() ] ); }
+    }
+    4 => // Random block repeated near the maximum distance.
+    {
+      let b = rng.bytes( 1000 );
+      while v.len() < len
+      {
+        v.extend_from_slice( &b );
+        let gap = 0x8000 - 1000 - 2 + rng.below( 4 ); // Distance 32766 to 32769.
+        let filler = rng.bytes( gap );
+        v.extend_from_slice( &filler );
+      }
+    }
+    5 => while v.len() < len // Skewed byte distribution.
+    {
+      let r = rng.below( 32 );
+      v.push( ( r * r / 32 ) as u8 );
+    }
+    _ => while v.len() < len // Sections of the other kinds.
+    {
+      let n = 1 + rng.below( 5000 );
+      let k = rng.below( KINDS - 1 );
+      let s = structured( rng, k, n );
+      v.extend_from_slice( &s );
+    }
+  }
+  v.truncate( len );
+  v
+}
+
+/// Inputs used for the exhaustive option tests : edge cases plus each kind of structured input.
+fn inputs() -> Vec<Vec<u8>>
+{
+  let mut rng = Rng::new( 1 );
+  let mut v = vec![ vec![], vec![ 0 ], vec![ 1, 2 ], vec![ 1, 2, 3 ], vec![ 7; 259 ], vec![ 7; 262 ], b"abcabcabcabc".to_vec() ];
+  for kind in 0..KINDS { v.push( structured( &mut rng, kind, 8000 ) ); }
+  v.push( structured( &mut rng, 4, 40000 ) );
+  v
+}
+
+/// Every level, with every combination of the Options flags ( lazy matching does not apply to optimal parsing ).
+fn all_options() -> Vec<Options>
+{
+  let mut result = Vec::new();
+  for level in 0..=9
+  {
+    let base = Level( level ).options();
+    if base.store_only { result.push( base ); continue; }
+    for &dynamic_block_size in &[ false, true ]
+    {
+      for &lazy_match in &[ false, true ]
+      {
+        for &optimal_passes in &[ 0, 2 ]
+        {
+          if optimal_passes > 0 && !lazy_match { continue; }
+          result.push( Options{ dynamic_block_size, lazy_match, optimal_passes, ..base.clone() } );
+        }
+      }
+    }
+  }
+  result
+}
+
+/// Random options.
+fn random_options( rng: &mut Rng ) -> Options
+{
+  let mut o = Level( rng.below( 10 ) as u8 ).options();
+  o.dynamic_block_size = rng.below( 2 ) == 0;
+  o.lazy_match = rng.below( 2 ) == 0;
+  o.block_size = 0x100 << rng.below( 9 );
+  o.probe_max = 1 + rng.below( 64 );
+  o.optimal_passes = if rng.below( 4 ) == 0 { 1 + rng.below( 3 ) } else { 0 };
+  o.match_finder = matcher::FINDERS[ rng.below( matcher::FINDERS.len() ) ];
+  o
+}
+
+fn inflate_options( c: &Config ) -> InflateOptions
+{
+  let mut opts = InflateOptions::new();
+  opts.container = c.container;
+  opts.dictionary = c.dictionary.clone();
+  opts
+}
+
+/// Compress data with compress and with the streaming Compressor ( writing pieces of random size ), check both
+/// inflate to data, using try_inflate_with and Inflater ( reading pieces of random size ).
+fn check_round_trip( data: &[u8], c: &mut Config, rng: &mut Rng, what: &str )
+{
+  let opts = inflate_options( c );
+  let cb = compress::compress( data, c );
+  match inflate::try_inflate_with( &cb, &opts )
+  {
+    Ok( ub ) => assert!( ub == data, "{}: compress round trip mismatch, len={}", what, data.len() ),
+    Err( e ) => panic!( "{}: inflate failed: {}", what, e )
+  }
+
+  let mut sc = Compressor::new( Vec::new(), c );
+  let mut i = 0;
+  while i < data.len()
+  {
+    let n = std::cmp::min( data.len() - i, 1 + rng.below( 70000 ) );
+    sc.write_all( &data[ i..i + n ] ).unwrap();
+    i += n;
+  }
+  let sb = sc.finish().unwrap();
+
+  let mut inf = Inflater::with_options( &sb[..], &opts );
+  let mut ub = Vec::new();
+  let mut buf = vec![ 0; 1 + rng.below( 100000 ) ];
+  loop
+  {
+    let n = inf.read( &mut buf ).unwrap_or_else( |e| panic!( "{}: streaming inflate failed: {}", what, e ) );
+    if n == 0 { break; }
+    ub.extend_from_slice( &buf[ ..n ] );
+  }
+  assert!( ub == data, "{}: streaming round trip mismatch, len={}", what, data.len() );
+}
+
+/// Compress data with the streaming Compressor, writing pieces of random size with a flush ( of random mode ) after about
+/// one piece in three, and check the output inflates to data.
+fn check_flushes( data: &[u8], c: &Config, rng: &mut Rng, what: &str )
+{
+  let modes = [ Flush::Block, Flush::Partial, Flush::Sync, Flush::Full ];
+  let mut sc = Compressor::new( Vec::new(), c );
+  let mut i = 0;
+  while i < data.len()
+  {
+    let n = std::cmp::min( data.len() - i, 1 + rng.below( 300 ) );
+    sc.write_all( &data[ i..i + n ] ).unwrap();
+    if rng.below( 3 ) == 0 { sc.flush_mode( modes[ rng.below( 4 ) ] ).unwrap(); }
+    i += n;
+  }
+  let sb = sc.finish().unwrap();
+  match inflate::try_inflate_with( &sb, &inflate_options( c ) )
+  {
+    Ok( ub ) => assert!( ub == data, "{}: flushed round trip mismatch, len={}", what, data.len() ),
+    Err( e ) => panic!( "{}: inflate of flushed output failed: {}", what, e )
+  }
+}
+
+#[test]
+fn round_trip_all_options()
+{
+  let mut rng = Rng::new( 2 );
+  let inputs = inputs();
+  for ( oi, o ) in all_options().iter().enumerate()
+  {
+    for ( ii, data ) in inputs.iter().enumerate()
+    {
+      let mut c = Config::new();
+      c.options = o.clone();
+      check_round_trip( data, &mut c, &mut rng, &format!( "options {} input {}", oi, ii ) );
+    }
+  }
+}
+
+#[test]
+fn round_trip_containers_and_dictionary()
+{
+  let mut rng = Rng::new( 3 );
+  let dictionary = structured( &mut rng, 2, 50000 );
+  for container in [ Container::Raw, Container::Zlib, Container::Gzip ]
+  {
+    for level in [ 0, 1, 6, 9 ]
+    {
+      for data in inputs()
+      {
+        let mut c = Config::with_level( Level( level ) );
+        c.container = container;
+        check_round_trip( &data, &mut c, &mut rng, &format!( "{:?} level {}", container, level ) );
+        if container != Container::Gzip
+        {
+          c.dictionary = dictionary.clone();
+          check_round_trip( &data, &mut c, &mut rng, &format!( "{:?} level {} with dictionary", container, level ) );
+        }
+      }
+    }
+  }
+}
+
+#[test]
+fn round_trip_parallel()
+{
+  let mut rng = Rng::new( 4 );
+  for kind in 0..KINDS
+  {
+    let len = 300000 + rng.below( 100000 );
+    let data = structured( &mut rng, kind, len );
+    for container in [ Container::Raw, Container::Zlib, Container::Gzip ]
+    {
+      let mut c = Config::with_level( Level( 1 + rng.below( 6 ) as u8 ) );
+      c.container = container;
+      c.threads = 2 + rng.below( 3 );
+      check_round_trip( &data, &mut c, &mut rng, &format!( "parallel {:?} kind {}", container, kind ) );
+    }
+  }
+}
+
+/// Parallel compression ends each chunk ( of 128K ) with a sync point, and costs little compared with compressing serially.
+#[test]
+fn parallel_chunks()
+{
+  let mut rng = Rng::new( 15 );
+  let data = structured( &mut rng, 2, 0x20000 * 7 / 2 );
+  let mut c = Config::new();
+  let serial = compress::compress( &data, &mut c );
+  for dictionary in [ Vec::new(), structured( &mut rng, 2, 1000 ) ]
+  {
+    c.threads = 4;
+    c.dictionary = dictionary;
+    let cb = compress::compress( &data, &mut c );
+    let opts = inflate_options( &c );
+    assert!( inflate::try_inflate_with( &cb, &opts ).unwrap() == data );
+    assert!( cb.len() < serial.len() + serial.len() / 100, "parallel {} serial {}", cb.len(), serial.len() );
+    let ( items, _ ) = inflate::trace( &cb, &opts );
+    let syncs = items.iter().filter( |t| matches!( t, TraceItem::Stored{ len: 0, last: false, .. } ) ).count();
+    assert_eq!( syncs, 3 );
+  }
+}
+
+/// Property : any input round trips with any options. Each case has its own seed, reported on failure.
+#[test]
+fn round_trip_random()
+{
+  for seed in 100..300
+  {
+    let mut rng = Rng::new( seed );
+    let len = match rng.below( 4 ) { 0 => rng.below( 20 ), 1 => rng.below( 1000 ), _ => rng.below( 50000 ) };
+    let kind = rng.below( KINDS );
+    let data = structured( &mut rng, kind, len );
+    let mut c = Config::new();
+    c.options = random_options( &mut rng );
+    c.container = [ Container::Raw, Container::Zlib, Container::Gzip ][ rng.below( 3 ) ];
+    if c.container != Container::Gzip && rng.below( 4 ) == 0
+    {
+      let n = rng.below( 40000 );
+      c.dictionary = structured( &mut rng, kind, n );
+    }
+    check_round_trip( &data, &mut c, &mut rng, &format!( "seed {}", seed ) );
+  }
+}
+
+/// The streaming Compressor writes output as input is supplied, and matches refer back across segments ( to the
+/// sliding window of history ).
+#[test]
+fn compressor_streams_output()
+{
+  let mut rng = Rng::new( 7 );
+  let block = rng.bytes( 20000 );
+  let data = block.repeat( 50 );
+  let pipe = Pipe::new();
+  let mut sc = Compressor::new( pipe.clone(), &Config::new() );
+  let mut cb = Vec::new();
+  for piece in data.chunks( 10000 )
+  {
+    sc.write_all( piece ).unwrap();
+    cb.extend( pipe.drain() );
+  }
+  assert!( !cb.is_empty() ); // Output before finish.
+  sc.finish().unwrap();
+  cb.extend( pipe.drain() );
+  assert!( cb.len() < 2 * block.len() );
+  assert!( inflate::try_inflate( &cb ).unwrap() == data );
+
+  // The Adler-32 trailer is computed incrementally.
+  let check = crate::checksum::adler32( &data );
+  assert_eq!( cb[ cb.len() - 4.. ], check.to_be_bytes() );
+}
+
+/// Reader that returns at most a few bytes from each read, then an error if fail is set.
+/// Reader returning a few bytes at a time, then an error if fail is set. Once block bytes have been read, about one read
+/// in three returns WouldBlock.
+struct Trickle<'a>{ data: &'a [u8], rng: Rng, fail: bool, block: usize }
+
+impl Read for Trickle<'_>
+{
+  fn read( &mut self, buf: &mut [u8] ) -> std::io::Result<usize>
+  {
+    if self.block == 0 && self.rng.below( 3 ) == 0 { return Err( std::io::ErrorKind::WouldBlock.into() ); }
+    if self.data.is_empty() && self.fail { return Err( std::io::Error::other( "trickle" ) ); }
+    let n = std::cmp::min( std::cmp::min( buf.len(), self.data.len() ), 1 + self.rng.below( 7 ) );
+    buf[ ..n ].copy_from_slice( &self.data[ ..n ] );
+    self.data = &self.data[ n.. ];
+    self.block = self.block.saturating_sub( n );
+    Ok( n )
+  }
+}
+
+/// The streaming Inflater decodes input supplied a few bytes at a time, with copies reaching back through the window
+/// of history, and reports read errors.
+#[test]
+fn inflater_small_reads()
+{
+  let mut rng = Rng::new( 8 );
+  let block = rng.bytes( 30000 );
+  let mut data = block.repeat( 4 );
+  data.extend( structured( &mut rng, 4, 100000 ) );
+  let cb = compress::compress( &data, &mut Config::new() );
+
+  let mut inf = Inflater::new( Trickle{ data: &cb, rng: Rng::new( 9 ), fail: false, block: usize::MAX } );
+  let mut ub = Vec::new();
+  let mut buf = [ 0; 100 ];
+  loop
+  {
+    let n = inf.read( &mut buf ).unwrap();
+    if n == 0 { break; }
+    ub.extend_from_slice( &buf[ ..n ] );
+  }
+  assert!( ub == data );
+
+  // An error is returned by every later read, rather than read returning 0 as if the stream had ended.
+  let mut inf = Inflater::new( Trickle{ data: &cb[ ..cb.len() / 2 ], rng: Rng::new( 10 ), fail: true, block: usize::MAX } );
+  let mut ub = Vec::new();
+  for _ in 0..3 { assert_eq!( inf.read_to_end( &mut ub ).unwrap_err().to_string(), "trickle" ); }
+  assert!( data.starts_with( &ub ) );
+  let mut bad = cb.clone();
+  *bad.last_mut().unwrap() ^= 1;
+  let mut inf = Inflater::new( &bad[..] );
+  let mut ub = Vec::new();
+  assert_eq!( inf.read_to_end( &mut ub ).unwrap_err().to_string(), "Bad checksum" );
+  assert!( ub.len() < data.len() );
+  for _ in 0..3 { assert_eq!( inf.read( &mut buf ).unwrap_err().to_string(), "Bad checksum" ); }
+
+  // WouldBlock while decoding a block ( after its header ) is returned by read, and decoding continues on the next read.
+  let text = structured( &mut rng, 2, 60000 );
+  let mut c = Config::new();
+  c.container = Container::Raw;
+  c.options.dynamic_block_size = false;
+  c.options.block_size = 1 << 20;
+  let cb = compress::compress( &text, &mut c );
+  let ( items, _ ) = inflate::trace( &cb, &inflate_options( &c ) );
+  assert_eq!( items.iter().filter( |i| matches!( i, TraceItem::Dynamic{ .. } ) ).count(), 1 );
+  let mut inf = Inflater::with_options( Trickle{ data: &cb, rng: Rng::new( 11 ), fail: false, block: 1000 }, &inflate_options( &c ) );
+  let ( mut ub, mut blocked ) = ( Vec::new(), 0 );
+  loop
+  {
+    match inf.read( &mut buf )
+    {
+      Ok( 0 ) => break,
+      Ok( n ) => ub.extend_from_slice( &buf[ ..n ] ),
+      Err( e ) => { assert_eq!( e.kind(), std::io::ErrorKind::WouldBlock ); blocked += 1; }
+    }
+  }
+  assert!( ub == text && blocked > 100, "blocked {}", blocked );
+}
+
+#[test]
+fn checksums_and_combine()
+{
+  use crate::checksum::{adler32,adler32_update,adler32_combine,crc32,crc32_update,crc32_combine};
+  assert_eq!( adler32( b"Wikipedia" ), 0x11e60398 );
+  assert_eq!( crc32( b"123456789" ), 0xcbf43926 );
+
+  let adler_ref = | data: &[u8] |
+  {
+    let ( mut s1, mut s2 ) = ( 1u32, 0u32 );
+    for b in data { s1 = ( s1 + *b as u32 ) % 65521; s2 = ( s2 + s1 ) % 65521; }
+    s2 << 16 | s1
+  };
+  let crc_ref = | data: &[u8] |
+  {
+    let mut c = !0u32;
+    for b in data
+    {
+      c ^= *b as u32;
+      for _ in 0..8 { c = if c & 1 != 0 { ( c >> 1 ) ^ 0xedb88320 } else { c >> 1 }; }
+    }
+    !c
+  };
+
+  let mut rng = Rng::new( 16 );
+  let data = [ rng.bytes( 100000 ), vec![ 255; 100000 ] ].concat();
+  assert_eq!( adler32( &data ), adler_ref( &data ) );
+  assert_eq!( crc32( &data ), crc_ref( &data ) );
+  for split in [ 0, 1, 15, 16, 5552, 65521, 100000, 131042, data.len() - 1, data.len() ]
+  {
+    let ( a, b ) = data.split_at( split );
+    assert_eq!( adler32_update( adler32( a ), b ), adler32( &data ), "split {}", split );
+    assert_eq!( crc32_update( crc32( a ), b ), crc32( &data ), "split {}", split );
+    assert_eq!( adler32_combine( adler32( a ), adler32( b ), b.len() ), adler32( &data ), "split {}", split );
+    assert_eq!( crc32_combine( crc32( a ), crc32( b ), b.len() ), crc32( &data ), "split {}", split );
+  }
+}
+
+/// Output from zlib ( Python zlib.compress ), covering stored, fixed and dynamic blocks.
+#[test]
+fn decode_reference_zlib()
+{
+  let dynamic_input : Vec<u8> = ( 0..300 ).map( |i| b"abcdefghij"[ ( i * i + i / 7 ) % 10 ] ).collect();
+  let all_bytes : Vec<u8> = ( 0..=255 ).collect();
+  let mut stored = vec![ 120, 1, 1, 0, 1, 255, 254 ];
+  stored.extend_from_slice( &all_bytes );
+  stored.extend_from_slice( &[ 173, 246, 127, 129 ] );
+  let cases : Vec<( &[u8], Vec<u8> )> = vec!
+  [
+    ( b"", vec![ 120, 156, 3, 0, 0, 0, 0, 1 ] ),
+    ( b"a", vec![ 120, 156, 75, 4, 0, 0, 98, 0, 98 ] ),
+    ( b"hello hello hello hello", vec![ 120, 156, 203, 72, 205, 201, 201, 87, 200, 64, 39, 1, 104, 3, 8, 177 ] ),
+    ( &all_bytes, stored ),
+    ( &dynamic_input, vec![ 120, 218, 237, 202, 177, 1, 0, 48, 8, 2, 176, 91, 17, 69, 241, 255, 3, 28, 250, 70, 51, 7, 81, 219,
+      106, 136, 65, 193, 227, 232, 100, 13, 215, 75, 171, 228, 4, 126, 121, 229, 0, 223, 95, 118, 212 ] ),
+  ];
+  for ( data, cb ) in &cases
+  {
+    assert_eq!( inflate::try_inflate( cb ).unwrap(), *data );
+  }
+
+  let mut opts = InflateOptions::new();
+  opts.container = Container::Raw;
+  let raw = [ 43, 74, 44, 87, 72, 73, 77, 203, 73, 44, 73, 85, 40, 66, 176, 1 ];
+  assert_eq!( inflate::try_inflate_with( &raw, &opts ).unwrap(), b"raw deflate raw deflate" );
+
+  opts.container = Container::Gzip;
+  let gz = [ 31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 203, 72, 205, 201, 201, 87, 72, 175, 202, 44, 0, 0, 25, 106, 210, 223, 10, 0, 0, 0 ];
+  assert_eq!( inflate::try_inflate_with( &gz, &opts ).unwrap(), b"hello gzip" );
+}
+
+/// Output of flate2 at every level, in each container, decodes correctly.
+#[test]
+fn decode_flate2_output()
+{
+  use flate2::Compression;
+  for data in inputs()
+  {
+    for level in 0..=9
+    {
+      let mut e = flate2::write::ZlibEncoder::new( Vec::new(), Compression::new( level ) );
+      e.write_all( &data ).unwrap();
+      let cb = e.finish().unwrap();
+      assert!( inflate::try_inflate( &cb ).unwrap() == data );
+      assert!( crate::old_inflate::inflate( &cb ).unwrap() == data ); // The benchmark baseline.
+
+      let mut opts = InflateOptions::new();
+      opts.container = Container::Gzip;
+      let mut e = flate2::write::GzEncoder::new( Vec::new(), Compression::new( level ) );
+      e.write_all( &data ).unwrap();
+      assert!( inflate::try_inflate_with( &e.finish().unwrap(), &opts ).unwrap() == data );
+
+      opts.container = Container::Raw;
+      let mut e = flate2::write::DeflateEncoder::new( Vec::new(), Compression::new( level ) );
+      e.write_all( &data ).unwrap();
+      assert!( inflate::try_inflate_with( &e.finish().unwrap(), &opts ).unwrap() == data );
+    }
+  }
+}
+
+/// Copies at every short distance ( overlapping the output being written when the length exceeds the distance ) and
+/// at the maximum distance decode correctly, in fixed and dynamic blocks, with each decoding API.
+#[test]
+fn inflate_copies()
+{
+  use flate2::Compression;
+  let mut rng = Rng::new( 17 );
+  let mut data = Vec::new();
+  for period in 1..=300
+  {
+    let p = rng.bytes( period );
+    let n = 3 * period + rng.below( 300 );
+    data.extend( ( 0..n ).map( |i| p[ i % period ] ) );
+  }
+  data.extend( structured( &mut rng, 4, 100000 ) );
+  let mut cases = Vec::new();
+  for level in [ 1, 6, 9 ]
+  {
+    let mut e = flate2::write::ZlibEncoder::new( Vec::new(), Compression::new( level ) );
+    e.write_all( &data ).unwrap();
+    cases.push( ( e.finish().unwrap(), &data[..] ) );
+  }
+  let short = &data[ ..200 ]; // Fixed codes.
+  cases.push( ( compress::compress( short, &mut Config::new() ), short ) );
+  for ( cb, data ) in cases
+  {
+    assert!( inflate::try_inflate( &cb ).unwrap() == data );
+    let mut ob = vec![ 0; data.len() ];
+    assert_eq!( inflate::inflate_into( &cb, &mut ob ).unwrap(), data.len() );
+    assert!( ob == data );
+    let mut ub = Vec::new();
+    Inflater::new( &cb[..] ).read_to_end( &mut ub ).unwrap();
+    assert!( ub == data );
+  }
+}
+
+/// Output of compress decodes correctly with flate2.
+#[test]
+fn flate2_decodes_output()
+{
+  for data in inputs()
+  {
+    for level in 0..=9
+    {
+      let mut c = Config::with_level( Level( level ) );
+      let mut ub = Vec::new();
+      flate2::read::ZlibDecoder::new( &compress::compress( &data, &mut c )[..] ).read_to_end( &mut ub ).unwrap();
+      assert!( ub == data );
+
+      c.container = Container::Gzip;
+      let mut ub = Vec::new();
+      flate2::read::GzDecoder::new( &compress::compress( &data, &mut c )[..] ).read_to_end( &mut ub ).unwrap();
+      assert!( ub == data );
+    }
+  }
+}
+
+/// Every proper prefix of a valid stream is an error ( not a panic, and not success ).
+#[test]
+fn truncated_input_is_error()
+{
+  let mut rng = Rng::new( 5 );
+  let data = structured( &mut rng, 6, 5000 );
+  for container in [ Container::Zlib, Container::Gzip ]
+  {
+    let mut c = Config::new();
+    c.container = container;
+    let cb = compress::compress( &data, &mut c );
+    let opts = inflate_options( &c );
+    for n in 0..cb.len()
+    {
+      assert!( inflate::try_inflate_with( &cb[ ..n ], &opts ).is_err(), "{:?} prefix {}", container, n );
+    }
+  }
+}
+
+/// Raw deflate stream with a dynamic block header, given the lengths of the code length codes ( in RFC 1951 order ) and
+/// then bits ( pairs of bit count and value ).
+fn dynamic_header( clen: &[u64], bits: &[( u8, u64 )] ) -> Vec<u8>
+{
+  let mut bs = BitStream::new( 100 );
+  bs.write( 3, 0b101 ); // Last block, dynamic.
+  bs.write( 5, 0 ); // 257 literal/length codes.
+  bs.write( 5, 0 ); // 1 distance code.
+  bs.write( 4, clen.len() as u64 - 4 );
+  for l in clen { bs.write( 3, *l ); }
+  for ( n, v ) in bits { bs.write( *n, *v ); }
+  bs.write( 32, 0 );
+  bs.flush();
+  bs.bytes
+}
+
+/// Each kind of invalid stream gives its own error.
+#[test]
+fn inflate_errors()
+{
+  let mut opts = InflateOptions::new();
+  opts.container = Container::Raw;
+  // The error from try_inflate_with, which the Inflater also returns, from every read.
+  let err = | data: &[u8], opts: &InflateOptions | 
+  {
+    let e = inflate::try_inflate_with( data, opts ).unwrap_err();
+    let mut inf = Inflater::with_options( data, opts );
+    let mut out = Vec::new();
+    for _ in 0..3 { assert_eq!( inf.read_to_end( &mut out ).unwrap_err().to_string(), e.to_string() ); }
+    e
+  };
+
+  assert!( matches!( err( &[], &opts ), InflateError::Truncated ) );
+  assert!( matches!( err( &[ 0b111 ], &opts ), InflateError::InvalidBlockType ) );
+  assert!( matches!( err( &[ 1, 1, 0, 0, 0, b'a' ], &opts ), InflateError::BadStoredLength ) );
+  assert!( matches!( err( &[ 1, 1, 0, 0xfe, 0xff ], &opts ), InflateError::Truncated ) );
+
+  // Code lengths 1,1,1 for the code length symbols 16, 17 and 18 are over-subscribed.
+  assert!( matches!( err( &dynamic_header( &[ 1, 1, 1, 0 ], &[] ), &opts ), InflateError::InvalidHuffman ) );
+  // Code 16 ( repeat the previous length ) cannot be the first length.
+  assert!( matches!( err( &dynamic_header( &[ 1, 1, 0, 0 ], &[ ( 1, 0 ), ( 2, 0 ) ] ), &opts ), InflateError::InvalidHuffman ) );
+
+  // A copy from before the start of the output ( here, into a dictionary that is not supplied ).
+  let mut c = Config::new();
+  c.container = Container::Raw;
+  c.dictionary = b"hello world".to_vec();
+  assert!( matches!( err( &compress::compress( b"hello world", &mut c ), &opts ), InflateError::BadDistance ) );
+
+  let mut cb = compress::compress( b"hello world", &mut Config::new() );
+  *cb.last_mut().unwrap() ^= 1;
+  assert!( matches!( inflate::try_inflate( &cb ), Err( InflateError::ChecksumMismatch ) ) );
+  assert!( matches!( err( &cb, &InflateOptions::new() ), InflateError::ChecksumMismatch ) );
+
+  // The output before an error is not returned as a complete stream.
+  let cb = compress::compress( &[ 7; 100000 ], &mut Config::new() );
+  let e = err( &cb[ ..cb.len() - 1 ], &InflateOptions::new() );
+  assert!( matches!( e, InflateError::Truncated ) );
+}
+
+/// Levels trade speed for size : level 0 only stores, higher levels give smaller output, and the level is recorded in
+/// the zlib and gzip headers.
+#[test]
+fn levels()
+{
+  let mut rng = Rng::new( 12 );
+  let data = structured( &mut rng, 4, 100000 );
+  let mut previous = usize::MAX;
+  for level in 0..=9
+  {
+    let mut c = Config::with_level( Level( level ) );
+    assert_eq!( c.options.store_only, level == 0 );
+    assert_eq!( c.options.lazy_match, level >= 4 );
+    let cb = compress::compress( &data, &mut c );
+    assert_eq!( inflate::zlib_header( &cb ).unwrap().level, Level( level ).flevel() );
+    assert!( cb.len() <= previous, "level {} size {} previous {}", level, cb.len(), previous );
+    previous = cb.len();
+    if level == 0
+    {
+      let ( items, _ ) = inflate::trace( &cb, &InflateOptions::new() );
+      assert!( items.iter().all( |t| matches!( t, TraceItem::Stored{..} | TraceItem::Literal( _ ) | TraceItem::EndOfBlock ) ) );
+    }
+  }
+  assert_eq!( Config::with_level( Level::FASTEST ).gzip.xfl, 4 );
+  assert_eq!( Config::with_level( Level::BEST ).gzip.xfl, 2 );
+  assert!( Level::NONE.options().store_only && Level::DEFAULT.options().probe_max < Level::BEST.options().probe_max );
+}
+
+/// Splitting blocks where the symbol frequencies change gives smaller output than fixed size blocks, on input made of
+/// sections with different statistics ( and boundaries not at multiples of the block size ).
+#[test]
+fn block_splitting_smaller()
+{
+  let mut rng = Rng::new( 14 );
+  let mut data = Vec::new();
+  for i in 0..12
+  {
+    let len = 5000 + rng.below( 20000 );
+    data.extend( structured( &mut rng, i % 4, len ) );
+  }
+  for level in [ 6, 9 ]
+  {
+    let mut c = Config::with_level( Level( level ) );
+    c.options.dynamic_block_size = false;
+    let fixed = compress::compress( &data, &mut c );
+    c.options.dynamic_block_size = true;
+    c.stats = Some( CompressStats::new() );
+    let split = compress::compress( &data, &mut c );
+    assert!( inflate::try_inflate( &split ).unwrap() == data );
+    assert!( split.len() < fixed.len(), "level {} split {} fixed {}", level, split.len(), fixed.len() );
+    // Block sizes are not limited to powers of two.
+    assert!( c.stats.unwrap().blocks.iter().any( |b| !( b.input_end - b.input_start ).is_power_of_two() ) );
+  }
+}
+
+/// Optimal parsing gives smaller output than lazy matching, and more passes do not make it larger.
+#[test]
+fn optimal_parsing_smaller()
+{
+  let mut rng = Rng::new( 13 );
+  let data : Vec<u8> = ( 0..KINDS ).flat_map( |kind| structured( &mut rng, kind, 20000 ) ).collect();
+  let size = | passes |
+  {
+    let mut c = Config::with_level( Level( 9 ) );
+    c.options.optimal_passes = passes;
+    let cb = compress::compress( &data, &mut c );
+    assert!( inflate::try_inflate( &cb ).unwrap() == data );
+    cb.len()
+  };
+  let ( lazy, one, three ) = ( size( 0 ), size( 1 ), size( 3 ) );
+  assert!( one < lazy && three <= one, "lazy {} optimal {} {}", lazy, one, three );
+}
+
+/// Blocks are written stored or with fixed codes when that is smaller than dynamic codes, so random input grows by only
+/// a little ( 5 bytes per stored block, which are limited to 65535 bytes ), and short input uses the fixed codes.
+#[test]
+fn stored_and_fixed_blocks()
+{
+  let mut rng = Rng::new( 11 );
+  let blocks = | cb: &[u8] |
+  {
+    let ( items, error ) = inflate::trace( cb, &InflateOptions::new() );
+    assert!( error.is_none() );
+    items.into_iter().filter( |t| matches!( t, TraceItem::Stored{..} | TraceItem::Fixed{..} | TraceItem::Dynamic{..} ) )
+      .collect::<Vec<_>>()
+  };
+  for level in 1..=9
+  {
+    let data = rng.bytes( 200000 );
+    let cb = compress::compress( &data, &mut Config::with_level( Level( level ) ) );
+    assert!( cb.len() <= data.len() + data.len() / 1000 + 20, "level {} size {}", level, cb.len() );
+    assert!( blocks( &cb ).iter().all( |b| matches!( b, TraceItem::Stored{ len, .. } if *len <= 0xffff ) ) );
+
+    let cb = compress::compress( b"a short message", &mut Config::with_level( Level( level ) ) );
+    assert!( matches!( blocks( &cb )[..], [ TraceItem::Fixed{ last: true, .. } ] ) );
+  }
+}
+
+/// A preset dictionary lets small messages refer to text they have in common, in each API and container.
+#[test]
+fn dictionary_helps_small_messages()
+{
+  let dictionary = br#"{"id":0,"name":"","email":"","created":"2020-01-01T00:00:00Z","tags":[]}"#.to_vec();
+  let msg = br#"{"id":1234,"name":"Alice","email":"alice@example.com","created":"2020-06-01T12:00:00Z","tags":["a"]}"#;
+  for container in [ Container::Raw, Container::Zlib ]
+  {
+    let mut c = Config::new();
+    c.container = container;
+    let plain = compress::compress( msg, &mut c );
+    c.dictionary = dictionary.clone();
+    let cb = compress::compress( msg, &mut c );
+    assert!( cb.len() + 20 < plain.len(), "{:?} {} {}", container, cb.len(), plain.len() );
+    assert_eq!( inflate::try_inflate_with( &cb, &inflate_options( &c ) ).unwrap(), msg );
+
+    let mut sc = Compressor::new( Vec::new(), &c );
+    sc.write_all( msg ).unwrap();
+    assert_eq!( sc.finish().unwrap(), cb );
+  }
+}
+
+/// The zlib header is validated, its fields are reported, and FDICT streams need the matching dictionary.
+#[test]
+fn zlib_header_fields()
+{
+  let h = inflate::zlib_header( &[ 0x78, 0x9c ] ).unwrap();
+  assert_eq!( ( h.window_size, h.level, h.dictid ), ( 0x8000, 2, None ) );
+  let h = inflate::zlib_header( &[ 0x28, 0x15 ] ).unwrap();
+  assert_eq!( ( h.window_size, h.level ), ( 1024, 0 ) );
+  assert!( matches!( inflate::zlib_header( &[ 0x78 ] ), Err( InflateError::Truncated ) ) );
+  assert!( matches!( inflate::zlib_header( &[ 0x78, 0x9d ] ), Err( InflateError::InvalidHeader ) ) ); // FCHECK.
+  assert!( matches!( inflate::zlib_header( &[ 0x77, 0x85 ] ), Err( InflateError::InvalidHeader ) ) ); // Method 7.
+  assert!( matches!( inflate::zlib_header( &[ 0x88, 0x98 ] ), Err( InflateError::InvalidHeader ) ) ); // 64K window.
+
+  let data = b"preset dictionary, preset dictionary";
+  let mut c = Config::new();
+  c.dictionary = b"preset dictionary".to_vec();
+  let cb = compress::compress( data, &mut c );
+  let id = crate::checksum::adler32( &c.dictionary );
+  assert_eq!( inflate::zlib_header( &cb ).unwrap().dictid, Some( id ) );
+  let mut opts = InflateOptions::new();
+  assert!( matches!( inflate::try_inflate_with( &cb, &opts ), Err( InflateError::WrongDictionary( x ) ) if x == id ) );
+  opts.dictionary = b"another dictionary".to_vec();
+  assert!( matches!( inflate::try_inflate_with( &cb, &opts ), Err( InflateError::WrongDictionary( x ) ) if x == id ) );
+  opts.dictionary = c.dictionary.clone();
+  assert_eq!( inflate::try_inflate_with( &cb, &opts ).unwrap(), data );
+
+  // The streaming Inflater reports the same header.
+  let mut inf = Inflater::with_options( &cb[..], &opts );
+  let mut out = Vec::new();
+  inf.read_to_end( &mut out ).unwrap();
+  assert_eq!( out, data );
+  assert_eq!( inf.zlib_header(), inflate::zlib_header( &cb ).ok().as_ref() );
+}
+
+/// Gzip header fields round trip, and a header that is cut short or has a bad CRC is an error.
+#[test]
+fn gzip_header_fields()
+{
+  let data = b"gzip header fields";
+  let mut c = Config::new();
+  c.container = Container::Gzip;
+  c.gzip.text = true;
+  c.gzip.mtime = 1234567890;
+  c.gzip.xfl = 2;
+  c.gzip.os = 3;
+  c.gzip.extra = Some( b"AB\x02\x00xy".to_vec() );
+  c.gzip.filename = Some( b"data.txt".to_vec() );
+  c.gzip.comment = Some( b"a comment".to_vec() );
+  c.gzip.hcrc = true;
+  let gb = compress::compress( data, &mut c );
+  let opts = inflate_options( &c );
+  let mut inf = Inflater::with_options( &gb[..], &opts );
+  let mut ub = Vec::new();
+  inf.read_to_end( &mut ub ).unwrap();
+  assert_eq!( ub, data );
+  assert_eq!( inf.gzip_header(), Some( &c.gzip ) );
+
+  // flate2 reads the same fields.
+  let mut d = flate2::read::GzDecoder::new( &gb[..] );
+  let mut ub = Vec::new();
+  d.read_to_end( &mut ub ).unwrap();
+  let h = d.header().unwrap();
+  assert_eq!( ( h.filename(), h.comment(), h.mtime() ), ( Some( &b"data.txt"[..] ), Some( &b"a comment"[..] ), 1234567890 ) );
+
+  let header_len = c.gzip.to_bytes().len();
+  for n in 0..header_len
+  {
+    assert!( matches!( inflate::try_inflate_with( &gb[ ..n ], &opts ), Err( InflateError::Truncated ) ), "header prefix {}", n );
+  }
+  let mut bad = gb.clone();
+  bad[ header_len - 1 ] ^= 1;
+  assert!( matches!( inflate::try_inflate_with( &bad, &opts ), Err( InflateError::InvalidHeader ) ) );
+  let mut bad = gb.clone();
+  bad[ 0 ] = 0x1e;
+  assert!( matches!( inflate::try_inflate_with( &bad, &opts ), Err( InflateError::InvalidHeader ) ) );
+
+  // The trailer has the CRC-32 and length of the data.
+  let n = gb.len();
+  assert_eq!( gb[ n - 8..n - 4 ], crate::checksum::crc32( data ).to_le_bytes() );
+  assert_eq!( gb[ n - 4.. ], ( data.len() as u32 ).to_le_bytes() );
+}
+
+/// Inflate arbitrary bytes, and corrupted or truncated valid streams, with each API. Errors are expected, panics are not.
+#[test]
+fn inflate_never_panics()
+{
+  let mut rng = Rng::new( 6 );
+  let mut valid = Vec::new();
+  for kind in 0..KINDS
+  {
+    let data = structured( &mut rng, kind, 3000 );
+    for level in [ 0, 1, 6, 9 ]
+    {
+      let mut c = Config::with_level( Level( level ) );
+      c.container = Container::Raw;
+      valid.push( compress::compress( &data, &mut c ) );
+    }
+  }
+  for i in 0..20000
+  {
+    let mut b = if i % 2 == 0
+    {
+      let n = rng.below( 300 );
+      rng.bytes( n )
+    } else {
+      valid[ rng.below( valid.len() ) ].clone()
+    };
+    for _ in 0..rng.below( 4 )
+    {
+      if !b.is_empty() { let j = rng.below( b.len() ); b[ j ] ^= 1 << rng.below( 8 ); }
+    }
+    if rng.below( 3 ) == 0 { let n = rng.below( b.len() + 1 ); b.truncate( n ); }
+    inflate_any( &b );
+  }
+}
+
+/// Inflate data in each container with each API, ignoring the result.
+fn inflate_any( data: &[u8] )
+{
+  for container in [ Container::Raw, Container::Zlib, Container::Gzip ]
+  {
+    let mut opts = InflateOptions::new();
+    opts.container = container;
+    opts.max_output = 1 << 20;
+    let _ = inflate::try_inflate_with( data, &opts );
+    let mut ob = vec![ 0; 1000 ];
+    let _ = inflate::inflate_into_with( data, &mut ob, &opts );
+    let mut ub = Vec::new();
+    let _ = Inflater::with_options( data, &opts ).read_to_end( &mut ub );
+  }
+}
+
+/// Inflate into a buffer of known size, or append to a Vec with a cap on the bytes appended ( the Vec only grows as
+/// output is produced ). Output that does not fit is an error, and a failed append leaves the Vec unchanged.
+#[test]
+fn inflate_into_and_append()
+{
+  let mut rng = Rng::new( 18 );
+  let data = structured( &mut rng, 6, 50000 );
+  let mut c = Config::new();
+  for dictionary in [ Vec::new(), structured( &mut rng, 6, 2000 ) ]
+  {
+    c.dictionary = dictionary;
+    let cb = compress::compress( &data, &mut c );
+    let opts = inflate_options( &c );
+
+    let mut ob = vec![ 0; data.len() + 10 ];
+    assert_eq!( inflate::inflate_into_with( &cb, &mut ob, &opts ).unwrap(), data.len() );
+    assert!( ob[ ..data.len() ] == data[..] );
+    let n = data.len() - 1;
+    assert!( matches!( inflate::inflate_into_with( &cb, &mut ob[ ..n ], &opts ), Err( InflateError::OutputOverflow ) ) );
+
+    for max in [ data.len(), usize::MAX ]
+    {
+      let mut ab = b"prefix".to_vec();
+      assert_eq!( inflate::inflate_append_with( &cb, &mut ab, max, &opts ).unwrap(), data.len() );
+      assert!( ab[ ..6 ] == b"prefix"[..] && ab[ 6.. ] == data[..] );
+      assert!( ab.capacity() < 4 * data.len() );
+    }
+    let mut ab = b"prefix".to_vec();
+    assert!( matches!( inflate::inflate_append_with( &cb, &mut ab, n, &opts ), Err( InflateError::OutputOverflow ) ) );
+    assert_eq!( ab, b"prefix" );
+    assert!( matches!( inflate::inflate_append_with( &cb[ ..cb.len() - 1 ], &mut ab, usize::MAX, &opts ), 
+      Err( InflateError::Truncated ) ) );
+    assert_eq!( ab, b"prefix" );
+  }
+  let mut ab = Vec::new();
+  assert_eq!( inflate::inflate_append( &compress::compress( &data, &mut Config::new() ), &mut ab, usize::MAX ).unwrap(), data.len() );
+}
+
+#[test]
+fn limits()
+{
+  let data = vec![ 5; 1 << 20 ];
+  let cb = compress::compress( &data, &mut Config::new() );
+  let mut opts = InflateOptions::new();
+  opts.max_output = data.len() - 1;
+  assert!( matches!( inflate::try_inflate_with( &cb, &opts ), Err( InflateError::LimitExceeded( inflate::Limit::Output ) ) ) );
+  opts.max_output = data.len();
+  assert!( inflate::try_inflate_with( &cb, &opts ).unwrap() == data );
+
+  // The limits apply to each API, and to stored, fixed and dynamic blocks.
+  let mut rng = Rng::new( 19 );
+  let mut c = Config::new();
+  let mixed = [ rng.bytes( 0x11000 ), vec![ 5; 0x30000 ] ].concat();
+  for ( data, level ) in [ ( &mixed, 6 ), ( &mixed, 0 ), ( &vec![ 5; 1 << 20 ], 6 ), ( &b"ab".repeat( 20000 ), 1 ) ]
+  {
+    c.options = Level( level ).options();
+    c.options.block_size = 0x1000;
+    let cb = compress::compress( data, &mut c );
+    let ( items, _ ) = inflate::trace( &cb, &InflateOptions::new() );
+    let blocks = items.iter().filter( |t| matches!( t, TraceItem::Stored{..} | TraceItem::Fixed{..} | TraceItem::Dynamic{..} ) ).count();
+    let ratio = data.len() / cb.len();
+    let check = | opts: &InflateOptions, limit: Option<inflate::Limit> |
+    {
+      let what = format!( "level {} len {} limit {:?}", level, data.len(), limit );
+      match limit
+      {
+        None => assert!( inflate::try_inflate_with( &cb, opts ).unwrap() == *data, "{}", what ),
+        Some( l ) => 
+        {
+          assert!( matches!( inflate::try_inflate_with( &cb, opts ), Err( InflateError::LimitExceeded( x ) ) if x == l ), "{}", what );
+          let mut ob = vec![ 0; data.len() ];
+          assert!( matches!( inflate::inflate_into_with( &cb, &mut ob, opts ), Err( InflateError::LimitExceeded( x ) ) if x == l ) );
+          let e = Inflater::with_options( &cb[..], opts ).read_to_end( &mut Vec::new() ).unwrap_err();
+          assert_eq!( e.to_string(), InflateError::LimitExceeded( l ).to_string(), "{}", what );
+        }
+      }
+    };
+    let mut opts = InflateOptions::new();
+    opts.max_output = data.len() - 1;
+    check( &opts, Some( inflate::Limit::Output ) );
+    opts.max_output = usize::MAX;
+    opts.max_blocks = blocks - 1;
+    check( &opts, Some( inflate::Limit::Blocks ) );
+    opts.max_blocks = blocks;
+    check( &opts, None );
+    if data.len() > 0x20000 && ratio > 2
+    {
+      opts.max_ratio = ratio - 1;
+      check( &opts, Some( inflate::Limit::Ratio ) );
+      opts.max_ratio = ratio + 1;
+      check( &opts, None );
+    }
+  }
+}
+
+/// Buffer shared between a Compressor and an Inflater, so flushed output can be decoded as soon as it is written.
+#[derive(Clone)]
+struct Pipe( std::rc::Rc<std::cell::RefCell<std::collections::VecDeque<u8>>> );
+
+impl Pipe
+{
+  fn new() -> Pipe
+  {
+    Pipe( Default::default() )
+  }
+
+  fn drain( &self ) -> Vec<u8>
+  {
+    self.0.borrow_mut().drain(..).collect()
+  }
+}
+
+impl Write for Pipe
+{
+  fn write( &mut self, data: &[u8] ) -> std::io::Result<usize>
+  {
+    self.0.borrow_mut().extend( data );
+    Ok( data.len() )
+  }
+
+  fn flush( &mut self ) -> std::io::Result<()> { Ok(()) }
+}
+
+impl Read for Pipe
+{
+  fn read( &mut self, buf: &mut [u8] ) -> std::io::Result<usize>
+  {
+    self.0.borrow_mut().read( buf )
+  }
+}
+
+#[test]
+fn flush_modes()
+{
+  let mut rng = Rng::new( 21 );
+  let containers = [ Container::Raw, Container::Zlib, Container::Gzip ];
+  for ( i, level ) in [ 0, 1, 6, 9 ].iter().enumerate()
+  {
+    for mode in [ Flush::Block, Flush::Partial, Flush::Sync, Flush::Full ]
+    {
+      let what = format!( "level {} {:?}", level, mode );
+      let mut c = Config::with_level( Level( *level ) );
+      c.container = containers[ i % 3 ];
+      if *level == 9 { c.options.optimal_passes = 2; }
+      let mut opts = inflate_options( &c );
+      opts.partial = true;
+
+      // Each message is decoded as soon as it is flushed, except for Block ( which may hold back the end of a code ).
+      let pipe = Pipe::new();
+      let mut sc = Compressor::new( pipe.clone(), &c );
+      let mut inf = Inflater::with_options( pipe.clone(), &opts );
+      let ( mut input, mut output ) = ( Vec::new(), Vec::new() );
+      for m in 0..8
+      {
+        let kind = rng.below( KINDS );
+        let len = rng.below( if m == 3 { 150000 } else { 3000 } );
+        let msg = structured( &mut rng, kind, len );
+        sc.write_all( &msg ).unwrap();
+        sc.flush_mode( mode ).unwrap();
+        input.extend_from_slice( &msg );
+        if mode == Flush::Block { continue; }
+        let mut ub = Vec::new();
+        inf.read_to_end( &mut ub ).unwrap_or_else( |e| panic!( "{}: {}", what, e ) );
+        assert!( ub == msg, "{}: message {} len {} decoded {}", what, m, msg.len(), ub.len() );
+        output.extend_from_slice( &ub );
+      }
+      sc.finish().unwrap();
+      inf.read_to_end( &mut output ).unwrap();
+      assert!( output == input, "{}", what );
+    }
+  }
+}
+
+#[test]
+fn multiple_members_and_trailing_data()
+{
+  let mut rng = Rng::new( 22 );
+  let mut c = Config::new();
+  c.container = Container::Gzip;
+  let parts : Vec<Vec<u8>> = ( 0..4 ).map( |i| { let len = [ 0, 1000, 100000, 10 ][ i ]; structured( &mut rng, i, len ) } ).collect();
+  let members : Vec<Vec<u8>> = parts.iter().map( |p| compress::compress( p, &mut c ) ).collect();
+  let all = parts.concat();
+  let mut opts = inflate_options( &c );
+
+  for trailing in [ &b""[..], &[ 0; 10 ], b"junk", &[ 0x1f ] ]
+  {
+    let data = [ members.concat(), trailing.to_vec() ].concat();
+    let end = data.len() - trailing.len();
+
+    // Only the first member, unless multi_member is set.
+    opts.multi_member = false;
+    let ( ub, used ) = inflate::try_inflate_stream( &data, &opts ).unwrap();
+    assert!( ub == parts[ 0 ] && used == members[ 0 ].len() );
+    opts.multi_member = true;
+    let ( ub, used ) = inflate::try_inflate_stream( &data, &opts ).unwrap();
+    assert!( ub == all && used == end );
+
+    // Streaming, the input read ahead is returned with the rest of the input.
+    let mut inf = Inflater::with_options( &data[..], &opts );
+    let mut ub = Vec::new();
+    let mut buf = vec![ 0; 1 + rng.below( 5000 ) ];
+    loop
+    {
+      let n = inf.read( &mut buf ).unwrap();
+      if n == 0 { break; }
+      ub.extend_from_slice( &buf[ ..n ] );
+    }
+    assert!( ub == all );
+    assert_eq!( inf.consumed(), end );
+    let mut rest = Vec::new();
+    inf.into_remainder().read_to_end( &mut rest ).unwrap();
+    assert_eq!( rest, trailing );
+  }
+
+  // A member that is not valid is an error, even if it is the last.
+  let data = [ members.concat(), members[ 1 ][ ..20 ].to_vec() ].concat();
+  assert!( matches!( inflate::try_inflate_stream( &data, &opts ), Err( InflateError::Truncated ) ) );
+  let mut data = members.concat();
+  let n = members[ 0 ].len() + members[ 1 ].len() - 1;
+  data[ n ] ^= 1; // Corrupt the length in the trailer of the second member.
+  assert!( matches!( inflate::try_inflate_stream( &data, &opts ), Err( InflateError::ChecksumMismatch ) ) );
+
+  // Other containers report the input used.
+  for container in [ Container::Raw, Container::Zlib ]
+  {
+    c.container = container;
+    let cb = compress::compress( &parts[ 1 ], &mut c );
+    let data = [ &cb[..], &cb ].concat();
+    let ( ub, used ) = inflate::try_inflate_stream( &data, &inflate_options( &c ) ).unwrap();
+    assert!( ub == parts[ 1 ] && used == cb.len() );
+  }
+}
+
+#[test]
+fn sync_and_full_flush()
+{
+  let mut rng = Rng::new( 22 );
+  let msg = structured( &mut rng, 4, 5000 );
+  let mut c = Config::new();
+  c.container = Container::Raw;
+  let mut opts = inflate_options( &c );
+  opts.partial = true;
+
+  // A sync flush ends with an empty stored block, which a flushed prefix can be decoded without.
+  let pipe = Pipe::new();
+  let mut sc = Compressor::new( pipe.clone(), &c );
+  sc.write_all( &msg ).unwrap();
+  sc.flush().unwrap();
+  let part = pipe.drain();
+  assert!( part.ends_with( &[ 0, 0, 0xff, 0xff ] ) );
+  assert!( inflate::try_inflate_with( &part, &opts ).unwrap() == msg );
+  assert!( inflate::try_inflate_with( &part[ ..part.len() - 4 ], &opts ).unwrap() == msg );
+  assert!( matches!( inflate::try_inflate_with( &part, &inflate_options( &c ) ), Err( InflateError::Truncated ) ) );
+
+  // After a full flush, the rest of the stream does not refer to earlier input, so it can be decoded on its own.
+  sc.write_all( &msg ).unwrap();
+  sc.flush_mode( Flush::Full ).unwrap();
+  let part = pipe.drain();
+  assert!( part.len() < msg.len() / 2 ); // Refers to the first message.
+  sc.write_all( &msg ).unwrap();
+  sc.finish().unwrap();
+  let rest = pipe.drain();
+  assert!( inflate::try_inflate_with( &rest, &opts ).unwrap() == msg );
+
+  // Input ending within a block is still an error.
+  assert!( matches!( inflate::try_inflate_with( &rest[ ..rest.len() / 2 ], &opts ), Err( InflateError::Truncated ) ) );
+}
+
+/// Sum of 2^-length over the used symbols, scaled by 2^MAX_BITS ( equal to 2^MAX_BITS for a complete code ).
+fn kraft( lengths: &[u8] ) -> u64
+{
+  lengths.iter().filter( |l| **l > 0 ).map( |l| 1 << ( huffman::MAX_BITS - *l as usize ) ).sum()
+}
+
+/// Check code lengths computed from freq, then write the lengths and random symbols ( weighted by freq ) and read them back.
+fn check_huffman( freq: &[u32], limit: usize, rng: &mut Rng ) -> Vec<u8>
+{
+  let enc = Encoder::from_frequencies( freq, limit ).unwrap();
+  let used : Vec<usize> = ( 0..freq.len() ).filter( |s| freq[ *s ] > 0 ).collect();
+  for ( sym, len ) in enc.lengths.iter().enumerate()
+  {
+    assert!( ( *len > 0 ) == ( freq[ sym ] > 0 ) && *len as usize <= limit, "symbol {} limit {}", sym, limit );
+  }
+  if used.len() > 1 { assert_eq!( kraft( &enc.lengths ), 1 << huffman::MAX_BITS ); }
+
+  let mut symbols = Vec::new();
+  for _ in 0..1000.min( used.len() * 20 )
+  {
+    symbols.push( used[ rng.below( used.len() ) ] );
+  }
+  let mut bs = BitStream::new( 1000 );
+  enc.write_lengths( &mut bs );
+  for sym in &symbols { enc.encode( *sym, &mut bs ); }
+  bs.flush();
+
+  let mut input = InputBitStream::new( &bs.bytes[..] );
+  let dec = Decoder::read( &mut input, freq.len() ).unwrap();
+  for sym in &symbols { assert_eq!( dec.decode( &mut input ).unwrap(), *sym ); }
+  enc.lengths
+}
+
+fn cost( freq: &[u32], lengths: &[u8] ) -> u64
+{
+  freq.iter().zip( lengths ).map( |( f, l )| *f as u64 * *l as u64 ).sum()
+}
+
+#[test]
+fn huffman_one_symbol()
+{
+  let mut rng = Rng::new( 1 );
+  for ( nsym, sym ) in [ ( 1, 0 ), ( 2, 1 ), ( 300, 123 ), ( 70000, 69999 ) ]
+  {
+    let mut freq = vec![ 0; nsym ];
+    freq[ sym ] = 7;
+    for limit in [ 1, 15, huffman::MAX_BITS ]
+    {
+      let lengths = check_huffman( &freq, limit, &mut rng );
+      assert_eq!( kraft( &lengths ), 1 << ( huffman::MAX_BITS - 1 ) );
+    }
+
+    // The code for the symbol is a single 0 bit, a 1 bit is not a valid code.
+    let dec = Decoder::new( &huffman::code_lengths( &freq, 15 ).unwrap() ).unwrap();
+    assert_eq!( dec.decode( &mut InputBitStream::new( &[ 0xfe ][..] ) ), Ok( sym ) );
+    assert_eq!( dec.decode( &mut InputBitStream::new( &[ 0x01 ][..] ) ), Err( HuffmanError::InvalidCode ) );
+  }
+
+  // No symbols used.
+  let dec = Decoder::new( &huffman::code_lengths( &[ 0; 10 ], 15 ).unwrap() ).unwrap();
+  assert_eq!( dec.decode( &mut InputBitStream::new( &[ 0 ][..] ) ), Err( HuffmanError::InvalidCode ) );
+}
+
+#[test]
+fn huffman_all_equal()
+{
+  let mut rng = Rng::new( 2 );
+  for nsym in [ 2, 3, 5, 256, 300, 1000, 1 << 16, 100000 ]
+  {
+    let bits = 64 - ( nsym as u64 - 1 ).leading_zeros() as usize; // Minimum limit.
+    for limit in [ bits, bits + 1, huffman::MAX_BITS ]
+    {
+      let lengths = check_huffman( &vec![ 1000; nsym ], limit, &mut rng );
+
+      // Lengths differ by at most one bit.
+      let min = *lengths.iter().min().unwrap() as usize;
+      let max = *lengths.iter().max().unwrap() as usize;
+      assert!( max == bits && max - min <= 1, "nsym {} limit {}", nsym, limit );
+    }
+    assert_eq!( huffman::code_lengths( &vec![ 1; nsym ], bits - 1 ), Err( HuffmanError::InvalidLimit ) );
+  }
+}
+
+#[test]
+fn huffman_random()
+{
+  let mut rng = Rng::new( 3 );
+  for nsym in [ 2, 19, 30, 288, 1000, 5000 ]
+  {
+    for _ in 0..5
+    {
+      // Geometric frequencies give long codes when unlimited.
+      let freq : Vec<u32> = ( 0..nsym ).map( |_| if rng.below( 4 ) == 0 { 0 } else { 1 + ( rng.next() as u32 >> 12 >> rng.below( 20 ) ) } ).collect();
+      let used = freq.iter().filter( |f| **f > 0 ).count();
+      if used < 2 { continue; }
+      let mut previous = u64::MAX;
+      for limit in ( 64 - ( used as u64 - 1 ).leading_zeros() as usize )..=huffman::MAX_BITS
+      {
+        // Relaxing the limit never makes the code worse.
+        let c = cost( &freq, &check_huffman( &freq, limit, &mut rng ) );
+        assert!( c <= previous );
+        previous = c;
+
+        // Same cost as the DEFLATE coder.
+        if limit == 15 && nsym <= 288
+        {
+          let mut bc = BitCoder::new( 15, nsym );
+          bc.used.copy_from_slice( &freq );
+          bc.compute_bits();
+          assert_eq!( c, bc.total() as u64 );
+        }
+      }
+    }
+  }
+
+  // Fibonacci frequencies make the longest possible codes, which are decoded a bit at a time.
+  let mut freq = vec![ 1, 1 ];
+  while freq.len() < 40 { freq.push( freq[ freq.len() - 1 ] + freq[ freq.len() - 2 ] ); }
+  let lengths = check_huffman( &freq, huffman::MAX_BITS, &mut rng );
+  assert_eq!( *lengths.iter().max().unwrap() as usize, huffman::MAX_BITS );
+  let lengths = check_huffman( &freq[ ..30 ], huffman::MAX_BITS, &mut rng );
+  assert_eq!( lengths[ 0 ], 29 );
+}
+
+#[test]
+fn huffman_invalid()
+{
+  assert_eq!( huffman::code_lengths( &[ 1, 1 ], 0 ), Err( HuffmanError::InvalidLimit ) );
+  assert_eq!( huffman::code_lengths( &[ 1, 1 ], huffman::MAX_BITS + 1 ), Err( HuffmanError::InvalidLimit ) );
+  assert_eq!( huffman::code_lengths( &[ 1, 1, 1 ], 1 ), Err( HuffmanError::InvalidLimit ) );
+  assert!( Encoder::new( vec![ 1, 1, 1 ] ).is_err() ); // Over-subscribed.
+  assert!( Decoder::new( &[ 1, 2, 33 ] ).is_err() ); // Too long.
+
+  // Incomplete code, the unused code is invalid.
+  let dec = Decoder::new( &[ 1, 2 ] ).unwrap();
+  assert_eq!( dec.decode( &mut InputBitStream::new( &[ 0b01 ][..] ) ), Ok( 1 ) );
+  assert_eq!( dec.decode( &mut InputBitStream::new( &[ 0b11 ][..] ) ), Err( HuffmanError::InvalidCode ) );
+
+  // Truncated input.
+  let enc = Encoder::from_frequencies( &[ 5, 1, 1, 1, 1, 1, 1, 1, 1 ], 15 ).unwrap();
+  let mut bs = BitStream::new( 10 );
+  enc.write_lengths( &mut bs );
+  bs.flush();
+  let dec = Decoder::read( &mut InputBitStream::new( &bs.bytes[..] ), 9 ).unwrap();
+  let mut input = InputBitStream::new( &bs.bytes[ ..0 ] );
+  assert_eq!( dec.decode( &mut input ), Err( HuffmanError::Truncated ) );
+  assert!( Decoder::read( &mut InputBitStream::new( &bs.bytes[ ..1 ] ), 9 ).is_err() );
+  assert!( Decoder::read( &mut InputBitStream::new( &bs.bytes[..] ), 8 ).is_err() );
+}
+
+/// Check the statistics recorded when compressing data against a trace of the output.
+fn check_stats( data: &[u8], c: &mut Config )
+{
+  c.container = Container::Raw;
+  c.stats = Some( CompressStats::new() );
+  let cb = compress::compress( data, c );
+  let stats = c.stats.take().unwrap();
+  let mut opts = InflateOptions::new();
+  opts.container = Container::Raw;
+  let ( items, error ) = inflate::trace( &cb, &opts );
+  assert!( error.is_none() );
+
+  // Blocks cover the input in order, and their sizes add up to the output size.
+  let mut position = 0;
+  for b in &stats.blocks
+  {
+    assert_eq!( b.input_start, position );
+    position = b.input_end;
+  }
+  assert_eq!( position, data.len() );
+  let bits : usize = stats.blocks.iter().map( |b| b.bits ).sum();
+  assert_eq!( bits.div_ceil( 8 ), cb.len() );
+
+  // The trace has the same blocks and symbols, and replaying it gives the input.
+  let mut out = Vec::new();
+  let mut blocks = stats.blocks.iter();
+  let ( mut literals, mut matches ) = ( 0, 0 );
+  for ( i, item ) in items.iter().enumerate()
+  {
+    let block_type = match item
+    {
+      TraceItem::Stored{ len, .. } => { out.extend_from_slice( &data[ out.len()..out.len() + len ] ); Some( BlockType::Stored ) }
+      TraceItem::Fixed{ .. } => Some( BlockType::Fixed ),
+      TraceItem::Dynamic{ lit_bits, dist_bits, len_bits, .. } =>
+      {
+        let b = stats.blocks.iter().find( |b| b.input_start == out.len() && b.block_type == BlockType::Dynamic ).unwrap();
+        assert_eq!( ( b.lit_symbols, b.dist_symbols, b.len_symbols ), ( lit_bits.len(), dist_bits.len(), len_bits.len() ) );
+        Some( BlockType::Dynamic )
+      }
+      TraceItem::Literal( x ) => { out.push( *x ); literals += 1; None }
+      TraceItem::Match{ length, distance } =>
+      {
+        for _ in 0..*length { out.push( out[ out.len() - distance ] ); }
+        matches += 1;
+        None
+      }
+      TraceItem::EndOfBlock => None,
+    };
+    if let Some( t ) = block_type
+    {
+      let b = blocks.next().unwrap();
+      assert_eq!( b.block_type, t );
+      let last = matches!( item, TraceItem::Stored{ last: true, .. } | TraceItem::Fixed{ last: true, .. } | TraceItem::Dynamic{ last: true, .. } );
+      assert_eq!( last, i + 1 == items.len() || items[ i + 1.. ].iter().all( |x| !matches!( x, 
+        TraceItem::Stored{ .. } | TraceItem::Fixed{ .. } | TraceItem::Dynamic{ .. } ) ) );
+    }
+  }
+  assert!( blocks.next().is_none() );
+  assert_eq!( out, data );
+  assert_eq!( ( literals, matches ), ( stats.literals(), stats.matches() ) );
+  if matches > 0 { assert!( stats.average_match() >= 3.0 && stats.average_match() <= 258.0 ); }
+}
+
+#[test]
+fn stats_and_trace()
+{
+  let mut rng = Rng::new( 23 );
+  for kind in 0..KINDS
+  {
+    let data = structured( &mut rng, kind, 100000 );
+    for level in [ 0, 1, 6, 9 ]
+    {
+      check_stats( &data, &mut Config::with_level( Level( level ) ) );
+    }
+  }
+  let data = structured( &mut rng, KINDS - 1, 300000 );
+  let mut c = Config::new();
+  c.threads = 3;
+  check_stats( &data, &mut c );
+  check_stats( &[], &mut Config::new() );
+
+  // A fixed code block, written out.
+  let mut opts = InflateOptions::new();
+  opts.container = Container::Raw;
+  let mut c = Config::with_level( Level( 6 ) );
+  c.container = Container::Raw;
+  let cb = compress::compress( b"abcabcabc!", &mut c );
+  let ( items, error ) = inflate::trace( &cb, &opts );
+  assert!( error.is_none() );
+  let text : Vec<String> = items.iter().map( |i| i.to_string() ).collect();
+  assert_eq!( text, [ "fixed block at bit 0 ( last )", "literal 'a'", "literal 'b'", "literal 'c'", 
+    "match length 6 distance 3", "literal '!'", "end of block" ] );
+
+  // The items before an error are returned.
+  let ( items, error ) = inflate::trace( &cb[ ..2 ], &opts );
+  assert_eq!( items[ 0 ], TraceItem::Fixed{ position: 0, last: true } );
+  assert!( matches!( error, Some( InflateError::Truncated ) ) );
+}
+
+#[test]
+fn round_trip_match_finders()
+{
+  let mut rng = Rng::new( 24 );
+  let dictionary = structured( &mut rng, 2, 50000 );
+  for match_finder in matcher::FINDERS
+  {
+    for ( lazy_match, optimal_passes ) in [ ( false, 0 ), ( true, 0 ), ( true, 2 ) ]
+    {
+      for ( ii, data ) in inputs().iter().enumerate()
+      {
+        let mut c = Config::with_level( Level( 7 ) );
+        c.options = Options{ match_finder, lazy_match, optimal_passes, ..c.options.clone() };
+        let what = format!( "{:?} lazy {} optimal {} input {}", match_finder, lazy_match, optimal_passes, ii );
+        check_round_trip( data, &mut c, &mut rng, &what );
+        check_flushes( data, &c, &mut rng, &what );
+        c.dictionary = dictionary.clone();
+        check_round_trip( data, &mut c, &mut rng, &( what.clone() + " with dictionary" ) );
+        check_flushes( data, &c, &mut rng, &( what + " with dictionary" ) );
+      }
+    }
+    // Level 9 with many flushes in longer input.
+    let mut c = Config::with_level( Level( 9 ) );
+    c.options.match_finder = match_finder;
+    for kind in 0..KINDS
+    {
+      let data = structured( &mut rng, kind, 100000 );
+      check_flushes( &data, &c, &mut rng, &format!( "{:?} level 9 kind {}", match_finder, kind ) );
+    }
+  }
+}
+
+/// With no limit on the positions searched, each finder finds the longest match ( at least as long as the bytes hashed ).
+#[test]
+fn match_finders_longest()
+{
+  let mut rng = Rng::new( 25 );
+  for kind in [ 2, 3, 5, 6 ]
+  {
+    let data = structured( &mut rng, kind, 3000 );
+    let len = data.len();
+    for ( match_finder, exact ) in [ ( Finder::HashChain, 3 ), ( Finder::Hash4, 4 ), ( Finder::Hash5, 5 ), ( Finder::Hash34, 4 ), 
+      ( Finder::BinaryTree, 4 ) ]
+    {
+      let mut opts = Level( 9 ).options();
+      opts.probe_max = usize::MAX;
+      opts.match_finder = match_finder;
+      let mut m = matcher::new_finder( len, &opts );
+      let mut found = Vec::new();
+      for position in 0..len - 4
+      {
+        found.clear();
+        m.matches( &data, position, &mut found );
+        let avail = std::cmp::min( len - position, MAX_MATCH );
+        let mut previous = 0;
+        for &( length, distance ) in &found
+        {
+          let ( length, distance ) = ( length as usize, distance as usize );
+          assert!( length > previous && length <= avail && distance >= 1 && distance <= position );
+          assert_eq!( data[ position - distance..position - distance + length ], data[ position..position + length ] );
+          previous = length;
+        }
+        let longest = ( 1..=position ).map( |d| ( 0..avail ).take_while( |&i| data[ position - d + i ] == data[ position + i ] ).count() )
+          .max().unwrap_or( 0 );
+        if longest >= exact && position + 2 * MAX_MATCH < len
+        { 
+          assert_eq!( previous, longest, "{:?} kind {} position {}", match_finder, kind, position ); 
+        }
+      }
+    }
+  }
+}
+
+/// A reused CompressorContext gives the same output ( and statistics ) as compress, as the input size and options vary.
+#[test]
+fn context_matches_compress()
+{
+  let mut rng = Rng::new( 26 );
+  let dictionary = structured( &mut rng, 2, 5000 );
+  let mut ctx = compress::CompressorContext::new();
+  let mut data = inputs();
+  for _ in 0..100
+  {
+    let ( kind, len ) = ( rng.below( KINDS ), [ 10, 100, 1000, 20000 ][ rng.below( 4 ) ] );
+    data.push( structured( &mut rng, kind, len ) );
+  }
+  for ( ii, d ) in data.iter().enumerate()
+  {
+    let mut c = Config::new();
+    c.options = random_options( &mut rng );
+    c.container = [ Container::Raw, Container::Zlib, Container::Gzip ][ rng.below( 3 ) ];
+    if rng.below( 4 ) == 0 { c.dictionary = dictionary.clone(); }
+    if rng.below( 2 ) == 0 { c.stats = Some( CompressStats::new() ); }
+    let expect = compress::compress( d, &mut c );
+    let expect_stats = c.stats.as_ref().map( |s| s.to_string() );
+    // Repeat with the same options, so the match finder is sometimes reused.
+    for _ in 0..1 + rng.below( 2 )
+    {
+      assert!( ctx.compress( d, &mut c ) == &expect[..], "input {} options {:?}", ii, c.options.match_finder );
+      assert_eq!( c.stats.as_ref().map( |s| s.to_string() ), expect_stats, "input {} stats", ii );
+    }
+  }
+}
+
+#[test]
+fn zip_round_trip()
+{
+  use crate::zip::{Archive,ZipWriter,ZipError,dos_time};
+  let mut rng = Rng::new( 26 );
+  let text = structured( &mut rng, 2, 50000 );
+  let noise = structured( &mut rng, 0, 1000 );
+  assert_eq!( dos_time( 0 ), 0x00210000 ); // Before 1980.
+  assert_eq!( dos_time( 1700000000 ), 0x576e_b1aa ); // 2023-11-14 22:13:20.
+  for zip64 in [ false, true ]
+  {
+    let mut z = ZipWriter::new( Vec::new(), Level::DEFAULT );
+    z.time = dos_time( 1700000000 );
+    z.zip64 = zip64;
+    z.add_dir( "docs" ).unwrap();
+    z.add_file( "docs/text.txt", &text ).unwrap();
+    z.add_file( "noise.bin", &noise ).unwrap(); // Incompressible, so stored.
+    z.add_stored( "docs/stored.txt", &text[ ..1000 ] ).unwrap();
+    z.add_file( "empty", b"" ).unwrap();
+    z.add_file( "caf\u{e9}.txt", b"caf\xc3\xa9" ).unwrap();
+    let zb = z.finish().unwrap();
+    assert_eq!( zb.windows( 4 ).any( |w| w == [ 0x50, 0x4b, 6, 6 ] ), zip64 ); // ZIP64 end record.
+
+    let a = Archive::new( &zb ).unwrap();
+    let names : Vec<&[u8]> = a.entries().iter().map( |e| &e.name[..] ).collect();
+    assert_eq!( names, [ &b"docs/"[..], b"docs/text.txt", b"noise.bin", b"docs/stored.txt", b"empty", "caf\u{e9}.txt".as_bytes() ] );
+    let dirs : Vec<bool> = a.entries().iter().map( |e| e.is_dir() ).collect();
+    assert_eq!( dirs, [ true, false, false, false, false, false ] );
+    let methods : Vec<u16> = a.entries().iter().map( |e| e.method ).collect();
+    assert_eq!( methods, [ 0, 8, 0, 0, 0, 0 ] ); // Short data is stored.
+    assert!( a.entries().iter().all( |e| e.time == 0x576e_b1aa ) );
+    assert_eq!( a.entries()[ 0 ].external_attr, 0x10 );
+    assert_eq!( a.entries()[ 5 ].flags, 0x800 ); // UTF-8 name.
+    assert!( a.comment().is_empty() );
+    assert!( a.find( b"docs" ).is_none() );
+
+    let e = a.find( b"docs/text.txt" ).unwrap();
+    assert_eq!( ( e.size, e.crc ), ( text.len() as u64, crate::checksum::crc32( &text ) ) );
+    assert!( e.compressed_size < e.size / 2 );
+    assert_eq!( a.read( e ).unwrap(), text );
+    assert_eq!( inflate::try_inflate_with( a.raw( e ).unwrap(), &inflate_options( &z_config() ) ).unwrap(), text );
+    assert_eq!( a.read( a.find( b"noise.bin" ).unwrap() ).unwrap(), noise );
+    assert_eq!( a.read( a.find( b"docs/stored.txt" ).unwrap() ).unwrap(), &text[ ..1000 ] );
+    assert!( a.read( a.find( b"empty" ).unwrap() ).unwrap().is_empty() );
+    assert!( a.read( &a.entries()[ 0 ] ).unwrap().is_empty() );
+
+    // Output limit applies to deflated entries.
+    let mut opts = InflateOptions::new();
+    opts.max_output = 1000;
+    assert!( matches!( a.read_with( e, &opts ), Err( ZipError::Inflate( InflateError::LimitExceeded( _ ) ) ) ) );
+
+    // Corrupt data in a stored entry, and a deflated entry.
+    let e = a.find( b"docs/stored.txt" ).unwrap().clone();
+    let mut bad = zb.clone();
+    bad[ e.offset as usize + 30 + e.name.len() + if zip64 { 20 } else { 0 } + 500 ] ^= 1;
+    let b = Archive::new( &bad ).unwrap();
+    assert!( matches!( b.read( b.find( b"docs/stored.txt" ).unwrap() ), Err( ZipError::ChecksumMismatch ) ) );
+    let e = a.find( b"docs/text.txt" ).unwrap().clone();
+    let mut bad = zb.clone();
+    let at = e.offset as usize + 30 + e.name.len() + if zip64 { 20 } else { 0 } + e.compressed_size as usize / 2;
+    bad[ at ] ^= 0x10;
+    let b = Archive::new( &bad ).unwrap();
+    assert!( matches!( b.read( b.find( b"docs/text.txt" ).unwrap() ), Err( ZipError::ChecksumMismatch | ZipError::SizeMismatch | ZipError::Inflate( _ ) ) ) );
+
+    // Wrong name in local header.
+    let mut bad = zb.clone();
+    bad[ e.offset as usize + 30 ] = b'D';
+    assert!( matches!( Archive::new( &bad ).unwrap().read( &e ), Err( ZipError::InvalidHeader ) ) );
+
+    // Truncated archives.
+    assert!( matches!( Archive::new( &zb[ ..zb.len() - 1 ] ), Err( ZipError::NotZip ) ) );
+    assert!( matches!( Archive::new( &zb[ e.offset as usize + 100.. ] ), Err( ZipError::Truncated | ZipError::InvalidHeader ) ) );
+  }
+  assert!( matches!( Archive::new( b"PK\x05\x06" ), Err( ZipError::NotZip ) ) );
+}
+
+/// Config for raw deflate, as used for zip entries.
+fn z_config() -> Config
+{
+  let mut c = Config::new();
+  c.container = Container::Raw;
+  c
+}
+
+/// Append the low n bytes of x, little-endian.
+fn le( z: &mut Vec<u8>, x: u64, n: usize )
+{
+  z.extend_from_slice( &x.to_le_bytes()[ ..n ] );
+}
+
+/// Handcrafted archive with one deflated entry written the way streaming tools do : the crc and sizes are 0 in the 
+/// local header and follow the data in a data descriptor ( with an optional signature ). If zip64 is set, the descriptor 
+/// has 8 byte sizes, both headers have ZIP64 extra fields, and the ZIP64 end record and locator are written.
+fn zip_fixture( data: &[u8], zip64: bool, signature: bool ) -> Vec<u8>
+{
+  let cb = compress::compress( data, &mut z_config() );
+  let crc = crate::checksum::crc32( data ) as u64;
+  let ( csize, size ) = ( cb.len() as u64, data.len() as u64 );
+  let unknown = if zip64 { 0xffffffff } else { 0 };
+  let version = if zip64 { 45 } else { 20 };
+  let name = b"stream.txt";
+  let mut z = Vec::new();
+
+  // Local header.
+  le( &mut z, 0x04034b50, 4 );
+  le( &mut z, version, 2 );
+  le( &mut z, 8, 2 ); // Flags : data descriptor.
+  le( &mut z, 8, 2 ); // Deflated.
+  le( &mut z, 0x00210000, 4 );
+  le( &mut z, 0, 4 );
+  le( &mut z, unknown, 4 );
+  le( &mut z, unknown, 4 );
+  le( &mut z, name.len() as u64, 2 );
+  le( &mut z, if zip64 { 20 } else { 0 }, 2 );
+  z.extend_from_slice( name );
+  if zip64
+  {
+    le( &mut z, 1, 2 );
+    le( &mut z, 16, 2 );
+    le( &mut z, 0, 8 );
+    le( &mut z, 0, 8 );
+  }
+  z.extend_from_slice( &cb );
+
+  // Data descriptor.
+  if signature { le( &mut z, 0x08074b50, 4 ); }
+  le( &mut z, crc, 4 );
+  le( &mut z, csize, if zip64 { 8 } else { 4 } );
+  le( &mut z, size, if zip64 { 8 } else { 4 } );
+
+  // Central directory, with size, compressed size and offset in the ZIP64 extra field.
+  let start = z.len() as u64;
+  le( &mut z, 0x02014b50, 4 );
+  le( &mut z, version, 2 );
+  le( &mut z, version, 2 );
+  le( &mut z, 8, 2 );
+  le( &mut z, 8, 2 );
+  le( &mut z, 0x00210000, 4 );
+  le( &mut z, crc, 4 );
+  le( &mut z, if zip64 { 0xffffffff } else { csize }, 4 );
+  le( &mut z, if zip64 { 0xffffffff } else { size }, 4 );
+  le( &mut z, name.len() as u64, 2 );
+  le( &mut z, if zip64 { 28 } else { 0 }, 2 );
+  le( &mut z, 0, 2 ); // Comment length.
+  le( &mut z, 0, 4 ); // Disk number, internal attributes.
+  le( &mut z, 0, 4 ); // External attributes.
+  le( &mut z, if zip64 { 0xffffffff } else { 0 }, 4 );
+  z.extend_from_slice( name );
+  if zip64
+  {
+    le( &mut z, 1, 2 );
+    le( &mut z, 24, 2 );
+    le( &mut z, size, 8 );
+    le( &mut z, csize, 8 );
+    le( &mut z, 0, 8 );
+  }
+  let cd_size = z.len() as u64 - start;
+
+  // ZIP64 end of central directory record and locator.
+  if zip64
+  {
+    let end64 = z.len() as u64;
+    le( &mut z, 0x06064b50, 4 );
+    le( &mut z, 44, 8 );
+    le( &mut z, 45, 2 );
+    le( &mut z, 45, 2 );
+    le( &mut z, 0, 8 ); // Disk numbers.
+    le( &mut z, 1, 8 );
+    le( &mut z, 1, 8 );
+    le( &mut z, cd_size, 8 );
+    le( &mut z, start, 8 );
+    le( &mut z, 0x07064b50, 4 );
+    le( &mut z, 0, 4 );
+    le( &mut z, end64, 8 );
+    le( &mut z, 1, 4 );
+  }
+
+  // End of central directory record, with a comment.
+  let comment = b"fixture";
+  le( &mut z, 0x06054b50, 4 );
+  le( &mut z, 0, 4 ); // Disk numbers.
+  le( &mut z, if zip64 { 0xffff } else { 1 }, 2 );
+  le( &mut z, if zip64 { 0xffff } else { 1 }, 2 );
+  le( &mut z, if zip64 { 0xffffffff } else { cd_size }, 4 );
+  le( &mut z, if zip64 { 0xffffffff } else { start }, 4 );
+  le( &mut z, comment.len() as u64, 2 );
+  z.extend_from_slice( comment );
+  z
+}
+
+#[test]
+fn zip_fixtures()
+{
+  use crate::zip::{Archive,ZipError};
+  let mut rng = Rng::new( 27 );
+  let data = structured( &mut rng, 3, 20000 );
+  for zip64 in [ false, true ]
+  {
+    for signature in [ false, true ]
+    {
+      let zb = zip_fixture( &data, zip64, signature );
+      let a = Archive::new( &zb ).unwrap();
+      assert_eq!( a.comment(), b"fixture" );
+      assert_eq!( a.entries().len(), 1 );
+      let e = &a.entries()[ 0 ];
+      assert_eq!( ( &e.name[..], e.flags, e.size, e.offset ), ( &b"stream.txt"[..], 8, data.len() as u64, 0 ) );
+      assert_eq!( a.read( e ).unwrap(), data );
+
+      // The descriptor is checked against the central directory.
+      let mut bad = zb.clone();
+      let at = 30 + 10 + if zip64 { 20 } else { 0 } + e.compressed_size as usize + if signature { 4 } else { 0 };
+      bad[ at ] ^= 1; // Low byte of crc.
+      assert!( matches!( Archive::new( &bad ).unwrap().read( e ), Err( ZipError::InvalidHeader ) ) );
+
+      // Matching crcs which are wrong for the data.
+      let mut e = e.clone();
+      e.crc ^= 1;
+      assert!( matches!( Archive::new( &bad ).unwrap().read( &e ), Err( ZipError::ChecksumMismatch ) ) );
+    }
+  }
+  // ZIP64 locator pointing at something else.
+  let mut zb = zip_fixture( &data, true, true );
+  let at = zb.len() - 22 - 7 - 12;
+  zb[ at ] ^= 1;
+  assert!( matches!( Archive::new( &zb ), Err( ZipError::InvalidHeader | ZipError::Truncated ) ) );
+}
+
+/// Parse a command line ( without the program name ).
+fn parse_args( line: &str ) -> Result<crate::Args, String>
+{
+  crate::Args::parse( line.split_whitespace().map( |s| s.to_string() ) )
+}
+
+#[test]
+fn command_line()
+{
+  use crate::Format;
+  let a = parse_args( "" ).unwrap();
+  assert!( !a.decompress && !a.stdout && !a.keep && !a.test && !a.force && a.format.is_none() );
+  assert_eq!( ( a.level, a.threads, &a.files[..] ), ( Level::DEFAULT, 1, &[ "-".to_string() ][..] ) );
+
+  // Short options may be combined, and values follow the letter or are the next argument.
+  let a = parse_args( "-dck9 a.gz -p 4 -S.x b" ).unwrap();
+  assert!( a.decompress && a.stdout && a.keep && !a.test );
+  assert_eq!( ( a.level, a.threads, a.suffix.as_deref(), &a.files[..] ), ( Level( 9 ), 4, Some( ".x" ), &[ "a.gz".to_string(), "b".to_string() ][..] ) );
+  let a = parse_args( "-p2 -S .y -tvfz" ).unwrap();
+  assert!( a.test && a.verbose && a.force && a.format == Some( Format::Deflate( Container::Zlib ) ) );
+  assert_eq!( ( a.threads, a.suffix.as_deref() ), ( 2, Some( ".y" ) ) );
+  assert!( parse_args( "-K" ).unwrap().format == Some( Format::Zip ) );
+  assert!( parse_args( "-h" ).unwrap().help );
+
+  // Long options, with values after '=' or as the next argument.
+  let a = parse_args( "--stdout --decompress --keep --force --verbose --raw --suffix=.r --threads 3 --optimal=2 f" ).unwrap();
+  assert!( a.stdout && a.decompress && a.keep && a.force && a.verbose && a.format == Some( Format::Deflate( Container::Raw ) ) );
+  assert_eq!( ( a.suffix.as_deref(), a.threads, a.optimal_passes ), ( Some( ".r" ), 3, 2 ) );
+  let a = parse_args( "--trace --zip --gzip --best" ).unwrap();
+  assert!( a.trace && a.test && a.format == Some( Format::Deflate( Container::Gzip ) ) && a.level == Level::BEST );
+  assert_eq!( parse_args( "--store" ).unwrap().level, Level::NONE );
+  assert_eq!( parse_args( "--fast" ).unwrap().level, Level::FASTEST );
+  assert!( parse_args( "--uncompress --to-stdout" ).map( |a| a.decompress && a.stdout ).unwrap() );
+  let a = parse_args( "--bench --json --repeat=5" ).unwrap();
+  assert!( a.bench && a.json && a.files.is_empty() );
+  assert_eq!( a.repeat, Some( 5 ) );
+
+  // After "--" everything is a file, "-" is standard input.
+  let a = parse_args( "-1 -- -2 -" ).unwrap();
+  assert_eq!( ( a.level, &a.files[..] ), ( Level( 1 ), &[ "-2".to_string(), "-".to_string() ][..] ) );
+
+  // The config follows the options.
+  let c = parse_args( "-7 -p 3 --optimal=1" ).unwrap().config( Container::Zlib );
+  assert_eq!( ( c.threads, c.options.optimal_passes, c.container ), ( 3, 1, Container::Zlib ) );
+
+  // Errors.
+  for ( line, e ) in 
+  [
+    ( "-x", "invalid option -- 'x'" ),
+    ( "--bogus", "unrecognized option '--bogus'" ),
+    ( "-p", "option -p requires a number" ),
+    ( "-p x", "option -p requires a number" ),
+    ( "--threads=", "option --threads requires a number" ),
+    ( "--repeat", "option --repeat requires a number" ),
+    ( "--optimal -1", "option --optimal requires a number" ),
+    ( "-S", "option -S requires an argument" ),
+    ( "--suffix", "option --suffix requires an argument" ),
+    ( "--suffix=", "suffix must not be empty" ),
+  ]
+  {
+    assert_eq!( parse_args( line ).err().as_deref(), Some( e ), "{}", line );
+  }
+}
+
+#[test]
+fn command_line_streams()
+{
+  use crate::{Format,compress_stream,decompress_stream};
+  let mut rng = Rng::new( 28 );
+  let data = structured( &mut rng, 2, 100000 );
+  for ( line, detected ) in 
+  [
+    ( "", Some( Format::Deflate( Container::Gzip ) ) ),
+    ( "-1 -p 3", Some( Format::Deflate( Container::Gzip ) ) ), // Parallel.
+    ( "-z --stats", Some( Format::Deflate( Container::Zlib ) ) ),
+    ( "--raw -0", None ),
+    ( "-K", Some( Format::Zip ) ),
+  ]
+  {
+    let args = parse_args( line ).unwrap();
+    let mut cb = Vec::new();
+    compress_stream( &mut &data[..], &mut cb, "dir/name.txt", None, &args ).unwrap();
+    assert!( Format::detect( &cb ) == detected, "{}", line );
+
+    // Decompress detecting the format ( raw deflate must be given ).
+    let mut out = Vec::new();
+    let d = parse_args( if detected.is_some() { "-d" } else { "-d --raw" } ).unwrap();
+    decompress_stream( &mut &cb[..], &mut out, &d ).unwrap();
+    assert!( out == data, "{}", line );
+
+    // Gzip output is a standard gzip file, zip output has one entry named after the file.
+    match detected
+    {
+      Some( Format::Deflate( Container::Gzip ) ) => 
+      {
+        let mut dec = flate2::read::MultiGzDecoder::new( &cb[..] );
+        let mut out = Vec::new();
+        dec.read_to_end( &mut out ).unwrap();
+        assert!( out == data );
+      }
+      Some( Format::Zip ) => 
+      {
+        let a = crate::zip::Archive::new( &cb ).unwrap();
+        assert_eq!( a.entries().len(), 1 );
+        assert_eq!( a.entries()[ 0 ].name, b"name.txt" );
+      }
+      _ => {}
+    }
+
+    // Truncated or corrupt input is an error.
+    let mut out = Vec::new();
+    assert!( decompress_stream( &mut &cb[ ..cb.len() - 5 ], &mut out, &d ).is_err(), "{}", line );
+  }
+
+  // Trailing data after gzip members is ignored.
+  let args = parse_args( "" ).unwrap();
+  let mut cb = Vec::new();
+  compress_stream( &mut &data[..], &mut cb, "-", None, &args ).unwrap();
+  cb.extend_from_slice( b"\0\0\0\0" );
+  let mut out = Vec::new();
+  decompress_stream( &mut &cb[..], &mut out, &parse_args( "-d" ).unwrap() ).unwrap();
+  assert!( out == data );
+
+  // Input which is not compressed, and zip archives with no files or with more than one file ( unless testing ).
+  let mut out = Vec::new();
+  let d = parse_args( "-d" ).unwrap();
+  assert!( decompress_stream( &mut &data[..], &mut out, &d ).is_err() );
+  let mut z = crate::zip::ZipWriter::new( Vec::new(), Level::DEFAULT );
+  z.add_dir( "a" ).unwrap();
+  let zb = z.finish().unwrap();
+  assert!( decompress_stream( &mut &zb[..], &mut out, &d ).is_err() );
+  let mut z = crate::zip::ZipWriter::new( Vec::new(), Level::DEFAULT );
+  z.add_file( "a", b"one" ).unwrap();
+  z.add_file( "b", b"two" ).unwrap();
+  let zb = z.finish().unwrap();
+  assert!( decompress_stream( &mut &zb[..], &mut out, &d ).is_err() );
+  decompress_stream( &mut &zb[..], &mut std::io::sink(), &parse_args( "-t" ).unwrap() ).unwrap();
+
+  // Format detection.
+  assert!( Format::detect( &[ 0x78, 0x9c ] ) == Some( Format::Deflate( Container::Zlib ) ) );
+  assert!( Format::detect( &[ 0x78, 0x9d ] ).is_none() ); // Header check fails.
+  assert!( Format::detect( b"PK\x05\x06" ) == Some( Format::Zip ) ); // Empty archive.
+  assert!( Format::detect( &[ 0x1f ] ).is_none() );
+}
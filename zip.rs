@@ -0,0 +1,544 @@
+//! ZIP archives ( PKWARE APPNOTE.TXT ). Entries may be stored ( method 0 ) or deflated ( method 8 ), archives
+//! larger than 4GB ( or with more than 65535 entries ) use the ZIP64 extensions.
+
+use std::io::Write;
+use std::convert::TryFrom;
+use crate::compress::{self,Config,Level};
+use crate::container::Container;
+use crate::inflate::{self,InflateOptions,InflateError};
+use crate::checksum::crc32;
+
+/// Compression method : data is not compressed.
+pub const STORED : u16 = 0;
+/// Compression method : RFC 1951 compressed data.
+pub const DEFLATED : u16 = 8;
+
+const LOCAL_SIG : u32 = 0x04034b50;
+const CENTRAL_SIG : u32 = 0x02014b50;
+const DESCRIPTOR_SIG : u32 = 0x08074b50;
+const END_SIG : u32 = 0x06054b50;
+const END64_SIG : u32 = 0x06064b50;
+const LOCATOR64_SIG : u32 = 0x07064b50;
+
+const ZIP64_EXTRA : u16 = 1; // Header id of ZIP64 extended information extra field.
+const FLAG_ENCRYPTED : u16 = 1;
+const FLAG_DESCRIPTOR : u16 = 8; // Crc and sizes follow the compressed data ( in a data descriptor ).
+const FLAG_UTF8 : u16 = 0x800; // Name and comment are UTF-8.
+
+/// 1980-01-01 00:00:00, the earliest MS-DOS date and time.
+pub const DOS_EPOCH : u32 = 0x00210000;
+
+/// Convert a time in seconds since 1970 ( UTC ) to MS-DOS date and time ( times before 1980 give DOS_EPOCH ).
+pub fn dos_time( secs: u64 ) -> u32
+{
+  // Civil date from day number, see http://howardhinnant.github.io/date_algorithms.html
+  let z = secs / 86400 + 719468;
+  let ( era, doe ) = ( z / 146097, z % 146097 );
+  let yoe = ( doe - doe / 1460 + doe / 36524 - doe / 146096 ) / 365;
+  let doy = doe - ( 365 * yoe + yoe / 4 - yoe / 100 );
+  let mp = ( 5 * doy + 2 ) / 153;
+  let day = doy - ( 153 * mp + 2 ) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = era * 400 + yoe + if month <= 2 { 1 } else { 0 };
+  if year < 1980 { return DOS_EPOCH; }
+  let year = std::cmp::min( year - 1980, 127 );
+  let t = secs % 86400;
+  let date = year << 9 | month << 5 | day;
+  let time = ( ( t / 3600 ) << 11 ) | ( ( t / 60 % 60 ) << 5 ) | ( t % 60 / 2 );
+  ( date << 16 | time ) as u32
+}
+
+/// Archive entry, as recorded in the central directory.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Entry
+{
+  pub name: Vec<u8>, // File name, with '/' separators. Directory names end with '/'.
+  pub comment: Vec<u8>,
+  pub method: u16, // STORED or DEFLATED ( other methods cannot be read ).
+  pub flags: u16, // General purpose bit flags.
+  pub time: u32, // Modification time, MS-DOS date ( high 16 bits ) and time ( low 16 bits ).
+  pub crc: u32, // CRC-32 of the uncompressed data.
+  pub compressed_size: u64,
+  pub size: u64, // Uncompressed size.
+  pub offset: u64, // Offset of the local header in the archive.
+  pub external_attr: u32, // Host file attributes.
+}
+
+impl Entry
+{
+  /// Entry is a directory.
+  pub fn is_dir( &self ) -> bool
+  {
+    self.name.last() == Some( &b'/' )
+  }
+}
+
+/// Reasons reading an archive can fail.
+#[derive(Debug)]
+pub enum ZipError
+{
+  NotZip, // End of central directory record not found.
+  Truncated, // A header or entry data extends past the end of the archive.
+  InvalidHeader, // A header signature or field is not valid.
+  Encrypted, // Entry is encrypted.
+  UnsupportedMethod( u16 ), // Entry uses a compression method other than stored or deflated.
+  SizeMismatch, // Size of the uncompressed data does not match the header.
+  ChecksumMismatch, // CRC-32 of the uncompressed data does not match the header.
+  Inflate( InflateError ), // Error decompressing entry data.
+}
+
+impl std::fmt::Display for ZipError
+{
+  fn fmt( &self, f: &mut std::fmt::Formatter ) -> std::fmt::Result
+  {
+    f.write_str( match self
+    {
+      ZipError::NotZip => "Not a zip archive",
+      ZipError::Truncated => "Unexpected end of archive",
+      ZipError::InvalidHeader => "Invalid header",
+      ZipError::Encrypted => "Encrypted entries are not supported",
+      ZipError::UnsupportedMethod( m ) => return write!( f, "Unsupported compression method {}", m ),
+      ZipError::SizeMismatch => "Bad entry size",
+      ZipError::ChecksumMismatch => "Bad checksum",
+      ZipError::Inflate( e ) => return e.fmt( f ),
+    } )
+  }
+}
+
+impl std::error::Error for ZipError {}
+
+impl From<InflateError> for ZipError
+{
+  fn from( e: InflateError ) -> ZipError
+  {
+    ZipError::Inflate( e )
+  }
+}
+
+/// Archive held in memory. The entries are read from the central directory when the archive is opened.
+///
+/// Example:
+/// let data = std::fs::read( "test.zip" )?;
+/// let z = zip::Archive::new( &data )?;
+/// for e in z.entries() { println!( "{} {}", e.size, String::from_utf8_lossy( &e.name ) ); }
+/// let contents : Vec<u8> = z.read( z.find( b"hello.txt" ).unwrap() )?;
+pub struct Archive<'a>
+{
+  data: &'a [u8],
+  entries: Vec<Entry>,
+  comment: Vec<u8>, // Archive comment.
+}
+
+impl <'a> Archive<'a>
+{
+  /// Open archive, reading the central directory.
+  pub fn new( data: &'a [u8] ) -> Result<Archive<'a>, ZipError>
+  {
+    let end = find_end( data )?;
+    let mut r = Reader::new( data, end + 10 );
+    let mut count = r.u16()? as u64;
+    let mut size = r.u32()? as u64;
+    let mut offset = r.u32()? as u64;
+    let n = r.u16()? as usize;
+    let comment = r.bytes( n )?.to_vec();
+
+    // ZIP64 end of central directory locator immediately precedes the end record.
+    if end >= 20 && get32( data, end - 20 ) == LOCATOR64_SIG
+    {
+      let mut r = Reader::new( data, end - 12 );
+      let mut r = Reader::new( data, to_usize( r.u64()? )? );
+      if r.u32()? != END64_SIG { return Err( ZipError::InvalidHeader ); }
+      r.skip( 28 )?;
+      count = r.u64()?;
+      size = r.u64()?;
+      offset = r.u64()?;
+    }
+
+    let start = to_usize( offset )?;
+    let end = start.checked_add( to_usize( size )? ).ok_or( ZipError::Truncated )?;
+    if end > data.len() { return Err( ZipError::Truncated ); }
+    let mut r = Reader::new( &data[ ..end ], start );
+    let mut entries = Vec::new();
+    for _ in 0..count { entries.push( read_central( &mut r )? ); }
+    Ok( Archive{ data, entries, comment } )
+  }
+
+  /// The entries in the order they appear in the central directory.
+  pub fn entries( &self ) -> &[Entry]
+  {
+    &self.entries
+  }
+
+  /// The archive comment.
+  pub fn comment( &self ) -> &[u8]
+  {
+    &self.comment
+  }
+
+  /// Find entry by name.
+  pub fn find( &self, name: &[u8] ) -> Option<&Entry>
+  {
+    self.entries.iter().find( |e| e.name == name )
+  }
+
+  /// Read and decompress the data for an entry.
+  pub fn read( &self, e: &Entry ) -> Result<Vec<u8>, ZipError>
+  {
+    self.read_with( e, &InflateOptions::new() )
+  }
+
+  /// Read and decompress the data for an entry. The limits in opts are applied to deflated data
+  /// ( the container and dictionary are ignored ).
+  pub fn read_with( &self, e: &Entry, opts: &InflateOptions ) -> Result<Vec<u8>, ZipError>
+  {
+    if e.flags & FLAG_ENCRYPTED != 0 { return Err( ZipError::Encrypted ); }
+    let data = self.raw( e )?;
+    let result = match e.method
+    {
+      STORED => data.to_vec(),
+      DEFLATED =>
+      {
+        let mut opts = opts.clone();
+        opts.container = Container::Raw;
+        opts.dictionary = Vec::new();
+        // Output larger than the recorded size is an error, so there is no need to decompress it.
+        opts.max_output = std::cmp::min( opts.max_output, usize::try_from( e.size ).unwrap_or( usize::MAX ) );
+        match inflate::try_inflate_with( data, &opts )
+        {
+          Err( InflateError::LimitExceeded( inflate::Limit::Output ) ) if opts.max_output as u64 == e.size =>
+            return Err( ZipError::SizeMismatch ),
+          r => r?
+        }
+      }
+      m => return Err( ZipError::UnsupportedMethod( m ) )
+    };
+    if result.len() as u64 != e.size { return Err( ZipError::SizeMismatch ); }
+    if crc32( &result ) != e.crc { return Err( ZipError::ChecksumMismatch ); }
+    Ok( result )
+  }
+
+  /// Get the ( compressed ) data for an entry. The local header is checked, and if the crc and sizes
+  /// follow the data in a data descriptor, the descriptor is checked against the central directory.
+  pub fn raw( &self, e: &Entry ) -> Result<&'a [u8], ZipError>
+  {
+    let mut r = Reader::new( self.data, to_usize( e.offset )? );
+    if r.u32()? != LOCAL_SIG { return Err( ZipError::InvalidHeader ); }
+    r.skip( 2 )?;
+    let flags = r.u16()?;
+    let method = r.u16()?;
+    r.skip( 16 )?;
+    let n = r.u16()? as usize;
+    let x = r.u16()? as usize;
+    if method != e.method || r.bytes( n )? != &e.name[..] { return Err( ZipError::InvalidHeader ); }
+    let extra = r.bytes( x )?;
+    let data = r.bytes( to_usize( e.compressed_size )? )?;
+
+    if flags & FLAG_DESCRIPTOR != 0
+    {
+      // The signature is optional. Sizes are 8 bytes if the local header has a ZIP64 extra field.
+      let mut crc = r.u32()?;
+      if crc == DESCRIPTOR_SIG && e.crc != DESCRIPTOR_SIG { crc = r.u32()?; }
+      let zip64 = extra_field( extra, ZIP64_EXTRA )?.is_some();
+      let ( csize, size ) = if zip64 { ( r.u64()?, r.u64()? ) } else { ( r.u32()? as u64, r.u32()? as u64 ) };
+      if crc != e.crc || csize != e.compressed_size || size != e.size { return Err( ZipError::InvalidHeader ); }
+    }
+    Ok( data )
+  }
+}
+
+/// Find the offset of the end of central directory record ( which is followed by a comment of up to 64K ).
+fn find_end( data: &[u8] ) -> Result<usize, ZipError>
+{
+  if data.len() < 22 { return Err( ZipError::NotZip ); }
+  let last = data.len() - 22;
+  for i in ( last.saturating_sub( 0xffff )..=last ).rev()
+  {
+    if get32( data, i ) == END_SIG && i + 22 + get16( data, i + 20 ) as usize == data.len() { return Ok( i ); }
+  }
+  Err( ZipError::NotZip )
+}
+
+/// Read a central directory file header.
+fn read_central( r: &mut Reader ) -> Result<Entry, ZipError>
+{
+  if r.u32()? != CENTRAL_SIG { return Err( ZipError::InvalidHeader ); }
+  r.skip( 4 )?;
+  let flags = r.u16()?;
+  let method = r.u16()?;
+  let time = r.u32()?;
+  let crc = r.u32()?;
+  let mut compressed_size = r.u32()? as u64;
+  let mut size = r.u32()? as u64;
+  let n = r.u16()? as usize;
+  let x = r.u16()? as usize;
+  let c = r.u16()? as usize;
+  r.skip( 4 )?;
+  let external_attr = r.u32()?;
+  let mut offset = r.u32()? as u64;
+  let name = r.bytes( n )?.to_vec();
+  let extra = r.bytes( x )?;
+  let comment = r.bytes( c )?.to_vec();
+
+  // ZIP64 extra field has 8 byte values for those fields which are 0xffffffff, in a fixed order.
+  if let Some( x ) = extra_field( extra, ZIP64_EXTRA )?
+  {
+    let mut f = Reader::new( x, 0 );
+    if size == 0xffffffff { size = f.u64()?; }
+    if compressed_size == 0xffffffff { compressed_size = f.u64()?; }
+    if offset == 0xffffffff { offset = f.u64()?; }
+  }
+  Ok( Entry{ name, comment, method, flags, time, crc, compressed_size, size, offset, external_attr } )
+}
+
+/// Find the data for an extra field given the header id.
+fn extra_field( extra: &[u8], id: u16 ) -> Result<Option<&[u8]>, ZipError>
+{
+  let mut r = Reader::new( extra, 0 );
+  while r.pos + 4 <= extra.len()
+  {
+    let x = r.u16()?;
+    let n = r.u16()? as usize;
+    let data = r.bytes( n )?;
+    if x == id { return Ok( Some( data ) ); }
+  }
+  Ok( None )
+}
+
+fn to_usize( x: u64 ) -> Result<usize, ZipError>
+{
+  usize::try_from( x ).map_err( |_| ZipError::Truncated )
+}
+
+fn get16( data: &[u8], i: usize ) -> u16
+{
+  u16::from_le_bytes( [ data[ i ], data[ i + 1 ] ] )
+}
+
+fn get32( data: &[u8], i: usize ) -> u32
+{
+  u32::from_le_bytes( [ data[ i ], data[ i + 1 ], data[ i + 2 ], data[ i + 3 ] ] )
+}
+
+/// Reads little-endian values from a slice.
+struct Reader<'a>
+{
+  data: &'a [u8],
+  pos: usize,
+}
+
+impl <'a> Reader<'a>
+{
+  fn new( data: &'a [u8], pos: usize ) -> Reader<'a>
+  {
+    Reader{ data, pos }
+  }
+
+  fn bytes( &mut self, n: usize ) -> Result<&'a [u8], ZipError>
+  {
+    if n > self.data.len().saturating_sub( self.pos ) { return Err( ZipError::Truncated ); }
+    self.pos += n;
+    Ok( &self.data[ self.pos - n..self.pos ] )
+  }
+
+  fn skip( &mut self, n: usize ) -> Result<(), ZipError>
+  {
+    self.bytes( n ).map( |_| () )
+  }
+
+  fn u16( &mut self ) -> Result<u16, ZipError>
+  {
+    self.bytes( 2 ).map( |b| get16( b, 0 ) )
+  }
+
+  fn u32( &mut self ) -> Result<u32, ZipError>
+  {
+    self.bytes( 4 ).map( |b| get32( b, 0 ) )
+  }
+
+  fn u64( &mut self ) -> Result<u64, ZipError>
+  {
+    Ok( self.u32()? as u64 | ( self.u32()? as u64 ) << 32 )
+  }
+}
+
+/// Writes a zip archive to out. Each entry is written as it is added, the central directory is written by finish.
+///
+/// Example:
+/// let mut z = zip::ZipWriter::new( std::fs::File::create( "test.zip" )?, compress::Level::DEFAULT );
+/// z.add_dir( "docs/" )?;
+/// z.add_file( "docs/hello.txt", b"Hello world" )?;
+/// z.finish()?;
+pub struct ZipWriter<W: Write>
+{
+  out: W,
+  pub config: Config, // Used to compress entries ( the container must be Raw ).
+  pub time: u32, // MS-DOS date and time recorded for entries added.
+  pub zip64: bool, // Use the ZIP64 extensions for every entry and the end record, even if the sizes and offsets fit in 32 bits.
+  entries: Vec<Entry>,
+  offset: u64, // Number of bytes written.
+}
+
+impl<W: Write> ZipWriter<W>
+{
+  pub fn new( out: W, level: Level ) -> ZipWriter<W>
+  {
+    let mut config = Config::with_level( level );
+    config.container = Container::Raw;
+    ZipWriter{ out, config, time: DOS_EPOCH, zip64: false, entries: Vec::new(), offset: 0 }
+  }
+
+  /// Add a file, compressed unless this does not reduce the size ( or the level is 0 ).
+  pub fn add_file( &mut self, name: &str, data: &[u8] ) -> std::io::Result<()>
+  {
+    if self.config.options.store_only { return self.add_stored( name, data ); }
+    let cb = compress::compress( data, &mut self.config );
+    if cb.len() >= data.len() { return self.add_stored( name, data ); }
+    self.add( name, DEFLATED, crc32( data ), data.len() as u64, &cb )
+  }
+
+  /// Add a file without compression.
+  pub fn add_stored( &mut self, name: &str, data: &[u8] ) -> std::io::Result<()>
+  {
+    self.add( name, STORED, crc32( data ), data.len() as u64, data )
+  }
+
+  /// Add a directory, a '/' is appended to name if it does not end with one.
+  pub fn add_dir( &mut self, name: &str ) -> std::io::Result<()>
+  {
+    let name = if name.ends_with( '/' ) { name.to_string() } else { name.to_string() + "/" };
+    self.add( &name, STORED, 0, 0, &[] )?;
+    self.entries.last_mut().unwrap().external_attr = 0x10; // MS-DOS directory attribute.
+    Ok(())
+  }
+
+  /// Write local header and data.
+  fn add( &mut self, name: &str, method: u16, crc: u32, size: u64, data: &[u8] ) -> std::io::Result<()>
+  {
+    let e = Entry
+    {
+      name: name.as_bytes().to_vec(),
+      comment: Vec::new(),
+      method,
+      flags: if name.is_ascii() { 0 } else { FLAG_UTF8 },
+      time: self.time,
+      crc,
+      compressed_size: data.len() as u64,
+      size,
+      offset: self.offset,
+      external_attr: 0,
+    };
+    let zip64 = self.zip64 || e.size >= 0xffffffff || e.compressed_size >= 0xffffffff;
+    let mut h = Vec::new();
+    put32( &mut h, LOCAL_SIG );
+    put16( &mut h, if zip64 { 45 } else { 20 } );
+    put16( &mut h, e.flags );
+    put16( &mut h, e.method );
+    put32( &mut h, e.time );
+    put32( &mut h, e.crc );
+    put32( &mut h, if zip64 { 0xffffffff } else { e.compressed_size as u32 } );
+    put32( &mut h, if zip64 { 0xffffffff } else { e.size as u32 } );
+    put16( &mut h, e.name.len() as u16 );
+    put16( &mut h, if zip64 { 20 } else { 0 } );
+    h.extend_from_slice( &e.name );
+    if zip64
+    {
+      put16( &mut h, ZIP64_EXTRA );
+      put16( &mut h, 16 );
+      put64( &mut h, e.size );
+      put64( &mut h, e.compressed_size );
+    }
+    self.out.write_all( &h )?;
+    self.out.write_all( data )?;
+    self.offset += ( h.len() + data.len() ) as u64;
+    self.entries.push( e );
+    Ok(())
+  }
+
+  /// Write the central directory and return the output.
+  pub fn finish( mut self ) -> std::io::Result<W>
+  {
+    let start = self.offset;
+    let force = self.zip64;
+    let mut h = Vec::new();
+    for e in &self.entries
+    {
+      // Values which do not fit in 32 bits are stored in the ZIP64 extra field.
+      let mut x = Vec::new();
+      let put = | x: &mut Vec<u8>, v: u64 | if v >= 0xffffffff || force { put64( x, v ); 0xffffffff } else { v as u32 };
+      let size = put( &mut x, e.size );
+      let csize = put( &mut x, e.compressed_size );
+      let offset = put( &mut x, e.offset );
+      put32( &mut h, CENTRAL_SIG );
+      put16( &mut h, if x.is_empty() { 20 } else { 45 } ); // Version made by ( MS-DOS ).
+      put16( &mut h, if x.is_empty() { 20 } else { 45 } );
+      put16( &mut h, e.flags );
+      put16( &mut h, e.method );
+      put32( &mut h, e.time );
+      put32( &mut h, e.crc );
+      put32( &mut h, csize );
+      put32( &mut h, size );
+      put16( &mut h, e.name.len() as u16 );
+      put16( &mut h, if x.is_empty() { 0 } else { 4 + x.len() as u16 } );
+      put16( &mut h, e.comment.len() as u16 );
+      put16( &mut h, 0 ); // Disk number.
+      put16( &mut h, 0 ); // Internal attributes.
+      put32( &mut h, e.external_attr );
+      put32( &mut h, offset );
+      h.extend_from_slice( &e.name );
+      if !x.is_empty()
+      {
+        put16( &mut h, ZIP64_EXTRA );
+        put16( &mut h, x.len() as u16 );
+        h.extend_from_slice( &x );
+      }
+      h.extend_from_slice( &e.comment );
+    }
+    let size = h.len() as u64;
+    let count = self.entries.len() as u64;
+    let end64 = start + size;
+    let zip64 = force || count >= 0xffff || size >= 0xffffffff || start >= 0xffffffff;
+    if zip64
+    {
+      put32( &mut h, END64_SIG );
+      put64( &mut h, 44 ); // Size of remaining record.
+      put16( &mut h, 45 );
+      put16( &mut h, 45 );
+      put32( &mut h, 0 );
+      put32( &mut h, 0 );
+      put64( &mut h, count );
+      put64( &mut h, count );
+      put64( &mut h, size );
+      put64( &mut h, start );
+
+      put32( &mut h, LOCATOR64_SIG );
+      put32( &mut h, 0 );
+      put64( &mut h, end64 );
+      put32( &mut h, 1 );
+    }
+    put32( &mut h, END_SIG );
+    put16( &mut h, 0 );
+    put16( &mut h, 0 );
+    put16( &mut h, std::cmp::min( count, 0xffff ) as u16 );
+    put16( &mut h, std::cmp::min( count, 0xffff ) as u16 );
+    put32( &mut h, std::cmp::min( size, 0xffffffff ) as u32 );
+    put32( &mut h, std::cmp::min( start, 0xffffffff ) as u32 );
+    put16( &mut h, 0 ); // Comment length.
+    self.out.write_all( &h )?;
+    Ok( self.out )
+  }
+}
+
+fn put16( v: &mut Vec<u8>, x: u16 )
+{
+  v.extend_from_slice( &x.to_le_bytes() );
+}
+
+fn put32( v: &mut Vec<u8>, x: u32 )
+{
+  v.extend_from_slice( &x.to_le_bytes() );
+}
+
+fn put64( v: &mut Vec<u8>, x: u64 )
+{
+  v.extend_from_slice( &x.to_le_bytes() );
+}